@@ -0,0 +1,96 @@
+#![allow(clippy::needless_return)]
+
+// compares the interned string table against the HashMap<String, Value> the vm used before it
+// the map borrows its keys from names built up front, so neither side allocates inside the timed loops
+// std::time only, so there's nothing to pull in, run with `cargo bench`
+
+use std::{
+    collections::HashMap,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use rustlox::{
+    memory::Heap,
+    table::{hash_string, Table},
+    values::{ObjRef, ObjString, ObjectType, Value},
+};
+
+const KEYS: usize = 1000;
+const ROUNDS: usize = 200;
+
+fn time<F: FnMut()>(name: &str, mut run: F) -> Duration {
+    // one untimed pass so both sides start warm
+    run();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        run();
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<28} {:>10.2?} per round ({} keys)",
+        name,
+        elapsed / ROUNDS as u32,
+        KEYS
+    );
+    return elapsed;
+}
+
+fn main() {
+    let names: Vec<String> = (0..KEYS).map(|i| format!("variable{}", i)).collect();
+
+    // the table side gets the strings the way the vm hands them out, interned with their hash
+    let mut heap = Heap::init();
+    let keys: Vec<(ObjRef, u32)> = names
+        .iter()
+        .map(|name| {
+            let hash = hash_string(name);
+            let string = ObjString::allocate(name.clone(), hash);
+            (heap.alloc(ObjectType::String(string)), hash)
+        })
+        .collect();
+
+    let table_set = time("Table set", || {
+        let mut table = Table::init();
+        for (i, (key, hash)) in keys.iter().enumerate() {
+            table.set(*key, *hash, Value::from_number(i as f64));
+        }
+        black_box(&table);
+    });
+
+    let hash_map_set = time("HashMap<&str, Value> set", || {
+        let mut map = HashMap::new();
+        for (i, name) in names.iter().enumerate() {
+            map.insert(name.as_str(), Value::from_number(i as f64));
+        }
+        black_box(&map);
+    });
+
+    let mut table = Table::init();
+    let mut map = HashMap::new();
+    for (i, ((key, hash), name)) in keys.iter().zip(&names).enumerate() {
+        table.set(*key, *hash, Value::from_number(i as f64));
+        map.insert(name.as_str(), Value::from_number(i as f64));
+    }
+
+    let table_get = time("Table get", || {
+        for (key, hash) in &keys {
+            black_box(table.get(*key, *hash));
+        }
+    });
+
+    let hash_map_get = time("HashMap<&str, Value> get", || {
+        for name in &names {
+            black_box(map.get(name.as_str()));
+        }
+    });
+
+    println!();
+    println!(
+        "set speedup {:.2}x, get speedup {:.2}x",
+        hash_map_set.as_secs_f64() / table_set.as_secs_f64(),
+        hash_map_get.as_secs_f64() / table_get.as_secs_f64()
+    );
+}
@@ -0,0 +1,493 @@
+use std::fmt::Write;
+
+use crate::{number::format_float, scanner::Span, values::UpvalueDescriptor};
+
+// the tree the multi pass pipeline works on
+// the parser builds it, the resolver fills in the fields marked as its own, lower.rs turns it into bytecode
+
+// an identifier as it was written
+#[derive(Debug, Clone, PartialEq)]
+pub struct Name {
+    pub lexeme: String,
+    pub span: Span,
+}
+
+// where a variable lives at runtime, everything starts out unresolved until the resolver has run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    Unresolved,
+    Global,
+    Local(usize),
+    Upvalue(usize),
+}
+
+// both a reference to a variable and a declaration of one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub name: Name,
+    pub resolution: Resolution,
+}
+
+impl Variable {
+    pub fn init(name: Name) -> Self {
+        Variable {
+            name,
+            resolution: Resolution::Unresolved,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FunctionType {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    FloorDivide,
+    Modulo,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Int(i64),
+    String(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    // what a runtime error in this expression points at, the operator for operations and the name for variables
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Literal(Literal),
+    Grouping(Box<Expr>),
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Logical {
+        op: LogicalOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Variable(Variable),
+    Assign {
+        target: Variable,
+        value: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        arguments: Vec<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Name,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Name,
+        value: Box<Expr>,
+    },
+    This(Variable),
+    // the receiver and the superclass are hidden locals, resolved like any other variable
+    Super {
+        this: Variable,
+        superclass: Variable,
+        method: Name,
+    },
+    // the string literals around each expression, there's always one more segment than expressions
+    Interpolation {
+        segments: Vec<Expr>,
+        expressions: Vec<Expr>,
+    },
+    // list and map literals and subscripts all point at their opening bracket or brace
+    List(Vec<Expr>),
+    Map(Vec<(Expr, Expr)>),
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: Name,
+    pub function_type: FunctionType,
+    pub params: Vec<Variable>,
+    pub body: Vec<Stmt>,
+    // the closing brace, the implicit return points at it
+    pub end: Span,
+    // filled in by the resolver
+    pub upvalues: Vec<UpvalueDescriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Class {
+    pub name: Variable,
+    pub superclass: Option<Variable>,
+    pub methods: Vec<Function>,
+    // whether a method closed over the hidden `super` local, filled in by the resolver
+    pub super_captured: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    // the statement's last token, the pops and jumps it needs point at it
+    pub span: Span,
+}
+
+// `captured` holds one flag per local the scope declared, in declaration order, filled in by the resolver
+// captured locals are closed over when the scope ends instead of popped
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Expression(Expr),
+    Print(Expr),
+    Var {
+        name: Variable,
+        initializer: Option<Expr>,
+    },
+    Block {
+        statements: Vec<Stmt>,
+        captured: Vec<bool>,
+    },
+    // the `_end` spans are the tokens that close each clause, the single pass compiler emits the jumps and pops
+    // that follow a clause there, so lowering points at them too
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+        condition_end: Span,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+        condition_end: Span,
+    },
+    For {
+        initializer: Option<Box<Stmt>>,
+        condition: Option<Expr>,
+        increment: Option<Box<Expr>>,
+        body: Box<Stmt>,
+        captured: Vec<bool>,
+        // the second `;`, the last token of the increment and the `)`
+        condition_end: Span,
+        increment_end: Span,
+        clauses_end: Span,
+    },
+    Return {
+        keyword: Name,
+        value: Option<Expr>,
+    },
+    Fun {
+        name: Variable,
+        function: Function,
+    },
+    Class(Class),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub statements: Vec<Stmt>,
+    // the end of the source, where the script's implicit return points
+    pub end: Span,
+}
+
+// renders the tree one node per line, children indented under their parent
+// resolved variables are tagged with where they live, so tooling can see what the resolver decided
+pub fn dump(program: &Program) -> String {
+    let mut out = String::new();
+    for statement in &program.statements {
+        dump_stmt(&mut out, statement, 0);
+    }
+    return out;
+}
+
+fn line(out: &mut String, depth: usize, text: &str) {
+    _ = writeln!(out, "{}{}", "  ".repeat(depth), text);
+}
+
+fn describe(variable: &Variable) -> String {
+    let location = match variable.resolution {
+        Resolution::Unresolved => return variable.name.lexeme.clone(),
+        Resolution::Global => "global".to_string(),
+        Resolution::Local(slot) => format!("local {}", slot),
+        Resolution::Upvalue(index) => format!("upvalue {}", index),
+    };
+    return format!("{} ({})", variable.name.lexeme, location);
+}
+
+fn dump_function(out: &mut String, function: &Function, depth: usize) {
+    let params: Vec<String> = function.params.iter().map(describe).collect();
+    line(
+        out,
+        depth,
+        &format!(
+            "{:?} {}({})",
+            function.function_type,
+            function.name.lexeme,
+            params.join(", ")
+        ),
+    );
+
+    for upvalue in &function.upvalues {
+        let from = if upvalue.is_local { "local" } else { "upvalue" };
+        line(
+            out,
+            depth + 1,
+            &format!("Captures {} {}", from, upvalue.index),
+        );
+    }
+    for statement in &function.body {
+        dump_stmt(out, statement, depth + 1);
+    }
+}
+
+fn dump_stmt(out: &mut String, statement: &Stmt, depth: usize) {
+    match &statement.kind {
+        StmtKind::Expression(expression) => {
+            line(out, depth, "Expression");
+            dump_expr(out, expression, depth + 1);
+        }
+        StmtKind::Print(expression) => {
+            line(out, depth, "Print");
+            dump_expr(out, expression, depth + 1);
+        }
+        StmtKind::Var { name, initializer } => {
+            line(out, depth, &format!("Var {}", describe(name)));
+            if let Some(initializer) = initializer {
+                dump_expr(out, initializer, depth + 1);
+            }
+        }
+        StmtKind::Block { statements, .. } => {
+            line(out, depth, "Block");
+            for statement in statements {
+                dump_stmt(out, statement, depth + 1);
+            }
+        }
+        StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            line(out, depth, "If");
+            dump_expr(out, condition, depth + 1);
+            dump_stmt(out, then_branch, depth + 1);
+            if let Some(else_branch) = else_branch {
+                line(out, depth, "Else");
+                dump_stmt(out, else_branch, depth + 1);
+            }
+        }
+        StmtKind::While {
+            condition, body, ..
+        } => {
+            line(out, depth, "While");
+            dump_expr(out, condition, depth + 1);
+            dump_stmt(out, body, depth + 1);
+        }
+        StmtKind::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => {
+            line(out, depth, "For");
+            if let Some(initializer) = initializer {
+                dump_stmt(out, initializer, depth + 1);
+            }
+            if let Some(condition) = condition {
+                line(out, depth + 1, "Condition");
+                dump_expr(out, condition, depth + 2);
+            }
+            if let Some(increment) = increment {
+                line(out, depth + 1, "Increment");
+                dump_expr(out, increment, depth + 2);
+            }
+            dump_stmt(out, body, depth + 1);
+        }
+        StmtKind::Return { value, .. } => {
+            line(out, depth, "Return");
+            if let Some(value) = value {
+                dump_expr(out, value, depth + 1);
+            }
+        }
+        StmtKind::Fun { name, function } => {
+            line(out, depth, &format!("Fun {}", describe(name)));
+            dump_function(out, function, depth + 1);
+        }
+        StmtKind::Class(class) => {
+            line(out, depth, &format!("Class {}", describe(&class.name)));
+            if let Some(superclass) = &class.superclass {
+                line(
+                    out,
+                    depth + 1,
+                    &format!("Inherits {}", describe(superclass)),
+                );
+            }
+            for method in &class.methods {
+                dump_function(out, method, depth + 1);
+            }
+        }
+    }
+}
+
+fn dump_expr(out: &mut String, expression: &Expr, depth: usize) {
+    match &expression.kind {
+        ExprKind::Literal(literal) => {
+            let text = match literal {
+                Literal::Nil => "nil".to_string(),
+                Literal::Bool(value) => value.to_string(),
+                Literal::Number(value) => format_float(*value),
+                Literal::Int(value) => value.to_string(),
+                Literal::String(value) => format!("{:?}", value),
+            };
+            line(out, depth, &format!("Literal {}", text));
+        }
+        ExprKind::Grouping(inner) => {
+            line(out, depth, "Grouping");
+            dump_expr(out, inner, depth + 1);
+        }
+        ExprKind::Unary { op, operand } => {
+            line(out, depth, &format!("Unary {:?}", op));
+            dump_expr(out, operand, depth + 1);
+        }
+        ExprKind::Binary { op, left, right } => {
+            line(out, depth, &format!("Binary {:?}", op));
+            dump_expr(out, left, depth + 1);
+            dump_expr(out, right, depth + 1);
+        }
+        ExprKind::Logical { op, left, right } => {
+            line(out, depth, &format!("Logical {:?}", op));
+            dump_expr(out, left, depth + 1);
+            dump_expr(out, right, depth + 1);
+        }
+        ExprKind::Variable(variable) => {
+            line(out, depth, &format!("Variable {}", describe(variable)))
+        }
+        ExprKind::Assign { target, value } => {
+            line(out, depth, &format!("Assign {}", describe(target)));
+            dump_expr(out, value, depth + 1);
+        }
+        ExprKind::Call { callee, arguments } => {
+            line(out, depth, "Call");
+            dump_expr(out, callee, depth + 1);
+            for argument in arguments {
+                dump_expr(out, argument, depth + 1);
+            }
+        }
+        ExprKind::Get { object, name } => {
+            line(out, depth, &format!("Get {}", name.lexeme));
+            dump_expr(out, object, depth + 1);
+        }
+        ExprKind::Set {
+            object,
+            name,
+            value,
+        } => {
+            line(out, depth, &format!("Set {}", name.lexeme));
+            dump_expr(out, object, depth + 1);
+            dump_expr(out, value, depth + 1);
+        }
+        ExprKind::This(variable) => line(out, depth, &format!("This {}", describe(variable))),
+        ExprKind::Super {
+            this,
+            superclass,
+            method,
+        } => {
+            line(
+                out,
+                depth,
+                &format!(
+                    "Super {} on {} of {}",
+                    method.lexeme,
+                    describe(superclass),
+                    describe(this)
+                ),
+            );
+        }
+        ExprKind::Interpolation {
+            segments,
+            expressions,
+        } => {
+            line(out, depth, "Interpolation");
+            for (index, segment) in segments.iter().enumerate() {
+                dump_expr(out, segment, depth + 1);
+                if let Some(expression) = expressions.get(index) {
+                    dump_expr(out, expression, depth + 1);
+                }
+            }
+        }
+        ExprKind::List(items) => {
+            line(out, depth, "List");
+            for item in items {
+                dump_expr(out, item, depth + 1);
+            }
+        }
+        ExprKind::Map(entries) => {
+            line(out, depth, "Map");
+            for (key, value) in entries {
+                dump_expr(out, key, depth + 1);
+                dump_expr(out, value, depth + 1);
+            }
+        }
+        ExprKind::Index { object, index } => {
+            line(out, depth, "Index");
+            dump_expr(out, object, depth + 1);
+            dump_expr(out, index, depth + 1);
+        }
+        ExprKind::SetIndex {
+            object,
+            index,
+            value,
+        } => {
+            line(out, depth, "SetIndex");
+            dump_expr(out, object, depth + 1);
+            dump_expr(out, index, depth + 1);
+            dump_expr(out, value, depth + 1);
+        }
+    }
+}
@@ -1,44 +1,356 @@
-use crate::values::{Value, ValueArray};
-
-pub enum OpCode {
-    OpReturn,
-    OpSubtract,
-    OpAdd,
-    OpMultiply,
-    OpDivide,
-    OpNegate,
-    OpConstant(usize),
-    OpNil,
-    OpNot,
-    OpTrue,
-    OpFalse,
-    OpEqual,
-    OpGreater,
-    OpLess,
-}
-
-pub struct Chunk {
-    pub lines: Vec<i32>,
-    pub code: Vec<OpCode>,
-    pub constants: ValueArray,
-}
-
-impl Chunk {
-    pub fn init() -> Self {
-        Chunk {
-            lines: Vec::new(),
-            code: Vec::new(),
-            constants: ValueArray::init(),
-        }
-    }
-
-    pub fn write(&mut self, byte: OpCode, line_num: i32) {
-        self.code.push(byte);
-        self.lines.push(line_num);
-    }
-
-    pub fn add_constant(&mut self, value: Value) -> usize {
-        self.constants.write(value);
-        return self.constants.values.len() - 1;
-    }
-}
+use crate::{
+    scanner::Span,
+    values::{Value, ValueArray},
+};
+
+// the decoded form of an instruction, operands included
+// chunks don't store these, they store an opcode byte followed by the operand bytes (little endian)
+// slots, upvalues and argument counts take one byte, name constants and jumps two,
+// OpConstant one and OpConstantLong three, list and map literal counts three as well
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    OpReturn,
+    OpSubtract,
+    OpAdd,
+    OpMultiply,
+    OpDivide,
+    OpNegate,
+    OpConstant(usize),
+    OpConstantLong(usize),
+    OpNil,
+    OpNot,
+    OpTrue,
+    OpFalse,
+    OpEqual,
+    OpGreater,
+    OpLess,
+    OpPrint,
+    OpPop,
+    OpDefineGlobal(usize),
+    OpGetGlobal(usize),
+    OpSetGlobal(usize),
+    OpGetLocal(usize),
+    OpSetLocal(usize),
+    OpJump(usize),
+    OpJumpIfFalse(usize),
+    OpLoop(usize),
+    OpCall(usize),
+    OpClosure(usize),
+    OpGetUpvalue(usize),
+    OpSetUpvalue(usize),
+    OpCloseUpvalue,
+    OpClass(usize),
+    OpGetProperty(usize),
+    OpSetProperty(usize),
+    OpMethod(usize),
+    // name constant, argument count
+    OpInvoke(usize, usize),
+    OpInherit,
+    OpGetSuper(usize),
+    // name constant, argument count
+    OpSuperInvoke(usize, usize),
+    // turns the value on top of the stack into the string `print` would show, for interpolation
+    OpStringify,
+    // item count, the items are on the stack in order
+    OpBuildList(usize),
+    // subscripts work on lists and maps alike
+    OpGetIndex,
+    OpSetIndex,
+    // entry count, each key is on the stack just before its value
+    OpBuildMap(usize),
+    // `//` and `%`, both round towards negative infinity
+    OpFloorDivide,
+    OpModulo,
+}
+
+// the largest value each operand width can hold, the compiler reports an error instead of going past these
+pub const BYTE_OPERAND_MAX: usize = u8::MAX as usize;
+pub const SHORT_OPERAND_MAX: usize = u16::MAX as usize;
+pub const LONG_OPERAND_MAX: usize = (1 << 24) - 1;
+
+// every operand, in the order they're encoded, paired with its width in bytes
+fn operands(instruction: &OpCode) -> ([(usize, usize); 2], usize) {
+    match *instruction {
+        OpCode::OpConstant(index) => ([(index, 1), (0, 0)], 1),
+        OpCode::OpConstantLong(index) | OpCode::OpBuildList(index) | OpCode::OpBuildMap(index) => {
+            ([(index, 3), (0, 0)], 1)
+        }
+        OpCode::OpGetLocal(slot)
+        | OpCode::OpSetLocal(slot)
+        | OpCode::OpGetUpvalue(slot)
+        | OpCode::OpSetUpvalue(slot)
+        | OpCode::OpCall(slot) => ([(slot, 1), (0, 0)], 1),
+        OpCode::OpDefineGlobal(index)
+        | OpCode::OpGetGlobal(index)
+        | OpCode::OpSetGlobal(index)
+        | OpCode::OpClosure(index)
+        | OpCode::OpClass(index)
+        | OpCode::OpGetProperty(index)
+        | OpCode::OpSetProperty(index)
+        | OpCode::OpMethod(index)
+        | OpCode::OpGetSuper(index)
+        | OpCode::OpJump(index)
+        | OpCode::OpJumpIfFalse(index)
+        | OpCode::OpLoop(index) => ([(index, 2), (0, 0)], 1),
+        OpCode::OpInvoke(index, arg_count) | OpCode::OpSuperInvoke(index, arg_count) => {
+            ([(index, 2), (arg_count, 1)], 2)
+        }
+        _ => ([(0, 0), (0, 0)], 0),
+    }
+}
+
+impl OpCode {
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            OpCode::OpReturn => 0,
+            OpCode::OpSubtract => 1,
+            OpCode::OpAdd => 2,
+            OpCode::OpMultiply => 3,
+            OpCode::OpDivide => 4,
+            OpCode::OpNegate => 5,
+            OpCode::OpConstant(_) => 6,
+            OpCode::OpConstantLong(_) => 7,
+            OpCode::OpNil => 8,
+            OpCode::OpNot => 9,
+            OpCode::OpTrue => 10,
+            OpCode::OpFalse => 11,
+            OpCode::OpEqual => 12,
+            OpCode::OpGreater => 13,
+            OpCode::OpLess => 14,
+            OpCode::OpPrint => 15,
+            OpCode::OpPop => 16,
+            OpCode::OpDefineGlobal(_) => 17,
+            OpCode::OpGetGlobal(_) => 18,
+            OpCode::OpSetGlobal(_) => 19,
+            OpCode::OpGetLocal(_) => 20,
+            OpCode::OpSetLocal(_) => 21,
+            OpCode::OpJump(_) => 22,
+            OpCode::OpJumpIfFalse(_) => 23,
+            OpCode::OpLoop(_) => 24,
+            OpCode::OpCall(_) => 25,
+            OpCode::OpClosure(_) => 26,
+            OpCode::OpGetUpvalue(_) => 27,
+            OpCode::OpSetUpvalue(_) => 28,
+            OpCode::OpCloseUpvalue => 29,
+            OpCode::OpClass(_) => 30,
+            OpCode::OpGetProperty(_) => 31,
+            OpCode::OpSetProperty(_) => 32,
+            OpCode::OpMethod(_) => 33,
+            OpCode::OpInvoke(_, _) => 34,
+            OpCode::OpInherit => 35,
+            OpCode::OpGetSuper(_) => 36,
+            OpCode::OpSuperInvoke(_, _) => 37,
+            OpCode::OpStringify => 38,
+            OpCode::OpBuildList(_) => 39,
+            OpCode::OpGetIndex => 40,
+            OpCode::OpSetIndex => 41,
+            OpCode::OpBuildMap(_) => 42,
+            OpCode::OpFloorDivide => 43,
+            OpCode::OpModulo => 44,
+        }
+    }
+
+    // the opcode with zeroed operands, decoding fills them in afterwards
+    fn from_byte(byte: u8) -> Option<OpCode> {
+        let instruction = match byte {
+            0 => OpCode::OpReturn,
+            1 => OpCode::OpSubtract,
+            2 => OpCode::OpAdd,
+            3 => OpCode::OpMultiply,
+            4 => OpCode::OpDivide,
+            5 => OpCode::OpNegate,
+            6 => OpCode::OpConstant(0),
+            7 => OpCode::OpConstantLong(0),
+            8 => OpCode::OpNil,
+            9 => OpCode::OpNot,
+            10 => OpCode::OpTrue,
+            11 => OpCode::OpFalse,
+            12 => OpCode::OpEqual,
+            13 => OpCode::OpGreater,
+            14 => OpCode::OpLess,
+            15 => OpCode::OpPrint,
+            16 => OpCode::OpPop,
+            17 => OpCode::OpDefineGlobal(0),
+            18 => OpCode::OpGetGlobal(0),
+            19 => OpCode::OpSetGlobal(0),
+            20 => OpCode::OpGetLocal(0),
+            21 => OpCode::OpSetLocal(0),
+            22 => OpCode::OpJump(0),
+            23 => OpCode::OpJumpIfFalse(0),
+            24 => OpCode::OpLoop(0),
+            25 => OpCode::OpCall(0),
+            26 => OpCode::OpClosure(0),
+            27 => OpCode::OpGetUpvalue(0),
+            28 => OpCode::OpSetUpvalue(0),
+            29 => OpCode::OpCloseUpvalue,
+            30 => OpCode::OpClass(0),
+            31 => OpCode::OpGetProperty(0),
+            32 => OpCode::OpSetProperty(0),
+            33 => OpCode::OpMethod(0),
+            34 => OpCode::OpInvoke(0, 0),
+            35 => OpCode::OpInherit,
+            36 => OpCode::OpGetSuper(0),
+            37 => OpCode::OpSuperInvoke(0, 0),
+            38 => OpCode::OpStringify,
+            39 => OpCode::OpBuildList(0),
+            40 => OpCode::OpGetIndex,
+            41 => OpCode::OpSetIndex,
+            42 => OpCode::OpBuildMap(0),
+            43 => OpCode::OpFloorDivide,
+            44 => OpCode::OpModulo,
+            _ => return None,
+        };
+        return Some(instruction);
+    }
+
+    fn with_operands(self, first: usize, second: usize) -> OpCode {
+        match self {
+            OpCode::OpConstant(_) => OpCode::OpConstant(first),
+            OpCode::OpConstantLong(_) => OpCode::OpConstantLong(first),
+            OpCode::OpDefineGlobal(_) => OpCode::OpDefineGlobal(first),
+            OpCode::OpGetGlobal(_) => OpCode::OpGetGlobal(first),
+            OpCode::OpSetGlobal(_) => OpCode::OpSetGlobal(first),
+            OpCode::OpGetLocal(_) => OpCode::OpGetLocal(first),
+            OpCode::OpSetLocal(_) => OpCode::OpSetLocal(first),
+            OpCode::OpJump(_) => OpCode::OpJump(first),
+            OpCode::OpJumpIfFalse(_) => OpCode::OpJumpIfFalse(first),
+            OpCode::OpLoop(_) => OpCode::OpLoop(first),
+            OpCode::OpCall(_) => OpCode::OpCall(first),
+            OpCode::OpClosure(_) => OpCode::OpClosure(first),
+            OpCode::OpGetUpvalue(_) => OpCode::OpGetUpvalue(first),
+            OpCode::OpSetUpvalue(_) => OpCode::OpSetUpvalue(first),
+            OpCode::OpClass(_) => OpCode::OpClass(first),
+            OpCode::OpGetProperty(_) => OpCode::OpGetProperty(first),
+            OpCode::OpSetProperty(_) => OpCode::OpSetProperty(first),
+            OpCode::OpMethod(_) => OpCode::OpMethod(first),
+            OpCode::OpInvoke(_, _) => OpCode::OpInvoke(first, second),
+            OpCode::OpGetSuper(_) => OpCode::OpGetSuper(first),
+            OpCode::OpSuperInvoke(_, _) => OpCode::OpSuperInvoke(first, second),
+            OpCode::OpBuildList(_) => OpCode::OpBuildList(first),
+            OpCode::OpBuildMap(_) => OpCode::OpBuildMap(first),
+            other => other,
+        }
+    }
+
+    // how many bytes the instruction takes in a chunk, opcode included
+    pub fn size(&self) -> usize {
+        let (operands, count) = operands(self);
+        return 1 + operands[..count]
+            .iter()
+            .map(|(_, width)| width)
+            .sum::<usize>();
+    }
+}
+
+// source spans are stored run length encoded, one entry per run of bytes that came from the same span
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpanRun {
+    pub span: Span,
+    pub count: usize,
+}
+
+#[derive(Debug)]
+pub struct Chunk {
+    pub spans: Vec<SpanRun>,
+    pub code: Vec<u8>,
+    pub constants: ValueArray,
+}
+
+impl Chunk {
+    pub fn init() -> Self {
+        Chunk {
+            spans: Vec::new(),
+            code: Vec::new(),
+            constants: ValueArray::init(),
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8, span: Span) {
+        self.code.push(byte);
+
+        match self.spans.last_mut() {
+            Some(run) if run.span == span => run.count += 1,
+            _ => self.spans.push(SpanRun { span, count: 1 }),
+        }
+    }
+
+    // encodes the instruction onto the end of the chunk, operands have to fit their width
+    pub fn write(&mut self, instruction: OpCode, span: Span) {
+        self.write_byte(instruction.to_byte(), span);
+
+        let (operands, count) = operands(&instruction);
+        for (value, width) in &operands[..count] {
+            for byte in 0..*width {
+                self.write_byte((value >> (8 * byte)) as u8, span);
+            }
+        }
+    }
+
+    // rewrites the operands of an instruction already in the chunk, used to patch jumps
+    pub fn patch(&mut self, offset: usize, instruction: OpCode) {
+        let mut position = offset + 1;
+        let (operands, count) = operands(&instruction);
+        for (value, width) in &operands[..count] {
+            for byte in 0..*width {
+                self.code[position] = (value >> (8 * byte)) as u8;
+                position += 1;
+            }
+        }
+    }
+
+    // drops every byte from offset on, the compiler uses it to replace code it just emitted
+    pub fn truncate(&mut self, offset: usize) {
+        let mut removed = self.code.len().saturating_sub(offset);
+        self.code.truncate(offset);
+
+        while removed > 0 {
+            let run = self.spans.last_mut().unwrap();
+            if run.count > removed {
+                run.count -= removed;
+                break;
+            }
+
+            removed -= run.count;
+            self.spans.pop();
+        }
+    }
+
+    // decodes the instruction starting at offset, None if the bytes there aren't a whole instruction
+    pub fn read(&self, offset: usize) -> Option<OpCode> {
+        let instruction = OpCode::from_byte(*self.code.get(offset)?)?;
+
+        let mut position = offset + 1;
+        let mut decoded = [0; 2];
+        let (operands, count) = operands(&instruction);
+        for (slot, (_, width)) in operands[..count].iter().enumerate() {
+            for byte in 0..*width {
+                decoded[slot] |= (*self.code.get(position)? as usize) << (8 * byte);
+                position += 1;
+            }
+        }
+
+        return Some(instruction.with_operands(decoded[0], decoded[1]));
+    }
+
+    // the source the byte at offset was compiled from
+    pub fn get_span(&self, offset: usize) -> Span {
+        let mut start = 0;
+        for run in &self.spans {
+            start += run.count;
+            if offset < start {
+                return run.span;
+            }
+        }
+
+        return self.spans.last().map_or(Span::default(), |run| run.span);
+    }
+
+    pub fn get_line(&self, offset: usize) -> i32 {
+        return self.get_span(offset).line;
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.write(value);
+        return self.constants.values.len() - 1;
+    }
+}
@@ -1,356 +1,1455 @@
-use std::{ops::Deref, rc::Rc};
-
-use crate::{
-    chunk::{Chunk, OpCode},
-    debug::disassemble_chunk,
-    scanner::{Scanner, Token, TokenType},
-    values::Value,
-    DEBUG_PRINT,
-};
-
-struct Parser {
-    current: Rc<Option<Token>>,
-    previous: Rc<Option<Token>>,
-    had_error: bool,
-    panic_mode: bool,
-}
-
-impl Parser {
-    fn error_at_current(&mut self, message: String) {
-        self.error_at(true, message);
-    }
-
-    fn error(&mut self, message: String) {
-        self.error_at(false, message);
-    }
-
-    fn error_at(&mut self, current: bool, message: String) {
-        if self.panic_mode {
-            return;
-        }
-
-        let token = if current {
-            self.current.deref().as_ref().unwrap()
-        } else {
-            self.previous.deref().as_ref().unwrap()
-        };
-
-        self.panic_mode = true;
-        eprint!("[line {}] Error", token.line);
-
-        if token.t_type == TokenType::Eof {
-            eprint!(" at end");
-        } else if token.t_type == TokenType::Error {
-            // nothing yet
-        } else {
-            eprint!(" at '{}'", token.content);
-        }
-
-        eprintln!(": {}", message);
-        self.had_error = true;
-    }
-}
-
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
-enum Precedence {
-    None = 0,
-    Assignment = 1, // =
-    Or = 2,         // or
-    And = 3,        // and
-    Equality = 4,   // == !=
-    Comparison = 5, // < > <= >=
-    Term = 6,       // + -
-    Factor = 7,     // * /
-    Unary = 8,      // ! -
-    Call = 9,       // . ()
-    Primary = 10,
-}
-
-impl Precedence {
-    fn next(&self) -> Precedence {
-        return match self {
-            Precedence::None => Self::Assignment,
-            Precedence::Assignment => Self::Or,
-            Precedence::Or => Self::And,
-            Precedence::And => Self::Equality,
-            Precedence::Equality => Self::Comparison,
-            Precedence::Comparison => Self::Term,
-            Precedence::Term => Self::Factor,
-            Precedence::Factor => Self::Unary,
-            Precedence::Unary => Self::Call,
-            Precedence::Call => Self::Primary,
-            Precedence::Primary => Self::None,
-        };
-    }
-}
-
-fn advance(scanner: &mut Scanner, parser: &mut Parser) {
-    parser.previous = Rc::new(parser.current.deref().clone());
-
-    loop {
-        let current = scanner.scan_token();
-
-        if current.t_type != TokenType::Error {
-            parser.current = Rc::new(Some(current));
-            break;
-        }
-
-        let current_content = current.content.clone();
-        parser.current = Rc::new(Some(current));
-        parser.error_at_current(current_content);
-    }
-}
-
-fn consume(t_type: TokenType, message: String, scanner: &mut Scanner, parser: &mut Parser) {
-    if parser.current.is_some() && parser.current.as_ref().as_ref().unwrap().t_type == t_type {
-        advance(scanner, parser);
-        return;
-    }
-
-    parser.error_at_current(message);
-}
-
-fn parse_precedence(
-    scanner: &mut Scanner,
-    parser: &mut Parser,
-    precedence: Precedence,
-    chunk: &mut Chunk,
-) {
-    advance(scanner, parser);
-    let prefix_rule = get_rule(&parser.previous.deref().as_ref().unwrap().t_type).prefix;
-    match prefix_rule {
-        Some(func) => func(parser, scanner, chunk),
-        None => parser.error("Expect expression".to_string()),
-    }
-
-    while precedence <= get_rule(&parser.current.deref().as_ref().unwrap().t_type).precedence {
-        advance(scanner, parser);
-        let infix_rule = get_rule(&parser.previous.deref().as_ref().unwrap().t_type).infix;
-        match infix_rule {
-            Some(func) => func(parser, scanner, chunk),
-            None => panic!("this shouldn't error"),
-        }
-    }
-}
-
-fn expression(scanner: &mut Scanner, parser: &mut Parser, chunk: &mut Chunk) {
-    parse_precedence(scanner, parser, Precedence::Assignment, chunk);
-}
-
-// emitting byte code
-fn emit_byte(parser: &Parser, chunk: &mut Chunk, byte: OpCode) {
-    let line = match parser.previous.deref().as_ref() {
-        Some(tok) => tok.line,
-        None => 0,
-    };
-
-    chunk.write(byte, line);
-}
-
-fn emit_bytes(parser: &Parser, chunk: &mut Chunk, byte1: OpCode, byte2: OpCode) {
-    emit_byte(parser, chunk, byte1);
-    emit_byte(parser, chunk, byte2);
-}
-
-fn emit_return(parser: &Parser, chunk: &mut Chunk) {
-    emit_byte(parser, chunk, OpCode::OpReturn);
-}
-
-fn make_constant(value: Value, chunk: &mut Chunk) -> usize {
-    return chunk.add_constant(value);
-}
-
-fn emit_constant(parser: &Parser, value: Value, chunk: &mut Chunk) {
-    let constant = make_constant(value, chunk);
-    emit_byte(parser, chunk, OpCode::OpConstant(constant));
-}
-
-fn end_compiler(parser: &Parser, chunk: &mut Chunk) {
-    emit_return(parser, chunk);
-
-    if DEBUG_PRINT {
-        if !parser.had_error {
-            disassemble_chunk(chunk, "code");
-        }
-    }
-}
-
-type ParseFn = fn(&mut Parser, &mut Scanner, &mut Chunk);
-
-struct ParseRule {
-    prefix: Option<ParseFn>,
-    infix: Option<ParseFn>,
-    precedence: Precedence,
-}
-
-fn get_rule(operator_type: &TokenType) -> ParseRule {
-    match operator_type {
-        TokenType::LeftParen => ParseRule {
-            prefix: Some(grouping),
-            infix: None,
-            precedence: Precedence::None,
-        },
-        TokenType::Minus => ParseRule {
-            prefix: Some(unary),
-            infix: Some(binary),
-            precedence: Precedence::Term,
-        },
-        TokenType::Plus => ParseRule {
-            prefix: None,
-            infix: Some(binary),
-            precedence: Precedence::Term,
-        },
-        TokenType::Slash => ParseRule {
-            prefix: None,
-            infix: Some(binary),
-            precedence: Precedence::Factor,
-        },
-        TokenType::Star => ParseRule {
-            prefix: None,
-            infix: Some(binary),
-            precedence: Precedence::Factor,
-        },
-        TokenType::Number => ParseRule {
-            prefix: Some(number),
-            infix: None,
-            precedence: Precedence::None,
-        },
-        TokenType::Bang => ParseRule {
-            prefix: Some(unary),
-            infix: None,
-            precedence: Precedence::None,
-        },
-        TokenType::BangEqual => ParseRule {
-            prefix: None,
-            infix: Some(binary),
-            precedence: Precedence::Equality,
-        },
-        TokenType::EqualEqual => ParseRule {
-            prefix: None,
-            infix: Some(binary),
-            precedence: Precedence::Equality,
-        },
-        TokenType::Greater => ParseRule {
-            prefix: None,
-            infix: Some(binary),
-            precedence: Precedence::Comparison,
-        },
-        TokenType::GreaterEqual => ParseRule {
-            prefix: None,
-            infix: Some(binary),
-            precedence: Precedence::Comparison,
-        },
-        TokenType::Less => ParseRule {
-            prefix: None,
-            infix: Some(binary),
-            precedence: Precedence::Comparison,
-        },
-        TokenType::LessEqual => ParseRule {
-            prefix: None,
-            infix: Some(binary),
-            precedence: Precedence::Comparison,
-        },
-        TokenType::True | TokenType::Nil | TokenType::False => ParseRule {
-            prefix: Some(literal),
-            infix: None,
-            precedence: Precedence::None,
-        },
-        TokenType::String => ParseRule {
-            prefix: Some(string),
-            infix: None,
-            precedence: Precedence::None,
-        },
-        _ => ParseRule {
-            prefix: None,
-            infix: None,
-            precedence: Precedence::None,
-        },
-    }
-}
-
-fn number(parser: &mut Parser, _scanner: &mut Scanner, chunk: &mut Chunk) {
-    let token = parser.previous.deref().as_ref().unwrap();
-    let value: f64 = token.content.parse().unwrap();
-    emit_constant(parser, Value::from_number(value), chunk);
-}
-
-fn string(parser: &mut Parser, _scanner: &mut Scanner, chunk: &mut Chunk) {
-    let token = parser.previous.deref().as_ref().unwrap();
-    emit_constant(parser, Value::from_string(token.content.to_string()), chunk)
-}
-
-fn grouping(parser: &mut Parser, scanner: &mut Scanner, chunk: &mut Chunk) {
-    expression(scanner, parser, chunk);
-    consume(
-        TokenType::RightParen,
-        "Expect ')' after expression".to_string(),
-        scanner,
-        parser,
-    )
-}
-
-fn unary(parser: &mut Parser, scanner: &mut Scanner, chunk: &mut Chunk) {
-    let token = parser.previous.deref().as_ref().unwrap().clone();
-    let operator_type = &token.t_type;
-
-    parse_precedence(scanner, parser, Precedence::Unary, chunk);
-
-    match operator_type {
-        TokenType::Bang => emit_byte(parser, chunk, OpCode::OpNot),
-        TokenType::Minus => emit_byte(parser, chunk, OpCode::OpNegate),
-        _ => return,
-    }
-}
-
-fn binary(parser: &mut Parser, scanner: &mut Scanner, chunk: &mut Chunk) {
-    let token = parser.previous.deref().as_ref().unwrap().clone();
-    let operator_type = &token.t_type;
-
-    let rule = get_rule(operator_type);
-    parse_precedence(scanner, parser, rule.precedence.next(), chunk);
-
-    match operator_type {
-        TokenType::BangEqual => emit_bytes(parser, chunk, OpCode::OpEqual, OpCode::OpNot),
-        TokenType::EqualEqual => emit_byte(parser, chunk, OpCode::OpEqual),
-        TokenType::Greater => emit_byte(parser, chunk, OpCode::OpGreater),
-        TokenType::GreaterEqual => emit_bytes(parser, chunk, OpCode::OpLess, OpCode::OpNot),
-        TokenType::Less => emit_byte(parser, chunk, OpCode::OpLess),
-        TokenType::LessEqual => emit_bytes(parser, chunk, OpCode::OpGreater, OpCode::OpNot),
-        TokenType::Plus => emit_byte(parser, chunk, OpCode::OpAdd),
-        TokenType::Minus => emit_byte(parser, chunk, OpCode::OpSubtract),
-        TokenType::Star => emit_byte(parser, chunk, OpCode::OpMultiply),
-        TokenType::Slash => emit_byte(parser, chunk, OpCode::OpDivide),
-
-        _ => return,
-    }
-}
-
-fn literal(parser: &mut Parser, _scanner: &mut Scanner, chunk: &mut Chunk) {
-    match parser.previous.deref().as_ref().unwrap().t_type {
-        TokenType::False => emit_byte(parser, chunk, OpCode::OpFalse),
-        TokenType::Nil => emit_byte(parser, chunk, OpCode::OpNil),
-        TokenType::True => emit_byte(parser, chunk, OpCode::OpTrue),
-        _ => return,
-    }
-}
-
-pub fn compile(source: String, chunk: &mut Chunk) -> bool {
-    let mut parser = Parser {
-        current: Rc::new(None),
-        previous: Rc::new(None),
-        had_error: false,
-        panic_mode: false,
-    };
-    let mut scanner = Scanner::init(source);
-
-    advance(&mut scanner, &mut parser);
-    expression(&mut scanner, &mut parser, chunk);
-    consume(
-        TokenType::Eof,
-        "Expect end of expression.".to_string(),
-        &mut scanner,
-        &mut parser,
-    );
-    end_compiler(&parser, chunk);
-    return !parser.had_error;
-}
+use std::{ops::Deref, rc::Rc};
+
+use crate::{
+    ast::FunctionType,
+    chunk::{Chunk, OpCode, BYTE_OPERAND_MAX, LONG_OPERAND_MAX, SHORT_OPERAND_MAX},
+    debug::disassemble_chunk,
+    error::CompileDiagnostic,
+    number::{self, Operation},
+    scanner::{Scanner, Span, Token, TokenType},
+    values::{ObjFunction, ObjRef, ObjectType, UpvalueDescriptor, Value},
+    vm::VM,
+    DEBUG_NO_FOLDING, DEBUG_PRINT,
+};
+
+// arguments and parameters are capped like in the book, even though our operands could hold more
+const MAX_ARGUMENTS: usize = 255;
+
+struct Parser {
+    current: Rc<Option<Token>>,
+    previous: Rc<Option<Token>>,
+    // everything reported so far, compilation failed if this isn't empty
+    diagnostics: Vec<CompileDiagnostic>,
+    panic_mode: bool,
+}
+
+impl Parser {
+    fn error_at_current(&mut self, message: String) {
+        self.error_at(true, message);
+    }
+
+    fn error(&mut self, message: String) {
+        self.error_at(false, message);
+    }
+
+    fn error_at(&mut self, current: bool, message: String) {
+        if self.panic_mode {
+            return;
+        }
+
+        let token = if current {
+            self.current.deref().as_ref().unwrap()
+        } else {
+            self.previous.deref().as_ref().unwrap()
+        };
+
+        self.panic_mode = true;
+        self.diagnostics.push(CompileDiagnostic::at(token, message));
+    }
+
+    fn had_error(&self) -> bool {
+        return !self.diagnostics.is_empty();
+    }
+}
+
+// shared with the ast parser, which climbs the same precedence levels
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Precedence {
+    None = 0,
+    Assignment = 1, // =
+    Or = 2,         // or
+    And = 3,        // and
+    Equality = 4,   // == !=
+    Comparison = 5, // < > <= >=
+    Term = 6,       // + -
+    Factor = 7,     // * /
+    Unary = 8,      // ! -
+    Call = 9,       // . ()
+    Primary = 10,
+}
+
+impl Precedence {
+    pub(crate) fn next(&self) -> Precedence {
+        return match self {
+            Precedence::None => Self::Assignment,
+            Precedence::Assignment => Self::Or,
+            Precedence::Or => Self::And,
+            Precedence::And => Self::Equality,
+            Precedence::Equality => Self::Comparison,
+            Precedence::Comparison => Self::Term,
+            Precedence::Term => Self::Factor,
+            Precedence::Factor => Self::Unary,
+            Precedence::Unary => Self::Call,
+            Precedence::Call => Self::Primary,
+            Precedence::Primary => Self::None,
+        };
+    }
+}
+
+struct Local {
+    name: Token,
+    // None while the initializer is being compiled, so `var a = a;` can be caught
+    depth: Option<usize>,
+    // captured locals get moved off the stack when they go out of scope instead of being popped
+    is_captured: bool,
+}
+
+// the state for the function currently being compiled
+// the locals vector mirrors the function's stack window at runtime, a local's index here is its slot
+struct FunctionCompiler {
+    function: ObjFunction,
+    function_type: FunctionType,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    // where each instruction emitted so far starts, folding needs to find the last few
+    instruction_starts: Vec<usize>,
+    // the furthest a forward jump has been patched to land, code before it can't be folded away
+    // loops jump back to statement boundaries, which an expression's operands never straddle
+    jump_target: usize,
+}
+
+impl FunctionCompiler {
+    fn init(function_type: FunctionType, name: Option<String>) -> Self {
+        // slot zero holds the function being called, it gets a name nobody can refer to
+        // methods keep their receiver there instead, so it is reachable as `this`
+        let slot_zero = match function_type {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            FunctionType::Function | FunctionType::Script => "",
+        };
+
+        FunctionCompiler {
+            function: ObjFunction::init(name),
+            function_type,
+            locals: vec![Local {
+                name: synthetic_token(slot_zero, Span::default()),
+                depth: Some(0),
+                is_captured: false,
+            }],
+            scope_depth: 0,
+            instruction_starts: Vec::new(),
+            jump_target: 0,
+        }
+    }
+}
+
+// a token that never came from the source, used for the hidden `this` and `super` locals
+// the span is what instructions using it point at, locals never emit it so they can use the default
+fn synthetic_token(text: &str, span: Span) -> Token {
+    Token {
+        t_type: TokenType::Identifier,
+        start: 0,
+        content: text.to_string(),
+        length: text.len(),
+        line: 0,
+        span,
+        literal: None,
+    }
+}
+
+// tracks the class body we are in, used to validate `this` and `super`
+struct ClassCompiler {
+    has_superclass: bool,
+}
+
+// the book links compilers together through an enclosing pointer, we keep them in a stack instead
+pub struct Compiler<'a> {
+    // objects made while compiling go straight into the vm's heap
+    vm: &'a mut VM,
+    parser: Parser,
+    scanner: Scanner,
+    states: Vec<FunctionCompiler>,
+    classes: Vec<ClassCompiler>,
+}
+
+impl<'a> Compiler<'a> {
+    fn init(source: String, vm: &'a mut VM) -> Self {
+        Compiler {
+            vm,
+            parser: Parser {
+                current: Rc::new(None),
+                previous: Rc::new(None),
+                diagnostics: Vec::new(),
+                panic_mode: false,
+            },
+            scanner: Scanner::init(source),
+            states: vec![FunctionCompiler::init(FunctionType::Script, None)],
+            classes: Vec::new(),
+        }
+    }
+
+    fn current(&self) -> &FunctionCompiler {
+        return self.states.last().unwrap();
+    }
+
+    fn current_mut(&mut self) -> &mut FunctionCompiler {
+        return self.states.last_mut().unwrap();
+    }
+
+    fn current_chunk(&mut self) -> &mut Chunk {
+        return &mut self.current_mut().function.chunk;
+    }
+
+    fn previous(&self) -> &Token {
+        return self.parser.previous.deref().as_ref().unwrap();
+    }
+
+    fn advance(&mut self) {
+        self.parser.previous = Rc::new(self.parser.current.deref().clone());
+
+        loop {
+            let current = self.scanner.scan_token();
+
+            if current.t_type != TokenType::Error {
+                self.parser.current = Rc::new(Some(current));
+                break;
+            }
+
+            let current_content = current.content.clone();
+            self.parser.current = Rc::new(Some(current));
+            self.parser.error_at_current(current_content);
+        }
+    }
+
+    fn consume(&mut self, t_type: TokenType, message: &str) {
+        if self.check(t_type) {
+            self.advance();
+            return;
+        }
+
+        self.parser.error_at_current(message.to_string());
+    }
+
+    fn check(&self, t_type: TokenType) -> bool {
+        match self.parser.current.deref() {
+            Some(token) => token.t_type == t_type,
+            None => false,
+        }
+    }
+
+    fn match_token(&mut self, t_type: TokenType) -> bool {
+        if !self.check(t_type) {
+            return false;
+        }
+
+        self.advance();
+        return true;
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) {
+        self.advance();
+        let prefix_rule = get_rule(&self.previous().t_type).prefix;
+        // only the lowest precedence expressions may be assignment targets, otherwise `a * b = c` would parse
+        let can_assign = precedence <= Precedence::Assignment;
+        match prefix_rule {
+            Some(func) => func(self, can_assign),
+            None => {
+                self.parser.error("Expect expression".to_string());
+                return;
+            }
+        }
+
+        while precedence
+            <= get_rule(&self.parser.current.deref().as_ref().unwrap().t_type).precedence
+        {
+            self.advance();
+            let infix_rule = get_rule(&self.previous().t_type).infix;
+            match infix_rule {
+                Some(func) => func(self, can_assign),
+                None => panic!("this shouldn't error"),
+            }
+        }
+
+        if can_assign && self.match_token(TokenType::Equal) {
+            self.parser.error("Invalid assignment target.".to_string());
+        }
+    }
+
+    // the collector can't see the chunks we're still building, so everything we allocate stays rooted until compile ends
+    fn alloc(&mut self, object: ObjectType) -> ObjRef {
+        let reference = self.vm.alloc(object);
+        self.vm.compiler_roots.push(reference);
+        return reference;
+    }
+
+    fn alloc_string(&mut self, content: String) -> Value {
+        let reference = self.vm.alloc_string(content);
+        self.vm.compiler_roots.push(reference);
+        return Value::from_object(reference);
+    }
+
+    fn identifier_constant(&mut self, name: &Token) -> usize {
+        let value = self.alloc_string(name.content.to_string());
+        return self.make_constant(value);
+    }
+
+    fn add_local(&mut self, name: Token) {
+        // slots are one byte operands
+        if self.current().locals.len() > BYTE_OPERAND_MAX {
+            self.parser
+                .error("Too many local variables in function.".to_string());
+            return;
+        }
+
+        self.current_mut().locals.push(Local {
+            name,
+            depth: None,
+            is_captured: false,
+        });
+    }
+
+    // state_index picks which function's locals to look through, the innermost function is the last state
+    fn resolve_local(&mut self, state_index: usize, name: &Token) -> Option<usize> {
+        let found = self.states[state_index]
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name.content == name.content)
+            .map(|(slot, local)| (slot, local.depth.is_none()));
+
+        let (slot, uninitialized) = found?;
+        if uninitialized {
+            self.parser
+                .error("Can't read local variable in its own initializer.".to_string());
+        }
+
+        return Some(slot);
+    }
+
+    fn add_upvalue(&mut self, state_index: usize, index: usize, is_local: bool) -> usize {
+        let upvalues = &mut self.states[state_index].function.upvalues;
+
+        // closing over the same variable twice should share one upvalue
+        if let Some(existing) = upvalues
+            .iter()
+            .position(|upvalue| upvalue.index == index && upvalue.is_local == is_local)
+        {
+            return existing;
+        }
+
+        if upvalues.len() > BYTE_OPERAND_MAX {
+            self.parser
+                .error("Too many closure variables in function.".to_string());
+            return 0;
+        }
+
+        upvalues.push(UpvalueDescriptor { is_local, index });
+        return upvalues.len() - 1;
+    }
+
+    // walks outwards through the enclosing functions, threading the upvalue through every function in between
+    fn resolve_upvalue(&mut self, state_index: usize, name: &Token) -> Option<usize> {
+        if state_index == 0 {
+            return None;
+        }
+
+        if let Some(local) = self.resolve_local(state_index - 1, name) {
+            self.states[state_index - 1].locals[local].is_captured = true;
+            return Some(self.add_upvalue(state_index, local, true));
+        }
+
+        if let Some(upvalue) = self.resolve_upvalue(state_index - 1, name) {
+            return Some(self.add_upvalue(state_index, upvalue, false));
+        }
+
+        return None;
+    }
+
+    // globals are late bound, so only locals need to be recorded at compile time
+    fn declare_variable(&mut self) {
+        let scope_depth = self.current().scope_depth;
+        if scope_depth == 0 {
+            return;
+        }
+
+        let name = self.previous().clone();
+        let mut duplicate = false;
+        for local in self.current().locals.iter().rev() {
+            if local.depth.is_some_and(|depth| depth < scope_depth) {
+                break;
+            }
+
+            if local.name.content == name.content {
+                duplicate = true;
+                break;
+            }
+        }
+
+        if duplicate {
+            self.parser
+                .error("Already a variable with this name in this scope.".to_string());
+        }
+
+        self.add_local(name);
+    }
+
+    fn parse_variable(&mut self, message: &str) -> usize {
+        self.consume(TokenType::Identifier, message);
+
+        self.declare_variable();
+        if self.current().scope_depth > 0 {
+            return 0;
+        }
+
+        let name = self.previous().clone();
+        return self.identifier_constant(&name);
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.current_mut();
+        // a global function declaration has no local to mark
+        if state.scope_depth == 0 {
+            return;
+        }
+
+        let depth = state.scope_depth;
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    fn define_variable(&mut self, global: usize) {
+        // a local's value is already sitting in its stack slot
+        if self.current().scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+
+        self.emit_byte(OpCode::OpDefineGlobal(global));
+    }
+
+    fn expression(&mut self) {
+        self.parse_precedence(Precedence::Assignment);
+    }
+
+    fn block(&mut self) {
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            self.declaration();
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block.");
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current_mut().scope_depth -= 1;
+
+        let scope_depth = self.current().scope_depth;
+        while let Some(local) = self.current().locals.last() {
+            if local.depth.is_none_or(|depth| depth <= scope_depth) {
+                break;
+            }
+
+            if local.is_captured {
+                self.emit_byte(OpCode::OpCloseUpvalue);
+            } else {
+                self.emit_byte(OpCode::OpPop);
+            }
+            self.current_mut().locals.pop();
+        }
+    }
+
+    fn function(&mut self, function_type: FunctionType) {
+        let name = self.previous().content.to_string();
+        self.states
+            .push(FunctionCompiler::init(function_type, Some(name)));
+        // never closed, the whole state is thrown away once the body is compiled
+        self.begin_scope();
+
+        self.consume(TokenType::LeftParen, "Expect '(' after function name.");
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.current_mut().function.arity += 1;
+                if self.current().function.arity > MAX_ARGUMENTS {
+                    self.parser
+                        .error_at_current("Can't have more than 255 parameters.".to_string());
+                }
+
+                let constant = self.parse_variable("Expect parameter name.");
+                self.define_variable(constant);
+
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.");
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.");
+        self.block();
+
+        let function = self.end_compiler();
+        let function = self.alloc(ObjectType::Function(function));
+        let constant = self.make_constant(Value::from_object(function));
+        self.emit_byte(OpCode::OpClosure(constant));
+    }
+
+    fn method(&mut self) {
+        self.consume(TokenType::Identifier, "Expect method name.");
+        let name = self.previous().clone();
+        let constant = self.identifier_constant(&name);
+
+        let function_type = if name.content == "init" {
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+
+        self.function(function_type);
+        self.emit_byte(OpCode::OpMethod(constant));
+    }
+
+    fn class_declaration(&mut self) {
+        self.consume(TokenType::Identifier, "Expect class name.");
+        let class_name = self.previous().clone();
+        let name_constant = self.identifier_constant(&class_name);
+        self.declare_variable();
+
+        self.emit_byte(OpCode::OpClass(name_constant));
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassCompiler {
+            has_superclass: false,
+        });
+
+        if self.match_token(TokenType::Less) {
+            self.consume(TokenType::Identifier, "Expect superclass name.");
+            variable(self, false);
+
+            if class_name.content == self.previous().content {
+                self.parser
+                    .error("A class can't inherit from itself.".to_string());
+            }
+
+            // each subclass gets its own scope holding `super`, so methods can close over it
+            self.begin_scope();
+            self.add_local(synthetic_token("super", Span::default()));
+            self.define_variable(0);
+
+            named_variable(self, &class_name, false);
+            self.emit_byte(OpCode::OpInherit);
+            self.classes.last_mut().unwrap().has_superclass = true;
+        }
+
+        // OpMethod expects the class right below each method, so load it back onto the stack
+        named_variable(self, &class_name, false);
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.");
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            self.method();
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.");
+        self.emit_byte(OpCode::OpPop);
+
+        if self.classes.last().unwrap().has_superclass {
+            self.end_scope();
+        }
+
+        self.classes.pop();
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expect function name.");
+        // functions can refer to themselves, so the name is usable before the body is compiled
+        self.mark_initialized();
+        self.function(FunctionType::Function);
+        self.define_variable(global);
+    }
+
+    fn argument_list(&mut self) -> usize {
+        let mut arg_count = 0;
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.expression();
+                if arg_count == MAX_ARGUMENTS {
+                    self.parser
+                        .error("Can't have more than 255 arguments.".to_string());
+                }
+                arg_count += 1;
+
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+        return arg_count;
+    }
+
+    fn var_declaration(&mut self) {
+        let global = self.parse_variable("Expect variable name.");
+
+        if self.match_token(TokenType::Equal) {
+            self.expression();
+        } else {
+            self.emit_byte(OpCode::OpNil);
+        }
+
+        self.consume(
+            TokenType::SemiColon,
+            "Expect ';' after variable declaration.",
+        );
+
+        self.define_variable(global);
+    }
+
+    fn return_statement(&mut self) {
+        if self.current().function_type == FunctionType::Script {
+            self.parser
+                .error("Can't return from top-level code.".to_string());
+        }
+
+        if self.match_token(TokenType::SemiColon) {
+            self.emit_return();
+        } else {
+            if self.current().function_type == FunctionType::Initializer {
+                self.parser
+                    .error("Can't return a value from an initializer.".to_string());
+            }
+
+            self.expression();
+            self.consume(TokenType::SemiColon, "Expect ';' after return value.");
+            self.emit_byte(OpCode::OpReturn);
+        }
+    }
+
+    fn print_statement(&mut self) {
+        self.expression();
+        self.consume(TokenType::SemiColon, "Expect ';' after value.");
+        self.emit_byte(OpCode::OpPrint);
+    }
+
+    fn expression_statement(&mut self) {
+        self.expression();
+        self.consume(TokenType::SemiColon, "Expect ';' after expression.");
+        self.emit_byte(OpCode::OpPop);
+    }
+
+    // skips tokens until we reach something that looks like a statement boundary
+    // this keeps one syntax error from cascading into a pile of bogus ones
+    fn synchronize(&mut self) {
+        self.parser.panic_mode = false;
+
+        while !self.check(TokenType::Eof) {
+            if let Some(previous) = self.parser.previous.deref() {
+                if previous.t_type == TokenType::SemiColon {
+                    return;
+                }
+            }
+
+            match self.parser.current.deref().as_ref().unwrap().t_type {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    fn declaration(&mut self) {
+        if self.match_token(TokenType::Class) {
+            self.class_declaration();
+        } else if self.match_token(TokenType::Fun) {
+            self.fun_declaration();
+        } else if self.match_token(TokenType::Var) {
+            self.var_declaration();
+        } else {
+            self.statement();
+        }
+
+        if self.parser.panic_mode {
+            self.synchronize();
+        }
+    }
+
+    fn if_statement(&mut self) {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.");
+        self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after condition.");
+
+        let then_jump = self.emit_jump(OpCode::OpJumpIfFalse(0));
+        self.emit_byte(OpCode::OpPop);
+        self.statement();
+
+        let else_jump = self.emit_jump(OpCode::OpJump(0));
+        self.patch_jump(then_jump);
+        self.emit_byte(OpCode::OpPop);
+
+        if self.match_token(TokenType::Else) {
+            self.statement();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = self.current_chunk().code.len();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after condition.");
+
+        let exit_jump = self.emit_jump(OpCode::OpJumpIfFalse(0));
+        self.emit_byte(OpCode::OpPop);
+        self.statement();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_byte(OpCode::OpPop);
+    }
+
+    fn for_statement(&mut self) {
+        // the initializer's variable only lives for the duration of the loop
+        self.begin_scope();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.");
+        if self.match_token(TokenType::SemiColon) {
+            // no initializer
+        } else if self.match_token(TokenType::Var) {
+            self.var_declaration();
+        } else {
+            self.expression_statement();
+        }
+
+        let mut loop_start = self.current_chunk().code.len();
+        let mut exit_jump = None;
+        if !self.match_token(TokenType::SemiColon) {
+            self.expression();
+            self.consume(TokenType::SemiColon, "Expect ';' after loop condition.");
+
+            exit_jump = Some(self.emit_jump(OpCode::OpJumpIfFalse(0)));
+            self.emit_byte(OpCode::OpPop);
+        }
+
+        // the increment is compiled before the body, so jump over it now and loop back to it after the body
+        if !self.match_token(TokenType::RightParen) {
+            let body_jump = self.emit_jump(OpCode::OpJump(0));
+            let increment_start = self.current_chunk().code.len();
+            self.expression();
+            self.emit_byte(OpCode::OpPop);
+            self.consume(TokenType::RightParen, "Expect ')' after for clauses.");
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement();
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_byte(OpCode::OpPop);
+        }
+
+        self.end_scope();
+    }
+
+    fn statement(&mut self) {
+        if self.match_token(TokenType::Print) {
+            self.print_statement();
+        } else if self.match_token(TokenType::Return) {
+            self.return_statement();
+        } else if self.match_token(TokenType::If) {
+            self.if_statement();
+        } else if self.match_token(TokenType::While) {
+            self.while_statement();
+        } else if self.match_token(TokenType::For) {
+            self.for_statement();
+        } else if self.check(TokenType::LeftBrace) && !self.scanner.brace_opens_map() {
+            self.advance();
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else {
+            self.expression_statement();
+        }
+    }
+
+    // emitting byte code
+    fn emit_byte(&mut self, byte: OpCode) {
+        let span = match self.parser.previous.deref().as_ref() {
+            Some(tok) => tok.span,
+            None => Span::default(),
+        };
+
+        self.emit_byte_at(byte, span);
+    }
+
+    fn emit_bytes(&mut self, byte1: OpCode, byte2: OpCode) {
+        self.emit_byte(byte1);
+        self.emit_byte(byte2);
+    }
+
+    // for instructions that can fail at runtime, the error should point at the operator or name
+    // that caused them rather than whatever token was parsed last
+    fn emit_byte_at(&mut self, byte: OpCode, span: Span) {
+        let start = self.current_chunk().code.len();
+        self.current_mut().instruction_starts.push(start);
+        self.current_chunk().write(byte, span);
+    }
+
+    // arithmetic, comparisons and `!` go through here so they can be folded when their operands are literals
+    fn emit_operator(&mut self, operator: OpCode, span: Span) {
+        if DEBUG_NO_FOLDING || !self.fold(operator) {
+            self.emit_byte_at(operator, span);
+        }
+    }
+
+    // the literal an instruction pushes, along with its constant slot if it has one
+    fn literal_at(&self, start: usize) -> Option<(Value, Option<usize>)> {
+        let chunk = &self.current().function.chunk;
+        match chunk.read(start)? {
+            OpCode::OpConstant(index) | OpCode::OpConstantLong(index) => {
+                return Some((chunk.constants.get(&index), Some(index)));
+            }
+            OpCode::OpNil => return Some((Value::from_nil(), None)),
+            OpCode::OpTrue => return Some((Value::from_bool(true), None)),
+            OpCode::OpFalse => return Some((Value::from_bool(false), None)),
+            _ => return None,
+        }
+    }
+
+    // replaces the instructions pushing an operator's operands with its result, if they're all literals
+    // anything that would be a runtime error, like `-"a"`, is left alone so it still fails when run
+    fn fold(&mut self, operator: OpCode) -> bool {
+        let arity = match operator {
+            OpCode::OpNegate | OpCode::OpNot => 1,
+            OpCode::OpAdd
+            | OpCode::OpSubtract
+            | OpCode::OpMultiply
+            | OpCode::OpDivide
+            | OpCode::OpFloorDivide
+            | OpCode::OpModulo
+            | OpCode::OpGreater
+            | OpCode::OpLess
+            | OpCode::OpEqual => 2,
+            _ => return false,
+        };
+
+        let starts = &self.current().instruction_starts;
+        if starts.len() < arity {
+            return false;
+        }
+        let operands_start = starts[starts.len() - arity];
+        // a jump landing between the operands means they aren't always the ones on the stack
+        if operands_start < self.current().jump_target {
+            return false;
+        }
+
+        let mut operands = Vec::new();
+        let mut constants = Vec::new();
+        for start in starts[starts.len() - arity..].iter().copied() {
+            match self.literal_at(start) {
+                Some((value, constant)) => {
+                    operands.push(value);
+                    constants.extend(constant);
+                }
+                None => return false,
+            }
+        }
+
+        let heap = &self.vm.heap;
+        let result = match (operator, operands.as_slice()) {
+            (OpCode::OpNegate, [a]) => match number::negate(*a) {
+                Ok(negated) => negated,
+                Err(_) => return false,
+            },
+            (OpCode::OpNot, [a]) => Value::from_bool(a.is_falsey()),
+            (OpCode::OpEqual, [a, b]) => Value::from_bool(number::equal(*a, *b)),
+            (OpCode::OpAdd, [a, b]) if heap.is_string(a) && heap.is_string(b) => {
+                let content = heap.as_string(a.as_object()).content.clone()
+                    + &heap.as_string(b.as_object()).content;
+                self.alloc_string(content)
+            }
+            (_, [a, b]) => {
+                let operation = match operator {
+                    OpCode::OpAdd => Operation::Plus,
+                    OpCode::OpSubtract => Operation::Minus,
+                    OpCode::OpMultiply => Operation::Star,
+                    OpCode::OpDivide => Operation::Div,
+                    OpCode::OpFloorDivide => Operation::FloorDiv,
+                    OpCode::OpModulo => Operation::Modulo,
+                    OpCode::OpGreater => Operation::Greater,
+                    OpCode::OpLess => Operation::Less,
+                    _ => return false,
+                };
+                // overflow, division by zero and non-numbers are all left to fail at runtime
+                match number::binary(operation, *a, *b) {
+                    Ok(result) => result,
+                    Err(_) => return false,
+                }
+            }
+            _ => return false,
+        };
+
+        // the operands' constants were the last ones added, so their slots can be reused
+        self.current_chunk().truncate(operands_start);
+        let state = self.current_mut();
+        state
+            .instruction_starts
+            .truncate(state.instruction_starts.len() - arity);
+        for constant in constants.iter().rev() {
+            if *constant + 1 == self.current_chunk().constants.values.len() {
+                self.current_chunk().constants.values.pop();
+            }
+        }
+
+        if result.is_bool() {
+            let literal = if result.as_bool() {
+                OpCode::OpTrue
+            } else {
+                OpCode::OpFalse
+            };
+            self.emit_byte(literal);
+        } else {
+            self.emit_constant(result);
+        }
+        return true;
+    }
+
+    fn emit_operators(&mut self, operator1: OpCode, operator2: OpCode, span: Span) {
+        self.emit_operator(operator1, span);
+        self.emit_operator(operator2, span);
+    }
+
+    // emits a jump with a placeholder offset, returning where it lives so it can be patched once the target is known
+    fn emit_jump(&mut self, instruction: OpCode) -> usize {
+        let offset = self.current_chunk().code.len();
+        self.emit_byte(instruction);
+        return offset;
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        // offsets are relative to the end of the jump instruction, which is where the ip will be
+        let instruction = self.current_chunk().read(offset).unwrap();
+        let jump = self.current_chunk().code.len() - offset - instruction.size();
+        if jump > SHORT_OPERAND_MAX {
+            self.parser.error("Too much code to jump over.".to_string());
+        }
+
+        let patched = match instruction {
+            OpCode::OpJump(_) => OpCode::OpJump(jump),
+            OpCode::OpJumpIfFalse(_) => OpCode::OpJumpIfFalse(jump),
+            _ => panic!("tried to patch an instruction that isn't a jump"),
+        };
+        self.current_chunk().patch(offset, patched);
+
+        let target = self.current_chunk().code.len();
+        self.current_mut().jump_target = target;
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        // also step back over the loop instruction itself
+        let offset = self.current_chunk().code.len() - loop_start + OpCode::OpLoop(0).size();
+        if offset > SHORT_OPERAND_MAX {
+            self.parser.error("Loop body too large.".to_string());
+        }
+
+        self.emit_byte(OpCode::OpLoop(offset));
+    }
+
+    // falling off the end of a function implicitly returns nil
+    // initializers always hand back the instance sitting in slot zero
+    fn emit_return(&mut self) {
+        if self.current().function_type == FunctionType::Initializer {
+            self.emit_bytes(OpCode::OpGetLocal(0), OpCode::OpReturn);
+        } else {
+            self.emit_bytes(OpCode::OpNil, OpCode::OpReturn);
+        }
+    }
+
+    // names and functions are referred to with two byte operands
+    fn make_constant(&mut self, value: Value) -> usize {
+        let constant = self.current_chunk().add_constant(value);
+        if constant > SHORT_OPERAND_MAX {
+            self.parser
+                .error("Too many constants in one chunk.".to_string());
+            return 0;
+        }
+
+        return constant;
+    }
+
+    // plain constants get the short form when they can and the 24 bit long form otherwise
+    fn emit_constant(&mut self, value: Value) {
+        let constant = self.current_chunk().add_constant(value);
+        if constant <= BYTE_OPERAND_MAX {
+            self.emit_byte(OpCode::OpConstant(constant));
+        } else if constant <= LONG_OPERAND_MAX {
+            self.emit_byte(OpCode::OpConstantLong(constant));
+        } else {
+            self.parser
+                .error("Too many constants in one chunk.".to_string());
+        }
+    }
+
+    fn end_compiler(&mut self) -> ObjFunction {
+        self.emit_return();
+        let state = self.states.pop().unwrap();
+
+        if DEBUG_PRINT && !self.parser.had_error() {
+            disassemble_chunk(
+                &state.function.chunk,
+                &state.function.to_string(),
+                &self.vm.heap,
+            );
+        }
+
+        return state.function;
+    }
+}
+
+type ParseFn = fn(&mut Compiler, bool);
+
+struct ParseRule {
+    prefix: Option<ParseFn>,
+    infix: Option<ParseFn>,
+    precedence: Precedence,
+}
+
+fn get_rule(operator_type: &TokenType) -> ParseRule {
+    match operator_type {
+        TokenType::LeftParen => ParseRule {
+            prefix: Some(grouping),
+            infix: Some(call),
+            precedence: Precedence::Call,
+        },
+        TokenType::LeftBrace => ParseRule {
+            prefix: Some(map),
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::LeftBracket => ParseRule {
+            prefix: Some(list),
+            infix: Some(subscript),
+            precedence: Precedence::Call,
+        },
+        TokenType::Dot => ParseRule {
+            prefix: None,
+            infix: Some(dot),
+            precedence: Precedence::Call,
+        },
+        TokenType::Super => ParseRule {
+            prefix: Some(super_),
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::This => ParseRule {
+            prefix: Some(this),
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::Minus => ParseRule {
+            prefix: Some(unary),
+            infix: Some(binary),
+            precedence: Precedence::Term,
+        },
+        TokenType::Plus => ParseRule {
+            prefix: None,
+            infix: Some(binary),
+            precedence: Precedence::Term,
+        },
+        TokenType::Slash => ParseRule {
+            prefix: None,
+            infix: Some(binary),
+            precedence: Precedence::Factor,
+        },
+        TokenType::SlashSlash | TokenType::Percent | TokenType::Star => ParseRule {
+            prefix: None,
+            infix: Some(binary),
+            precedence: Precedence::Factor,
+        },
+        TokenType::Number => ParseRule {
+            prefix: Some(number),
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::Int => ParseRule {
+            prefix: Some(int),
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::Bang => ParseRule {
+            prefix: Some(unary),
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::BangEqual => ParseRule {
+            prefix: None,
+            infix: Some(binary),
+            precedence: Precedence::Equality,
+        },
+        TokenType::EqualEqual => ParseRule {
+            prefix: None,
+            infix: Some(binary),
+            precedence: Precedence::Equality,
+        },
+        TokenType::Greater => ParseRule {
+            prefix: None,
+            infix: Some(binary),
+            precedence: Precedence::Comparison,
+        },
+        TokenType::GreaterEqual => ParseRule {
+            prefix: None,
+            infix: Some(binary),
+            precedence: Precedence::Comparison,
+        },
+        TokenType::Less => ParseRule {
+            prefix: None,
+            infix: Some(binary),
+            precedence: Precedence::Comparison,
+        },
+        TokenType::LessEqual => ParseRule {
+            prefix: None,
+            infix: Some(binary),
+            precedence: Precedence::Comparison,
+        },
+        TokenType::True | TokenType::Nil | TokenType::False => ParseRule {
+            prefix: Some(literal),
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::And => ParseRule {
+            prefix: None,
+            infix: Some(and),
+            precedence: Precedence::And,
+        },
+        TokenType::Or => ParseRule {
+            prefix: None,
+            infix: Some(or),
+            precedence: Precedence::Or,
+        },
+        TokenType::Identifier => ParseRule {
+            prefix: Some(variable),
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::String => ParseRule {
+            prefix: Some(string),
+            infix: None,
+            precedence: Precedence::None,
+        },
+        TokenType::Interpolation => ParseRule {
+            prefix: Some(interpolation),
+            infix: None,
+            precedence: Precedence::None,
+        },
+        _ => ParseRule {
+            prefix: None,
+            infix: None,
+            precedence: Precedence::None,
+        },
+    }
+}
+
+fn number(compiler: &mut Compiler, _can_assign: bool) {
+    let value: f64 = compiler.previous().content.parse().unwrap();
+    compiler.emit_constant(Value::from_number(value));
+}
+
+// the scanner already made sure it fits
+fn int(compiler: &mut Compiler, _can_assign: bool) {
+    let value: i64 = compiler.previous().content.parse().unwrap();
+    compiler.emit_constant(Value::from_int(value));
+}
+
+fn string(compiler: &mut Compiler, _can_assign: bool) {
+    let content = compiler.previous().literal.clone().unwrap_or_default();
+    let value = compiler.alloc_string(content);
+    compiler.emit_constant(value)
+}
+
+// `"a ${b} c"` arrives as an interpolation token for each `${`, with the text after the last `}` as the end token
+// it compiles to concatenation, each expression stringified first so anything can be interpolated
+fn interpolation(compiler: &mut Compiler, _can_assign: bool) {
+    // nothing is on the stack yet, the first piece doesn't get added to anything
+    let mut first = true;
+
+    loop {
+        let segment = compiler.previous().clone();
+        let text = segment.literal.clone().unwrap_or_default();
+        // empty text would only be concatenated for nothing
+        if !text.is_empty() {
+            let value = compiler.alloc_string(text);
+            compiler.emit_constant(value);
+            if !first {
+                compiler.emit_byte_at(OpCode::OpAdd, segment.span);
+            }
+            first = false;
+        }
+
+        if segment.t_type == TokenType::InterpolationEnd {
+            return;
+        }
+
+        compiler.expression();
+        compiler.emit_byte_at(OpCode::OpStringify, segment.span);
+        if !first {
+            compiler.emit_byte_at(OpCode::OpAdd, segment.span);
+        }
+        first = false;
+
+        if !compiler.match_token(TokenType::Interpolation)
+            && !compiler.match_token(TokenType::InterpolationEnd)
+        {
+            compiler
+                .parser
+                .error_at_current("Expect '}' after interpolated expression.".to_string());
+            return;
+        }
+    }
+}
+
+fn named_variable(compiler: &mut Compiler, name: &Token, can_assign: bool) {
+    let state_index = compiler.states.len() - 1;
+    let (get_op, set_op) = if let Some(slot) = compiler.resolve_local(state_index, name) {
+        (OpCode::OpGetLocal(slot), OpCode::OpSetLocal(slot))
+    } else if let Some(slot) = compiler.resolve_upvalue(state_index, name) {
+        (OpCode::OpGetUpvalue(slot), OpCode::OpSetUpvalue(slot))
+    } else {
+        let arg = compiler.identifier_constant(name);
+        (OpCode::OpGetGlobal(arg), OpCode::OpSetGlobal(arg))
+    };
+
+    if can_assign && compiler.match_token(TokenType::Equal) {
+        compiler.expression();
+        compiler.emit_byte_at(set_op, name.span);
+    } else {
+        compiler.emit_byte_at(get_op, name.span);
+    }
+}
+
+fn variable(compiler: &mut Compiler, can_assign: bool) {
+    let name = compiler.previous().clone();
+    named_variable(compiler, &name, can_assign);
+}
+
+fn grouping(compiler: &mut Compiler, _can_assign: bool) {
+    compiler.expression();
+    compiler.consume(TokenType::RightParen, "Expect ')' after expression")
+}
+
+fn call(compiler: &mut Compiler, _can_assign: bool) {
+    let paren_span = compiler.previous().span;
+    let arg_count = compiler.argument_list();
+    compiler.emit_byte_at(OpCode::OpCall(arg_count), paren_span);
+}
+
+fn list(compiler: &mut Compiler, _can_assign: bool) {
+    let bracket_span = compiler.previous().span;
+    let mut item_count = 0;
+    if !compiler.check(TokenType::RightBracket) {
+        loop {
+            compiler.expression();
+            // the count is a three byte operand, like OpConstantLong's index, so data heavy scripts fit
+            if item_count == LONG_OPERAND_MAX {
+                compiler.parser.error(format!(
+                    "Can't have more than {} items in a list literal.",
+                    LONG_OPERAND_MAX
+                ));
+            }
+            item_count += 1;
+
+            if !compiler.match_token(TokenType::Comma) {
+                break;
+            }
+        }
+    }
+
+    compiler.consume(TokenType::RightBracket, "Expect ']' after list items.");
+    compiler.emit_byte_at(OpCode::OpBuildList(item_count), bracket_span);
+}
+
+// a brace starting a statement is a block unless Scanner::brace_opens_map says otherwise
+fn map(compiler: &mut Compiler, _can_assign: bool) {
+    let brace_span = compiler.previous().span;
+    let mut entry_count = 0;
+    if !compiler.check(TokenType::RightBrace) {
+        loop {
+            compiler.expression();
+            compiler.consume(TokenType::Colon, "Expect ':' after map key.");
+            compiler.expression();
+            if entry_count == LONG_OPERAND_MAX {
+                compiler.parser.error(format!(
+                    "Can't have more than {} entries in a map literal.",
+                    LONG_OPERAND_MAX
+                ));
+            }
+            entry_count += 1;
+
+            if !compiler.match_token(TokenType::Comma) {
+                break;
+            }
+        }
+    }
+
+    compiler.consume(TokenType::RightBrace, "Expect '}' after map entries.");
+    compiler.emit_byte_at(OpCode::OpBuildMap(entry_count), brace_span);
+}
+
+// `xs[i]` and `xs[i] = v`, errors point at the bracket
+fn subscript(compiler: &mut Compiler, can_assign: bool) {
+    let bracket_span = compiler.previous().span;
+    compiler.expression();
+    compiler.consume(TokenType::RightBracket, "Expect ']' after index.");
+
+    if can_assign && compiler.match_token(TokenType::Equal) {
+        compiler.expression();
+        compiler.emit_byte_at(OpCode::OpSetIndex, bracket_span);
+    } else {
+        compiler.emit_byte_at(OpCode::OpGetIndex, bracket_span);
+    }
+}
+
+fn dot(compiler: &mut Compiler, can_assign: bool) {
+    compiler.consume(TokenType::Identifier, "Expect property name after '.'.");
+    let name_token = compiler.previous().clone();
+    let name = compiler.identifier_constant(&name_token);
+
+    if can_assign && compiler.match_token(TokenType::Equal) {
+        compiler.expression();
+        compiler.emit_byte_at(OpCode::OpSetProperty(name), name_token.span);
+    } else if compiler.match_token(TokenType::LeftParen) {
+        // `a.b(...)` skips creating a bound method and calls straight through
+        let arg_count = compiler.argument_list();
+        compiler.emit_byte_at(OpCode::OpInvoke(name, arg_count), name_token.span);
+    } else {
+        compiler.emit_byte_at(OpCode::OpGetProperty(name), name_token.span);
+    }
+}
+
+fn this(compiler: &mut Compiler, _can_assign: bool) {
+    if compiler.classes.is_empty() {
+        compiler
+            .parser
+            .error("Can't use 'this' outside of a class.".to_string());
+        return;
+    }
+
+    // `this` is just a local living in slot zero, it can't be assigned to
+    variable(compiler, false);
+}
+
+fn super_(compiler: &mut Compiler, _can_assign: bool) {
+    let super_span = compiler.previous().span;
+    match compiler.classes.last() {
+        None => compiler
+            .parser
+            .error("Can't use 'super' outside of a class.".to_string()),
+        Some(class) if !class.has_superclass => compiler
+            .parser
+            .error("Can't use 'super' in a class with no superclass.".to_string()),
+        _ => {}
+    }
+
+    compiler.consume(TokenType::Dot, "Expect '.' after 'super'.");
+    compiler.consume(TokenType::Identifier, "Expect superclass method name.");
+    let name_token = compiler.previous().clone();
+    let name = compiler.identifier_constant(&name_token);
+
+    // the receiver goes first, then the superclass the method is looked up on
+    named_variable(compiler, &synthetic_token("this", super_span), false);
+    if compiler.match_token(TokenType::LeftParen) {
+        let arg_count = compiler.argument_list();
+        named_variable(compiler, &synthetic_token("super", super_span), false);
+        compiler.emit_byte_at(OpCode::OpSuperInvoke(name, arg_count), name_token.span);
+    } else {
+        named_variable(compiler, &synthetic_token("super", super_span), false);
+        compiler.emit_byte_at(OpCode::OpGetSuper(name), name_token.span);
+    }
+}
+
+fn unary(compiler: &mut Compiler, _can_assign: bool) {
+    let operator_type = compiler.previous().t_type.clone();
+    let operator_span = compiler.previous().span;
+
+    compiler.parse_precedence(Precedence::Unary);
+
+    match operator_type {
+        TokenType::Bang => compiler.emit_operator(OpCode::OpNot, operator_span),
+        TokenType::Minus => compiler.emit_operator(OpCode::OpNegate, operator_span),
+        _ => return,
+    }
+}
+
+fn binary(compiler: &mut Compiler, _can_assign: bool) {
+    let operator_type = compiler.previous().t_type.clone();
+    let span = compiler.previous().span;
+
+    let rule = get_rule(&operator_type);
+    compiler.parse_precedence(rule.precedence.next());
+
+    match operator_type {
+        TokenType::BangEqual => compiler.emit_operators(OpCode::OpEqual, OpCode::OpNot, span),
+        TokenType::EqualEqual => compiler.emit_operator(OpCode::OpEqual, span),
+        TokenType::Greater => compiler.emit_operator(OpCode::OpGreater, span),
+        TokenType::GreaterEqual => compiler.emit_operators(OpCode::OpLess, OpCode::OpNot, span),
+        TokenType::Less => compiler.emit_operator(OpCode::OpLess, span),
+        TokenType::LessEqual => compiler.emit_operators(OpCode::OpGreater, OpCode::OpNot, span),
+        TokenType::Plus => compiler.emit_operator(OpCode::OpAdd, span),
+        TokenType::Minus => compiler.emit_operator(OpCode::OpSubtract, span),
+        TokenType::Star => compiler.emit_operator(OpCode::OpMultiply, span),
+        TokenType::Slash => compiler.emit_operator(OpCode::OpDivide, span),
+        TokenType::SlashSlash => compiler.emit_operator(OpCode::OpFloorDivide, span),
+        TokenType::Percent => compiler.emit_operator(OpCode::OpModulo, span),
+
+        _ => return,
+    }
+}
+
+// the left operand is already on the stack, if it's falsey it is the result and the right side is skipped
+fn and(compiler: &mut Compiler, _can_assign: bool) {
+    let end_jump = compiler.emit_jump(OpCode::OpJumpIfFalse(0));
+
+    compiler.emit_byte(OpCode::OpPop);
+    compiler.parse_precedence(Precedence::And);
+
+    compiler.patch_jump(end_jump);
+}
+
+fn or(compiler: &mut Compiler, _can_assign: bool) {
+    let else_jump = compiler.emit_jump(OpCode::OpJumpIfFalse(0));
+    let end_jump = compiler.emit_jump(OpCode::OpJump(0));
+
+    compiler.patch_jump(else_jump);
+    compiler.emit_byte(OpCode::OpPop);
+
+    compiler.parse_precedence(Precedence::Or);
+    compiler.patch_jump(end_jump);
+}
+
+fn literal(compiler: &mut Compiler, _can_assign: bool) {
+    match compiler.previous().t_type {
+        TokenType::False => compiler.emit_byte(OpCode::OpFalse),
+        TokenType::Nil => compiler.emit_byte(OpCode::OpNil),
+        TokenType::True => compiler.emit_byte(OpCode::OpTrue),
+        _ => return,
+    }
+}
+
+// compiles the whole source into the implicit top level function, which is handed back as a heap object
+pub fn compile(source: String, vm: &mut VM) -> Result<ObjRef, Vec<CompileDiagnostic>> {
+    let mut compiler = Compiler::init(source, vm);
+
+    compiler.advance();
+    while !compiler.match_token(TokenType::Eof) {
+        compiler.declaration();
+    }
+
+    let function = compiler.end_compiler();
+    let diagnostics = std::mem::take(&mut compiler.parser.diagnostics);
+    let function = compiler.alloc(ObjectType::Function(function));
+
+    // nothing can collect between here and the vm pushing the script, so the roots can go
+    compiler.vm.compiler_roots.clear();
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+
+    return Ok(function);
+}
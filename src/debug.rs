@@ -1,62 +1,186 @@
-use crate::{
-    chunk::{Chunk, OpCode},
-    values::{print_value, ValueArray},
-};
-
-pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
-    println!("== {} ==", name);
-    let mut offset = 0;
-    for instruction in &chunk.code {
-        offset = disassemble_instruction(&chunk.lines, &chunk.constants, instruction, offset);
-    }
-}
-
-pub fn disassemble_instruction(
-    lines: &Vec<i32>,
-    constants: &ValueArray,
-    instruction: &OpCode,
-    offset: usize,
-) -> usize {
-    print!("{off:0>4} ", off = offset);
-
-    if offset > 0 && lines.get(offset) == lines.get(offset - 1) {
-        print!("   | ");
-    } else {
-        let line_option = lines.get(offset);
-        if line_option.is_some() {
-            let line = line_option.unwrap();
-            print!("{off:>4} ", off = line);
-        }
-    }
-
-    match instruction {
-        OpCode::OpReturn => println!("OP_RETURN"),
-        OpCode::OpNegate => println!("OP_NEGATE"),
-        OpCode::OpAdd => println!("OP_ADD"),
-        OpCode::OpSubtract => println!("OP_SUBTRACT"),
-        OpCode::OpMultiply => println!("OP_MULTIPLY"),
-        OpCode::OpDivide => println!("OP_DIVIDE"),
-        OpCode::OpNil => println!("OP_NIL"),
-        OpCode::OpTrue => println!("OP_TRUE"),
-        OpCode::OpFalse => println!("OP_FALSE"),
-        OpCode::OpNot => println!("OP_NOT"),
-        OpCode::OpEqual => println!("OP_EQUAL"),
-        OpCode::OpGreater => println!("OP_GREATER"),
-        OpCode::OpLess => println!("OP_LESS"),
-        OpCode::OpConstant(index) => {
-            print!(
-                "OP_CONSTANT {space:>16} {cnst} '",
-                space = " ",
-                cnst = index
-            );
-            print_value(constants.get(index));
-            println!("'");
-            // in the book this is + 2
-            // this is because they add the instruction to the array, then add the index of where the constant is after
-            // we wrap the index inside the constant, because rust has powerful enums
-            // a refactor to consider in the future is to wrap the value of the constant in the enum, and remove the constant array entirely
-            return offset + 1;
-        }
-    }
-    return offset + 1;
-}
+use crate::{
+    chunk::{Chunk, OpCode},
+    memory::Heap,
+    values::{print_value, ValueArray},
+};
+
+// constants are heap handles, so printing them needs the heap they live in
+pub fn disassemble_chunk(chunk: &Chunk, name: &str, heap: &Heap) {
+    println!("== {} ==", name);
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(chunk, offset, heap);
+    }
+}
+
+// decodes the instruction at offset and returns the offset of the next one
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize, heap: &Heap) -> usize {
+    print!("{off:0>4} ", off = offset);
+
+    let line = chunk.get_line(offset);
+    if offset > 0 && line == chunk.get_line(offset - 1) {
+        print!("   | ");
+    } else {
+        print!("{off:>4} ", off = line);
+    }
+
+    let constants = &chunk.constants;
+    let instruction = match chunk.read(offset) {
+        Some(instruction) => instruction,
+        None => {
+            println!("Unknown opcode {}", chunk.code[offset]);
+            return offset + 1;
+        }
+    };
+
+    match &instruction {
+        OpCode::OpReturn => println!("OP_RETURN"),
+        OpCode::OpNegate => println!("OP_NEGATE"),
+        OpCode::OpAdd => println!("OP_ADD"),
+        OpCode::OpSubtract => println!("OP_SUBTRACT"),
+        OpCode::OpMultiply => println!("OP_MULTIPLY"),
+        OpCode::OpDivide => println!("OP_DIVIDE"),
+        OpCode::OpFloorDivide => println!("OP_FLOOR_DIVIDE"),
+        OpCode::OpModulo => println!("OP_MODULO"),
+        OpCode::OpNil => println!("OP_NIL"),
+        OpCode::OpTrue => println!("OP_TRUE"),
+        OpCode::OpFalse => println!("OP_FALSE"),
+        OpCode::OpNot => println!("OP_NOT"),
+        OpCode::OpEqual => println!("OP_EQUAL"),
+        OpCode::OpGreater => println!("OP_GREATER"),
+        OpCode::OpLess => println!("OP_LESS"),
+        OpCode::OpPrint => println!("OP_PRINT"),
+        OpCode::OpStringify => println!("OP_STRINGIFY"),
+        OpCode::OpBuildList(count) => return long_instruction("OP_BUILD_LIST", count, offset),
+        OpCode::OpGetIndex => println!("OP_GET_INDEX"),
+        OpCode::OpSetIndex => println!("OP_SET_INDEX"),
+        OpCode::OpBuildMap(count) => return long_instruction("OP_BUILD_MAP", count, offset),
+        OpCode::OpPop => println!("OP_POP"),
+        OpCode::OpDefineGlobal(index) => {
+            return constant_instruction("OP_DEFINE_GLOBAL", constants, index, offset, heap)
+        }
+        OpCode::OpGetGlobal(index) => {
+            return constant_instruction("OP_GET_GLOBAL", constants, index, offset, heap)
+        }
+        OpCode::OpSetGlobal(index) => {
+            return constant_instruction("OP_SET_GLOBAL", constants, index, offset, heap)
+        }
+        OpCode::OpGetLocal(slot) => return byte_instruction("OP_GET_LOCAL", slot, offset),
+        OpCode::OpSetLocal(slot) => return byte_instruction("OP_SET_LOCAL", slot, offset),
+        OpCode::OpCall(arg_count) => return byte_instruction("OP_CALL", arg_count, offset),
+        OpCode::OpGetUpvalue(slot) => return byte_instruction("OP_GET_UPVALUE", slot, offset),
+        OpCode::OpSetUpvalue(slot) => return byte_instruction("OP_SET_UPVALUE", slot, offset),
+        OpCode::OpCloseUpvalue => println!("OP_CLOSE_UPVALUE"),
+        OpCode::OpClosure(index) => {
+            let function = constants.get(index);
+            print!("{name:<16} {cnst:>4} ", name = "OP_CLOSURE", cnst = index);
+            print_value(&function, heap);
+            println!();
+
+            // the book reads these as trailing bytes, ours live on the function prototype
+            if heap.is_function(&function) {
+                for upvalue in &heap.as_function(function.as_object()).upvalues {
+                    let kind = if upvalue.is_local { "local" } else { "upvalue" };
+                    println!(
+                        "{offset:0>4}    |                     {kind} {}",
+                        upvalue.index
+                    );
+                }
+            }
+            return offset + 3;
+        }
+        OpCode::OpClass(index) => {
+            return constant_instruction("OP_CLASS", constants, index, offset, heap)
+        }
+        OpCode::OpGetProperty(index) => {
+            return constant_instruction("OP_GET_PROPERTY", constants, index, offset, heap)
+        }
+        OpCode::OpSetProperty(index) => {
+            return constant_instruction("OP_SET_PROPERTY", constants, index, offset, heap)
+        }
+        OpCode::OpMethod(index) => {
+            return constant_instruction("OP_METHOD", constants, index, offset, heap)
+        }
+        OpCode::OpInvoke(index, arg_count) => {
+            return invoke_instruction("OP_INVOKE", constants, index, arg_count, offset, heap)
+        }
+        OpCode::OpInherit => println!("OP_INHERIT"),
+        OpCode::OpGetSuper(index) => {
+            return constant_instruction("OP_GET_SUPER", constants, index, offset, heap)
+        }
+        OpCode::OpSuperInvoke(index, arg_count) => {
+            return invoke_instruction("OP_SUPER_INVOKE", constants, index, arg_count, offset, heap)
+        }
+        OpCode::OpJump(jump) => return jump_instruction("OP_JUMP", 1, jump, offset),
+        OpCode::OpJumpIfFalse(jump) => {
+            return jump_instruction("OP_JUMP_IF_FALSE", 1, jump, offset)
+        }
+        OpCode::OpLoop(jump) => return jump_instruction("OP_LOOP", -1, jump, offset),
+        OpCode::OpConstant(index) => {
+            print!(
+                "OP_CONSTANT {space:>16} {cnst} '",
+                space = " ",
+                cnst = index
+            );
+            print_value(&constants.get(index), heap);
+            println!("'");
+            return offset + 2;
+        }
+        OpCode::OpConstantLong(index) => {
+            print!(
+                "{name:<16} {cnst:>4} '",
+                name = "OP_CONSTANT_LONG",
+                cnst = index
+            );
+            print_value(&constants.get(index), heap);
+            println!("'");
+            return offset + 4;
+        }
+    }
+    return offset + 1;
+}
+
+fn constant_instruction(
+    name: &str,
+    constants: &ValueArray,
+    index: &usize,
+    offset: usize,
+    heap: &Heap,
+) -> usize {
+    print!("{name:<16} {cnst:>4} '", cnst = index);
+    print_value(&constants.get(index), heap);
+    println!("'");
+    return offset + 3;
+}
+
+// operands that are plain numbers (stack slots, argument counts), there is no constant to print
+fn byte_instruction(name: &str, slot: &usize, offset: usize) -> usize {
+    println!("{name:<16} {slot:>4}");
+    return offset + 2;
+}
+
+fn long_instruction(name: &str, count: &usize, offset: usize) -> usize {
+    println!("{name:<16} {count:>4}");
+    return offset + 4;
+}
+
+// jumps are measured from the end of the jump instruction
+fn jump_instruction(name: &str, sign: i64, jump: &usize, offset: usize) -> usize {
+    let target = offset as i64 + 3 + sign * *jump as i64;
+    println!("{name:<16} {offset:>4} -> {target}");
+    return offset + 3;
+}
+
+fn invoke_instruction(
+    name: &str,
+    constants: &ValueArray,
+    index: &usize,
+    arg_count: &usize,
+    offset: usize,
+    heap: &Heap,
+) -> usize {
+    print!("{name:<16} ({arg_count} args) {cnst:>4} '", cnst = index);
+    print_value(&constants.get(index), heap);
+    println!("'");
+    return offset + 4;
+}
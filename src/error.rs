@@ -0,0 +1,63 @@
+// what the interpreter hands back instead of printing, main decides how to show it
+
+use crate::scanner::{Span, Token, TokenType};
+
+// the token a compile error points at
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorToken {
+    End,
+    Lexeme(String),
+    // nothing to quote, the scanner couldn't make a token or the error came from a later pass
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileDiagnostic {
+    pub message: String,
+    pub line: i32,
+    // the offending token, for underlining it in the source
+    pub span: Span,
+    pub token: ErrorToken,
+}
+
+impl CompileDiagnostic {
+    // an error pointing at a token straight from the scanner
+    pub fn at(token: &Token, message: String) -> Self {
+        let error_token = if token.t_type == TokenType::Eof {
+            ErrorToken::End
+        } else if token.t_type == TokenType::Error {
+            ErrorToken::Invalid
+        } else {
+            ErrorToken::Lexeme(token.content.clone())
+        };
+
+        CompileDiagnostic {
+            message,
+            line: token.line,
+            span: token.span,
+            token: error_token,
+        }
+    }
+}
+
+// one call frame in a runtime error's trace, function is None for the top level script
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    // the code that was running in this frame, for the innermost one that's what failed
+    pub span: Span,
+    pub function: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    // innermost call first
+    pub trace: Vec<TraceFrame>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoxError {
+    // every error the compiler reported, in source order
+    CompileError(Vec<CompileDiagnostic>),
+    RuntimeError(RuntimeError),
+}
@@ -0,0 +1,253 @@
+use crate::{
+    ast::{BinaryOp, Class, Expr, ExprKind, Function, Literal, Program, Stmt, StmtKind, UnaryOp},
+    number::{self, Operation},
+    scanner::Span,
+    values::Value,
+};
+
+// constant folding on the tree, the ast pipeline's version of the peephole in compiler.rs
+// it folds exactly what the peephole would so both pipelines produce the same code,
+// anything that would be a runtime error is left for the vm to report
+
+fn is_falsey(literal: &Literal) -> bool {
+    return matches!(literal, Literal::Nil | Literal::Bool(false));
+}
+
+// numbers go through number.rs as values, the same arithmetic the vm does
+fn number_value(literal: &Literal) -> Option<Value> {
+    match literal {
+        Literal::Number(value) => return Some(Value::from_number(*value)),
+        Literal::Int(value) => return Some(Value::from_int(*value)),
+        _ => return None,
+    }
+}
+
+fn value_literal(value: Value) -> Literal {
+    if value.is_bool() {
+        return Literal::Bool(value.as_bool());
+    } else if value.is_int() {
+        return Literal::Int(value.as_int());
+    }
+    return Literal::Number(value.as_number());
+}
+
+fn unary(op: UnaryOp, operand: &Literal) -> Option<Literal> {
+    match op {
+        UnaryOp::Negate => {
+            let negated = number::negate(number_value(operand)?).ok()?;
+            return Some(value_literal(negated));
+        }
+        UnaryOp::Not => return Some(Literal::Bool(is_falsey(operand))),
+    }
+}
+
+fn binary(op: BinaryOp, left: &Literal, right: &Literal) -> Option<Literal> {
+    // the compiler emits these as the opposite comparison followed by a not
+    let negated = match op {
+        BinaryOp::NotEqual => Some(BinaryOp::Equal),
+        BinaryOp::GreaterEqual => Some(BinaryOp::Less),
+        BinaryOp::LessEqual => Some(BinaryOp::Greater),
+        _ => None,
+    };
+    if let Some(op) = negated {
+        let result = binary(op, left, right)?;
+        return unary(UnaryOp::Not, &result);
+    }
+
+    let numbers = number_value(left).zip(number_value(right));
+    match (op, left, right) {
+        (BinaryOp::Equal, left, right) => match numbers {
+            Some((a, b)) => return Some(Literal::Bool(number::equal(a, b))),
+            None => return Some(Literal::Bool(left == right)),
+        },
+        (BinaryOp::Add, Literal::String(a), Literal::String(b)) => {
+            return Some(Literal::String(a.clone() + b))
+        }
+        _ => {
+            let (a, b) = numbers?;
+            let operation = match op {
+                BinaryOp::Add => Operation::Plus,
+                BinaryOp::Subtract => Operation::Minus,
+                BinaryOp::Multiply => Operation::Star,
+                BinaryOp::Divide => Operation::Div,
+                BinaryOp::FloorDivide => Operation::FloorDiv,
+                BinaryOp::Modulo => Operation::Modulo,
+                BinaryOp::Greater => Operation::Greater,
+                BinaryOp::Less => Operation::Less,
+                _ => return None,
+            };
+            // overflow and division by zero are left for the vm to report
+            let result = number::binary(operation, a, b).ok()?;
+            return Some(value_literal(result));
+        }
+    }
+}
+
+fn literal_of(expression: &Expr) -> Option<&Literal> {
+    match &expression.kind {
+        ExprKind::Literal(literal) => return Some(literal),
+        _ => return None,
+    }
+}
+
+// the last token of an expression that might fold, the single pass compiler emits a folded value
+// once it has parsed the operands, so that's the span it gets
+fn end_span(expression: &Expr) -> Span {
+    match &expression.kind {
+        ExprKind::Unary { operand, .. } => return end_span(operand),
+        ExprKind::Binary { right, .. } => return end_span(right),
+        _ => return expression.span,
+    }
+}
+
+fn expression(expression: &mut Expr) {
+    let folded = match &mut expression.kind {
+        ExprKind::Literal(_)
+        | ExprKind::Variable(_)
+        | ExprKind::This(_)
+        | ExprKind::Super { .. } => None,
+        // parentheses only matter to the parser, but `(a.b)(c)` must not become an invoke so only literals lose them
+        ExprKind::Grouping(inner) => {
+            self::expression(inner);
+            literal_of(inner)
+                .cloned()
+                .map(|literal| (literal, inner.span))
+        }
+        ExprKind::Unary { op, operand } => {
+            let end = end_span(operand);
+            self::expression(operand);
+            literal_of(operand)
+                .and_then(|literal| unary(*op, literal))
+                .map(|literal| (literal, end))
+        }
+        ExprKind::Binary { op, left, right } => {
+            let end = end_span(right);
+            self::expression(left);
+            self::expression(right);
+            match (literal_of(left), literal_of(right)) {
+                (Some(a), Some(b)) => binary(*op, a, b).map(|literal| (literal, end)),
+                _ => None,
+            }
+        }
+        ExprKind::Logical { left, right, .. } => {
+            self::expression(left);
+            self::expression(right);
+            None
+        }
+        ExprKind::Assign { value, .. } => {
+            self::expression(value);
+            None
+        }
+        ExprKind::Call { callee, arguments } => {
+            self::expression(callee);
+            arguments.iter_mut().for_each(self::expression);
+            None
+        }
+        ExprKind::Get { object, .. } => {
+            self::expression(object);
+            None
+        }
+        ExprKind::Set { object, value, .. } => {
+            self::expression(object);
+            self::expression(value);
+            None
+        }
+        ExprKind::List(items) => {
+            items.iter_mut().for_each(self::expression);
+            None
+        }
+        ExprKind::Map(entries) => {
+            for (key, value) in entries.iter_mut() {
+                self::expression(key);
+                self::expression(value);
+            }
+            None
+        }
+        ExprKind::Index { object, index } => {
+            self::expression(object);
+            self::expression(index);
+            None
+        }
+        ExprKind::SetIndex {
+            object,
+            index,
+            value,
+        } => {
+            self::expression(object);
+            self::expression(index);
+            self::expression(value);
+            None
+        }
+        // the stringify between each expression and the concatenation is never folded
+        ExprKind::Interpolation { expressions, .. } => {
+            expressions.iter_mut().for_each(self::expression);
+            None
+        }
+    };
+
+    // the folded value points at the last token of its operands, which is where the compiler would have emitted it
+    if let Some((literal, span)) = folded {
+        *expression = Expr {
+            kind: ExprKind::Literal(literal),
+            span,
+        };
+    }
+}
+
+fn function(function: &mut Function) {
+    function.body.iter_mut().for_each(statement);
+}
+
+fn class(class: &mut Class) {
+    class.methods.iter_mut().for_each(function);
+}
+
+fn statement(statement: &mut Stmt) {
+    match &mut statement.kind {
+        StmtKind::Expression(value) | StmtKind::Print(value) => expression(value),
+        StmtKind::Var { initializer, .. } => initializer.iter_mut().for_each(expression),
+        StmtKind::Block { statements, .. } => statements.iter_mut().for_each(self::statement),
+        StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            expression(condition);
+            self::statement(then_branch);
+            else_branch
+                .iter_mut()
+                .for_each(|branch| self::statement(branch));
+        }
+        StmtKind::While {
+            condition, body, ..
+        } => {
+            expression(condition);
+            self::statement(body);
+        }
+        StmtKind::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => {
+            initializer
+                .iter_mut()
+                .for_each(|init| self::statement(init));
+            condition.iter_mut().for_each(expression);
+            increment
+                .iter_mut()
+                .for_each(|increment| expression(increment));
+            self::statement(body);
+        }
+        StmtKind::Return { value, .. } => value.iter_mut().for_each(expression),
+        StmtKind::Fun { function, .. } => self::function(function),
+        StmtKind::Class(class) => self::class(class),
+    }
+}
+
+// replaces operations on literals with their result, working bottom up so whole literal subtrees collapse
+pub fn fold(program: &mut Program) {
+    program.statements.iter_mut().for_each(statement);
+}
@@ -0,0 +1,30 @@
+#![allow(dead_code)]
+#![allow(clippy::needless_return)]
+#![allow(clippy::enum_variant_names)]
+
+// the interpreter lives in the library so it can be embedded, main.rs is just the command line around it
+pub mod ast;
+pub mod chunk;
+pub mod compiler;
+pub mod debug;
+pub mod error;
+pub mod fold;
+pub mod list;
+pub mod lower;
+pub mod map;
+pub mod memory;
+pub mod number;
+pub mod parser;
+pub mod resolver;
+pub mod scanner;
+pub mod serialize;
+pub mod table;
+pub mod values;
+pub mod vm;
+
+pub const DEBUG_PRINT: bool = true;
+// collect before every allocation, shakes out objects we forgot to root
+pub const DEBUG_STRESS_GC: bool = false;
+pub const DEBUG_LOG_GC: bool = false;
+// emit arithmetic on literals as written instead of folding it, handy when reading disassembly
+pub const DEBUG_NO_FOLDING: bool = false;
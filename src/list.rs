@@ -0,0 +1,100 @@
+use crate::{
+    values::{NativeFn, ObjList, ObjectType, Value},
+    vm::VM,
+};
+
+// the methods lists answer to, each native gets the list itself ahead of the call's arguments
+pub const LIST_METHODS: [(&str, usize, NativeFn); 6] = [
+    ("len", 0, len_native),
+    ("push", 1, push_native),
+    ("pop", 0, pop_native),
+    ("insert", 2, insert_native),
+    ("remove", 1, remove_native),
+    ("slice", 2, slice_native),
+];
+
+// turns a lox index into a position in a list of the given length, negative indices count from the end
+// `allow_end` lets the index land one past the last item, for inserting
+pub fn list_index(index: Value, length: usize, allow_end: bool) -> Result<usize, String> {
+    if !index.is_int() {
+        return Err("List index must be an integer.".to_string());
+    }
+
+    let index = index.as_int();
+    let position = if index < 0 {
+        index + length as i64
+    } else {
+        index
+    };
+
+    let limit = if allow_end { length + 1 } else { length };
+    if position < 0 || position >= limit as i64 {
+        return Err(format!(
+            "List index {} out of range for a list of length {}.",
+            index, length
+        ));
+    }
+
+    return Ok(position as usize);
+}
+
+// slice bounds are clamped to the list instead of failing, like most languages with slices
+fn slice_bound(bound: Value, length: usize) -> Result<usize, String> {
+    if !bound.is_int() {
+        return Err("Slice bounds must be integers.".to_string());
+    }
+
+    let bound = bound.as_int();
+    let position = if bound < 0 {
+        bound + length as i64
+    } else {
+        bound
+    };
+
+    return Ok(position.clamp(0, length as i64) as usize);
+}
+
+fn items(vm: &mut VM, list: Value) -> &mut Vec<Value> {
+    return &mut vm.heap.as_list_mut(list.as_object()).items;
+}
+
+fn len_native(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    return Ok(Value::from_int(items(vm, args[0]).len() as i64));
+}
+
+fn push_native(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    items(vm, args[0]).push(args[1]);
+    return Ok(Value::from_nil());
+}
+
+fn pop_native(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    match items(vm, args[0]).pop() {
+        Some(item) => return Ok(item),
+        None => return Err("Can't pop from an empty list.".to_string()),
+    }
+}
+
+fn insert_native(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let items = items(vm, args[0]);
+    let position = list_index(args[1], items.len(), true)?;
+    items.insert(position, args[2]);
+    return Ok(Value::from_nil());
+}
+
+fn remove_native(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let items = items(vm, args[0]);
+    let position = list_index(args[1], items.len(), false)?;
+    return Ok(items.remove(position));
+}
+
+// a new list with the items from start up to but not including end
+fn slice_native(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let items = items(vm, args[0]);
+    let start = slice_bound(args[1], items.len())?;
+    let end = slice_bound(args[2], items.len())?;
+
+    let slice = items[start..end.max(start)].to_vec();
+    // the items are still reachable through the original list while the new one is allocated
+    let list = vm.alloc(ObjectType::List(ObjList::init(slice)));
+    return Ok(Value::from_object(list));
+}
@@ -0,0 +1,588 @@
+use crate::{
+    ast::{
+        BinaryOp, Class, Expr, ExprKind, Function, FunctionType, Literal, LogicalOp, Name,
+        Resolution, Stmt, StmtKind, UnaryOp, Variable,
+    },
+    chunk::{Chunk, OpCode, BYTE_OPERAND_MAX, LONG_OPERAND_MAX, SHORT_OPERAND_MAX},
+    debug::disassemble_chunk,
+    error::{CompileDiagnostic, ErrorToken},
+    fold::fold,
+    parser::parse,
+    resolver::resolve,
+    scanner::Span,
+    values::{ObjFunction, ObjRef, ObjectType, Value},
+    vm::VM,
+    DEBUG_NO_FOLDING, DEBUG_PRINT,
+};
+
+// the back half of the multi pass pipeline, turns a resolved tree into the same bytecode compiler.rs emits
+// instructions, constants and their order all match, so the two pipelines can be diffed against each other
+
+struct FunctionState {
+    function: ObjFunction,
+    function_type: FunctionType,
+}
+
+pub struct Lowerer<'a> {
+    vm: &'a mut VM,
+    states: Vec<FunctionState>,
+    // only limits can go wrong this late, like too many constants or too long a jump
+    diagnostics: Vec<CompileDiagnostic>,
+}
+
+impl<'a> Lowerer<'a> {
+    fn init(vm: &'a mut VM) -> Self {
+        Lowerer {
+            vm,
+            states: vec![FunctionState {
+                function: ObjFunction::init(None),
+                function_type: FunctionType::Script,
+            }],
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn current(&self) -> &FunctionState {
+        return self.states.last().unwrap();
+    }
+
+    fn current_chunk(&mut self) -> &mut Chunk {
+        return &mut self.states.last_mut().unwrap().function.chunk;
+    }
+
+    fn error(&mut self, span: Span, message: &str) {
+        self.diagnostics.push(CompileDiagnostic {
+            message: message.to_string(),
+            line: span.line,
+            span,
+            token: ErrorToken::Invalid,
+        });
+    }
+
+    // same rooting as the single pass compiler, nothing allocated here is reachable until compile returns
+    fn alloc(&mut self, object: ObjectType) -> ObjRef {
+        let reference = self.vm.alloc(object);
+        self.vm.compiler_roots.push(reference);
+        return reference;
+    }
+
+    fn alloc_string(&mut self, content: String) -> Value {
+        let reference = self.vm.alloc_string(content);
+        self.vm.compiler_roots.push(reference);
+        return Value::from_object(reference);
+    }
+
+    fn make_constant(&mut self, value: Value, span: Span) -> usize {
+        let constant = self.current_chunk().add_constant(value);
+        if constant > SHORT_OPERAND_MAX {
+            self.error(span, "Too many constants in one chunk.");
+            return 0;
+        }
+
+        return constant;
+    }
+
+    fn identifier_constant(&mut self, name: &Name) -> usize {
+        let value = self.alloc_string(name.lexeme.clone());
+        return self.make_constant(value, name.span);
+    }
+
+    fn emit(&mut self, instruction: OpCode, span: Span) {
+        self.current_chunk().write(instruction, span);
+    }
+
+    fn emit_constant(&mut self, value: Value, span: Span) {
+        let constant = self.current_chunk().add_constant(value);
+        if constant <= BYTE_OPERAND_MAX {
+            self.emit(OpCode::OpConstant(constant), span);
+        } else if constant <= LONG_OPERAND_MAX {
+            self.emit(OpCode::OpConstantLong(constant), span);
+        } else {
+            self.error(span, "Too many constants in one chunk.");
+        }
+    }
+
+    fn emit_jump(&mut self, instruction: OpCode, span: Span) -> usize {
+        let offset = self.current_chunk().code.len();
+        self.emit(instruction, span);
+        return offset;
+    }
+
+    fn patch_jump(&mut self, offset: usize, span: Span) {
+        let instruction = self.current_chunk().read(offset).unwrap();
+        let jump = self.current_chunk().code.len() - offset - instruction.size();
+        if jump > SHORT_OPERAND_MAX {
+            self.error(span, "Too much code to jump over.");
+        }
+
+        let patched = match instruction {
+            OpCode::OpJump(_) => OpCode::OpJump(jump),
+            OpCode::OpJumpIfFalse(_) => OpCode::OpJumpIfFalse(jump),
+            _ => panic!("tried to patch an instruction that isn't a jump"),
+        };
+        self.current_chunk().patch(offset, patched);
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, span: Span) {
+        let offset = self.current_chunk().code.len() - loop_start + OpCode::OpLoop(0).size();
+        if offset > SHORT_OPERAND_MAX {
+            self.error(span, "Loop body too large.");
+        }
+
+        self.emit(OpCode::OpLoop(offset), span);
+    }
+
+    fn emit_return(&mut self, span: Span) {
+        if self.current().function_type == FunctionType::Initializer {
+            self.emit(OpCode::OpGetLocal(0), span);
+        } else {
+            self.emit(OpCode::OpNil, span);
+        }
+        self.emit(OpCode::OpReturn, span);
+    }
+
+    fn end_function(&mut self, span: Span) -> ObjFunction {
+        self.emit_return(span);
+        let state = self.states.pop().unwrap();
+
+        if DEBUG_PRINT && self.diagnostics.is_empty() {
+            disassemble_chunk(
+                &state.function.chunk,
+                &state.function.to_string(),
+                &self.vm.heap,
+            );
+        }
+
+        return state.function;
+    }
+
+    // globals need their name constant made before anything else, so it comes first in the pool like in compiler.rs
+    fn variable_ops(&mut self, variable: &Variable) -> (OpCode, OpCode) {
+        match variable.resolution {
+            Resolution::Local(slot) => return (OpCode::OpGetLocal(slot), OpCode::OpSetLocal(slot)),
+            Resolution::Upvalue(index) => {
+                return (OpCode::OpGetUpvalue(index), OpCode::OpSetUpvalue(index))
+            }
+            Resolution::Global | Resolution::Unresolved => {
+                let constant = self.identifier_constant(&variable.name);
+                return (OpCode::OpGetGlobal(constant), OpCode::OpSetGlobal(constant));
+            }
+        }
+    }
+
+    fn load(&mut self, variable: &Variable) {
+        let (get_op, _) = self.variable_ops(variable);
+        self.emit(get_op, variable.name.span);
+    }
+
+    // the global's name constant, made up front like parse_variable does, locals don't need one
+    fn declare(&mut self, variable: &Variable) -> usize {
+        if variable.resolution == Resolution::Global {
+            return self.identifier_constant(&variable.name);
+        }
+        return 0;
+    }
+
+    fn define(&mut self, variable: &Variable, global: usize, span: Span) {
+        if variable.resolution == Resolution::Global {
+            self.emit(OpCode::OpDefineGlobal(global), span);
+        }
+    }
+
+    fn end_scope(&mut self, captured: &[bool], span: Span) {
+        for is_captured in captured.iter().rev() {
+            if *is_captured {
+                self.emit(OpCode::OpCloseUpvalue, span);
+            } else {
+                self.emit(OpCode::OpPop, span);
+            }
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        let mut compiled = ObjFunction::init(Some(function.name.lexeme.clone()));
+        compiled.arity = function.params.len();
+        self.states.push(FunctionState {
+            function: compiled,
+            function_type: function.function_type,
+        });
+
+        for statement in &function.body {
+            self.statement(statement);
+        }
+
+        let mut compiled = self.end_function(function.end);
+        compiled.upvalues = function.upvalues.clone();
+        let compiled = self.alloc(ObjectType::Function(compiled));
+        let constant = self.make_constant(Value::from_object(compiled), function.end);
+        self.emit(OpCode::OpClosure(constant), function.end);
+    }
+
+    fn class(&mut self, class: &Class, span: Span) {
+        let name_span = class.name.name.span;
+        let name_constant = self.identifier_constant(&class.name.name);
+        self.emit(OpCode::OpClass(name_constant), name_span);
+        self.define(&class.name, name_constant, name_span);
+
+        if let Some(superclass) = &class.superclass {
+            self.load(superclass);
+            self.load(&class.name);
+            self.emit(OpCode::OpInherit, superclass.name.span);
+        }
+
+        // OpMethod expects the class right below each method
+        self.load(&class.name);
+        for method in &class.methods {
+            let constant = self.identifier_constant(&method.name);
+            self.function(method);
+            self.emit(OpCode::OpMethod(constant), method.end);
+        }
+        self.emit(OpCode::OpPop, span);
+
+        if class.superclass.is_some() {
+            self.end_scope(&[class.super_captured], span);
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        let span = statement.span;
+        match &statement.kind {
+            StmtKind::Expression(expression) => {
+                self.expression(expression);
+                self.emit(OpCode::OpPop, span);
+            }
+            StmtKind::Print(expression) => {
+                self.expression(expression);
+                self.emit(OpCode::OpPrint, span);
+            }
+            StmtKind::Var { name, initializer } => {
+                let global = self.declare(name);
+                match initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => self.emit(OpCode::OpNil, name.name.span),
+                }
+                self.define(name, global, span);
+            }
+            StmtKind::Block {
+                statements,
+                captured,
+            } => {
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.end_scope(captured, span);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+                condition_end,
+            } => {
+                self.expression(condition);
+                let then_jump = self.emit_jump(OpCode::OpJumpIfFalse(0), *condition_end);
+                self.emit(OpCode::OpPop, *condition_end);
+                self.statement(then_branch);
+
+                let else_jump = self.emit_jump(OpCode::OpJump(0), then_branch.span);
+                self.patch_jump(then_jump, then_branch.span);
+                self.emit(OpCode::OpPop, then_branch.span);
+
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump, span);
+            }
+            StmtKind::While {
+                condition,
+                body,
+                condition_end,
+            } => {
+                let loop_start = self.current_chunk().code.len();
+                self.expression(condition);
+
+                let exit_jump = self.emit_jump(OpCode::OpJumpIfFalse(0), *condition_end);
+                self.emit(OpCode::OpPop, *condition_end);
+                self.statement(body);
+                self.emit_loop(loop_start, body.span);
+
+                self.patch_jump(exit_jump, body.span);
+                self.emit(OpCode::OpPop, body.span);
+            }
+            StmtKind::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                captured,
+                condition_end,
+                increment_end,
+                clauses_end,
+            } => {
+                if let Some(initializer) = initializer {
+                    self.statement(initializer);
+                }
+
+                let mut loop_start = self.current_chunk().code.len();
+                let mut exit_jump = None;
+                if let Some(condition) = condition {
+                    self.expression(condition);
+                    exit_jump = Some(self.emit_jump(OpCode::OpJumpIfFalse(0), *condition_end));
+                    self.emit(OpCode::OpPop, *condition_end);
+                }
+
+                if let Some(increment) = increment {
+                    let body_jump = self.emit_jump(OpCode::OpJump(0), *condition_end);
+                    let increment_start = self.current_chunk().code.len();
+                    self.expression(increment);
+                    self.emit(OpCode::OpPop, *increment_end);
+
+                    self.emit_loop(loop_start, *clauses_end);
+                    loop_start = increment_start;
+                    self.patch_jump(body_jump, *clauses_end);
+                }
+
+                self.statement(body);
+                self.emit_loop(loop_start, body.span);
+
+                if let Some(exit_jump) = exit_jump {
+                    self.patch_jump(exit_jump, body.span);
+                    self.emit(OpCode::OpPop, body.span);
+                }
+
+                self.end_scope(captured, span);
+            }
+            StmtKind::Return { value, .. } => match value {
+                Some(value) => {
+                    self.expression(value);
+                    self.emit(OpCode::OpReturn, span);
+                }
+                None => self.emit_return(span),
+            },
+            StmtKind::Fun { name, function } => {
+                let global = self.declare(name);
+                self.function(function);
+                self.define(name, global, span);
+            }
+            StmtKind::Class(class) => self.class(class, span),
+        }
+    }
+
+    fn expression(&mut self, expression: &Expr) {
+        let span = expression.span;
+        match &expression.kind {
+            ExprKind::Literal(literal) => match literal {
+                Literal::Nil => self.emit(OpCode::OpNil, span),
+                Literal::Bool(true) => self.emit(OpCode::OpTrue, span),
+                Literal::Bool(false) => self.emit(OpCode::OpFalse, span),
+                Literal::Number(value) => self.emit_constant(Value::from_number(*value), span),
+                Literal::Int(value) => self.emit_constant(Value::from_int(*value), span),
+                Literal::String(content) => {
+                    let value = self.alloc_string(content.clone());
+                    self.emit_constant(value, span);
+                }
+            },
+            ExprKind::Grouping(inner) => self.expression(inner),
+            ExprKind::Unary { op, operand } => {
+                self.expression(operand);
+                match op {
+                    UnaryOp::Negate => self.emit(OpCode::OpNegate, span),
+                    UnaryOp::Not => self.emit(OpCode::OpNot, span),
+                }
+            }
+            ExprKind::Binary { op, left, right } => {
+                self.expression(left);
+                self.expression(right);
+
+                let (instruction, negate) = match op {
+                    BinaryOp::Add => (OpCode::OpAdd, false),
+                    BinaryOp::Subtract => (OpCode::OpSubtract, false),
+                    BinaryOp::Multiply => (OpCode::OpMultiply, false),
+                    BinaryOp::Divide => (OpCode::OpDivide, false),
+                    BinaryOp::FloorDivide => (OpCode::OpFloorDivide, false),
+                    BinaryOp::Modulo => (OpCode::OpModulo, false),
+                    BinaryOp::Equal => (OpCode::OpEqual, false),
+                    BinaryOp::NotEqual => (OpCode::OpEqual, true),
+                    BinaryOp::Greater => (OpCode::OpGreater, false),
+                    BinaryOp::GreaterEqual => (OpCode::OpLess, true),
+                    BinaryOp::Less => (OpCode::OpLess, false),
+                    BinaryOp::LessEqual => (OpCode::OpGreater, true),
+                };
+                self.emit(instruction, span);
+                if negate {
+                    self.emit(OpCode::OpNot, span);
+                }
+            }
+            ExprKind::Logical {
+                op: LogicalOp::And,
+                left,
+                right,
+            } => {
+                self.expression(left);
+                let end_jump = self.emit_jump(OpCode::OpJumpIfFalse(0), span);
+                self.emit(OpCode::OpPop, span);
+                self.expression(right);
+                self.patch_jump(end_jump, span);
+            }
+            ExprKind::Logical {
+                op: LogicalOp::Or,
+                left,
+                right,
+            } => {
+                self.expression(left);
+                let else_jump = self.emit_jump(OpCode::OpJumpIfFalse(0), span);
+                let end_jump = self.emit_jump(OpCode::OpJump(0), span);
+                self.patch_jump(else_jump, span);
+                self.emit(OpCode::OpPop, span);
+                self.expression(right);
+                self.patch_jump(end_jump, span);
+            }
+            ExprKind::Variable(variable) | ExprKind::This(variable) => self.load(variable),
+            ExprKind::Assign { target, value } => {
+                let (_, set_op) = self.variable_ops(target);
+                self.expression(value);
+                self.emit(set_op, target.name.span);
+            }
+            ExprKind::Call { callee, arguments } => self.call(callee, arguments, span),
+            ExprKind::Interpolation {
+                segments,
+                expressions,
+            } => self.interpolation(segments, expressions),
+            ExprKind::List(items) => {
+                items.iter().for_each(|item| self.expression(item));
+                self.emit(OpCode::OpBuildList(items.len()), span);
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                self.emit(OpCode::OpBuildMap(entries.len()), span);
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+                self.emit(OpCode::OpGetIndex, span);
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                self.emit(OpCode::OpSetIndex, span);
+            }
+            ExprKind::Get { object, name } => {
+                self.expression(object);
+                let constant = self.identifier_constant(name);
+                self.emit(OpCode::OpGetProperty(constant), name.span);
+            }
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object);
+                let constant = self.identifier_constant(name);
+                self.expression(value);
+                self.emit(OpCode::OpSetProperty(constant), name.span);
+            }
+            ExprKind::Super {
+                this,
+                superclass,
+                method,
+            } => {
+                let constant = self.identifier_constant(method);
+                self.load(this);
+                self.load(superclass);
+                self.emit(OpCode::OpGetSuper(constant), method.span);
+            }
+        }
+    }
+
+    // empty segments are skipped and every expression is stringified, like compiler.rs does
+    fn interpolation(&mut self, segments: &[Expr], expressions: &[Expr]) {
+        let mut first = true;
+        for (index, segment) in segments.iter().enumerate() {
+            if segment.kind != ExprKind::Literal(Literal::String(String::new())) {
+                self.expression(segment);
+                if !first {
+                    self.emit(OpCode::OpAdd, segment.span);
+                }
+                first = false;
+            }
+
+            if let Some(expression) = expressions.get(index) {
+                self.expression(expression);
+                self.emit(OpCode::OpStringify, segment.span);
+                if !first {
+                    self.emit(OpCode::OpAdd, segment.span);
+                }
+                first = false;
+            }
+        }
+    }
+
+    // calling a property or a superclass method straight away skips creating a bound method
+    fn call(&mut self, callee: &Expr, arguments: &[Expr], span: Span) {
+        match &callee.kind {
+            ExprKind::Get { object, name } => {
+                self.expression(object);
+                let constant = self.identifier_constant(name);
+                arguments
+                    .iter()
+                    .for_each(|argument| self.expression(argument));
+                self.emit(OpCode::OpInvoke(constant, arguments.len()), name.span);
+            }
+            ExprKind::Super {
+                this,
+                superclass,
+                method,
+            } => {
+                let constant = self.identifier_constant(method);
+                self.load(this);
+                arguments
+                    .iter()
+                    .for_each(|argument| self.expression(argument));
+                self.load(superclass);
+                self.emit(
+                    OpCode::OpSuperInvoke(constant, arguments.len()),
+                    method.span,
+                );
+            }
+            _ => {
+                self.expression(callee);
+                arguments
+                    .iter()
+                    .for_each(|argument| self.expression(argument));
+                self.emit(OpCode::OpCall(arguments.len()), span);
+            }
+        }
+    }
+}
+
+// the multi pass counterpart of compiler::compile, each pass stops the pipeline if it reports anything
+pub fn compile(source: String, vm: &mut VM) -> Result<ObjRef, Vec<CompileDiagnostic>> {
+    let mut program = parse(source)?;
+    resolve(&mut program)?;
+    if !DEBUG_NO_FOLDING {
+        fold(&mut program);
+    }
+
+    let mut lowerer = Lowerer::init(vm);
+    for statement in &program.statements {
+        lowerer.statement(statement);
+    }
+
+    let function = lowerer.end_function(program.end);
+    let diagnostics = std::mem::take(&mut lowerer.diagnostics);
+    let function = lowerer.alloc(ObjectType::Function(function));
+
+    lowerer.vm.compiler_roots.clear();
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+
+    return Ok(function);
+}
@@ -1,13 +1,7 @@
-#![allow(dead_code)]
-
-mod chunk;
-mod compiler;
-mod debug;
-mod scanner;
-mod values;
-mod vm;
+#![allow(clippy::needless_return)]
 
 use std::env;
+use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::BufRead;
@@ -15,16 +9,23 @@ use std::io::Read;
 use std::io::Result;
 use std::process::exit;
 
-use vm::VM;
-
-const DEBUG_PRINT: bool = true;
+use rustlox::ast;
+use rustlox::chunk::Chunk;
+use rustlox::error::{ErrorToken, LoxError};
+use rustlox::parser::parse;
+use rustlox::resolver::resolve;
+use rustlox::scanner::Span;
+use rustlox::vm::{self, VM};
 
 fn repl(mut vm: VM) -> Result<()> {
     print!("> ");
     let stdin = io::stdin();
 
     for line in stdin.lock().lines() {
-        vm.interpret(line? + "\n");
+        let source = line? + "\n";
+        if let Err(error) = vm.interpret(source.clone()) {
+            report_error(&error, Some(&source));
+        }
         print!("> ");
     }
 
@@ -37,30 +38,171 @@ fn run_file(mut vm: VM, file_name: &str) -> Result<()> {
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
 
-    let interpret_result = vm.interpret(contents);
-    match interpret_result {
-        vm::InterpretResult::InterpretOk => Ok(()),
-        vm::InterpretResult::InterpretCompileError => {
-            eprintln!("{}", "Compile Time Error");
+    let interpret_result = vm.interpret(contents.clone());
+    return exit_on_error(interpret_result, Some(&contents));
+}
+
+// compiles a script to a .loxc file without running it
+fn compile_file(mut vm: VM, input: &str, output: &str) -> Result<()> {
+    let contents = fs::read_to_string(input)?;
+
+    match vm.compile_bytecode(contents.clone()) {
+        Ok(bytes) => fs::write(output, bytes),
+        Err(error) => {
+            report_error(&error, Some(&contents));
+            eprintln!("Compile Time Error");
             exit(65);
         }
-        vm::InterpretResult::InterpretRuntimeError => {
-            eprintln!("{}", "Runtime Time Error");
-            exit(70);
+    }
+}
+
+// runs a .loxc file, skipping the scanner and compiler entirely
+fn run_bytecode(mut vm: VM, file_name: &str) -> Result<()> {
+    let bytes = fs::read(file_name)?;
+
+    let chunk = match Chunk::deserialize(&bytes, &mut vm) {
+        Ok(chunk) => chunk,
+        Err(message) => {
+            eprintln!("{file_name}: {message}");
+            exit(65);
+        }
+    };
+
+    // there's no source to quote, errors only get their line numbers
+    let interpret_result = vm.interpret_chunk(chunk);
+    return exit_on_error(interpret_result, None);
+}
+
+// parses and resolves a script without compiling it, printing the tree the ast pipeline would lower
+fn dump_ast(file_name: &str) -> Result<()> {
+    let contents = fs::read_to_string(file_name)?;
+
+    let result = parse(contents.clone()).and_then(|mut program| {
+        resolve(&mut program)?;
+        Ok(program)
+    });
+    match result {
+        Ok(program) => print!("{}", ast::dump(&program)),
+        Err(diagnostics) => {
+            report_error(&LoxError::CompileError(diagnostics), Some(&contents));
+            eprintln!("Compile Time Error");
+            exit(65);
+        }
+    }
+
+    Ok(())
+}
+
+// prints the source line a span is on with the span underlined, like
+//    3 | print "a" + 1;
+//      |           ^
+fn print_snippet(source: &str, span: Span) {
+    // spans from synthetic tokens don't point anywhere in the source
+    // split rather than lines so an error at the end of the source still has its empty last line
+    let Some(text) = source.split('\n').nth((span.line as usize).wrapping_sub(1)) else {
+        return;
+    };
+
+    let text: Vec<char> = text.trim_end_matches('\r').chars().collect();
+    let start = (span.column.max(1) - 1).min(text.len());
+    // spans running past the line, like multi line strings, are cut off at its end, the end of the source still gets one caret
+    let length = span.length.min(text.len() - start).max(1);
+
+    // tabs are kept so the carets line up however wide the terminal draws them
+    let padding: String = text[..start]
+        .iter()
+        .map(|c| if *c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    let gutter = span.line.to_string();
+    eprintln!("{} | {}", gutter, text.iter().collect::<String>());
+    eprintln!(
+        "{} | {}{}",
+        " ".repeat(gutter.len()),
+        padding,
+        "^".repeat(length)
+    );
+}
+
+// the interpreter only hands errors back, this is where they get shown
+// the source is only there to quote from, without it errors are reported by line alone
+fn report_error(error: &LoxError, source: Option<&str>) {
+    match error {
+        LoxError::CompileError(diagnostics) => {
+            for diagnostic in diagnostics {
+                let location = match &diagnostic.token {
+                    ErrorToken::End => " at end".to_string(),
+                    ErrorToken::Lexeme(lexeme) => format!(" at '{}'", lexeme),
+                    ErrorToken::Invalid => String::new(),
+                };
+                eprintln!(
+                    "[line {}] Error{}: {}",
+                    diagnostic.line, location, diagnostic.message
+                );
+                if let Some(source) = source {
+                    print_snippet(source, diagnostic.span);
+                }
+            }
+        }
+        LoxError::RuntimeError(error) => {
+            eprintln!("{}", error.message);
+            if let (Some(source), Some(frame)) = (source, error.trace.first()) {
+                print_snippet(source, frame.span);
+            }
+            for frame in &error.trace {
+                match &frame.function {
+                    Some(name) => eprintln!("[line {}] in {}()", frame.span.line, name),
+                    None => eprintln!("[line {}] in script", frame.span.line),
+                }
+            }
+        }
+    }
+}
+
+fn exit_on_error(interpret_result: vm::InterpretResult, source: Option<&str>) -> Result<()> {
+    match interpret_result {
+        Ok(_) => Ok(()),
+        Err(error) => {
+            report_error(&error, source);
+            match error {
+                LoxError::CompileError(_) => {
+                    eprintln!("Compile Time Error");
+                    exit(65);
+                }
+                LoxError::RuntimeError(_) => {
+                    eprintln!("Runtime Time Error");
+                    exit(70);
+                }
+            }
         }
     }
 }
 
 fn main() -> Result<()> {
-    let vm = VM::init();
+    let mut vm = VM::init();
+
+    let mut args: Vec<String> = env::args().collect();
+    // the flag can go anywhere, it only picks which compiler the other commands use
+    if let Some(position) = args.iter().position(|arg| arg == "--ast") {
+        args.remove(position);
+        vm.set_ast_pipeline();
+    }
 
-    let args: Vec<String> = env::args().collect();
     if args.len() == 1 {
         return repl(vm);
     } else if args.len() == 2 {
         return run_file(vm, args.get(1).unwrap());
+    } else if args.len() == 3 && args[1] == "run" {
+        return run_bytecode(vm, &args[2]);
+    } else if args.len() == 3 && args[1] == "ast" {
+        return dump_ast(&args[2]);
+    } else if args.len() == 5 && args[1] == "compile" && args[3] == "-o" {
+        return compile_file(vm, &args[2], &args[4]);
     } else {
-        eprintln!("Usage: rustlox [path]");
+        eprintln!("Usage: rustlox [--ast] [path]");
+        eprintln!("       rustlox [--ast] compile <input.lox> -o <output.loxc>");
+        eprintln!("       rustlox run <file.loxc>");
+        eprintln!("       rustlox ast <file.lox>");
         exit(64);
     }
 }
@@ -0,0 +1,82 @@
+use crate::{
+    memory::Heap,
+    values::{MapKey, NativeFn, ObjList, ObjMap, ObjectType, Value},
+    vm::VM,
+};
+
+// the methods maps answer to, each native gets the map itself ahead of the call's arguments
+pub const MAP_METHODS: [(&str, usize, NativeFn); 5] = [
+    ("len", 0, len_native),
+    ("keys", 0, keys_native),
+    ("values", 0, values_native),
+    ("has", 1, has_native),
+    ("delete", 1, delete_native),
+];
+
+// only strings, numbers, booleans and nil can be keys, anything else has no stable identity to hash
+pub fn map_key(heap: &Heap, key: Value) -> Result<MapKey, String> {
+    if key.is_nil() {
+        return Ok(MapKey::Nil);
+    } else if key.is_bool() {
+        return Ok(MapKey::Bool(key.as_bool()));
+    } else if key.is_int() {
+        return Ok(MapKey::Int(key.as_int()));
+    } else if key.is_number() {
+        let number = key.as_number();
+        if number.is_nan() {
+            return Err("Map keys can't be NaN.".to_string());
+        }
+
+        // whole floats key as the int they're equal to, which also puts -0 and 0 on the same entry
+        if number.fract() == 0.0
+            && (-9223372036854775808.0..9223372036854775808.0).contains(&number)
+        {
+            return Ok(MapKey::Int(number as i64));
+        }
+        return Ok(MapKey::Number(number.to_bits()));
+    } else if heap.is_string(&key) {
+        return Ok(MapKey::String(key.as_object()));
+    }
+
+    return Err("Map keys must be strings, numbers, booleans or nil.".to_string());
+}
+
+fn map(vm: &mut VM, map: Value) -> &mut ObjMap {
+    return vm.heap.as_map_mut(map.as_object());
+}
+
+fn len_native(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    return Ok(Value::from_int(map(vm, args[0]).entries.len() as i64));
+}
+
+// both come back as new lists in insertion order
+fn keys_native(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let keys = map(vm, args[0])
+        .entries
+        .iter()
+        .map(|(key, _)| *key)
+        .collect();
+    let list = vm.alloc(ObjectType::List(ObjList::init(keys)));
+    return Ok(Value::from_object(list));
+}
+
+fn values_native(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let values = map(vm, args[0])
+        .entries
+        .iter()
+        .map(|(_, value)| *value)
+        .collect();
+    let list = vm.alloc(ObjectType::List(ObjList::init(values)));
+    return Ok(Value::from_object(list));
+}
+
+fn has_native(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let key = map_key(&vm.heap, args[1])?;
+    return Ok(Value::from_bool(map(vm, args[0]).has(key)));
+}
+
+// true when the key was there to delete
+fn delete_native(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let key = map_key(&vm.heap, args[1])?;
+    return Ok(Value::from_bool(map(vm, args[0]).delete(key)));
+}
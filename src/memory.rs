@@ -0,0 +1,457 @@
+use crate::{
+    number::format_float,
+    table::Table,
+    values::{
+        ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjList, ObjMap, ObjNative,
+        ObjRef, ObjString, ObjUpvalue, ObjectType, Value,
+    },
+    DEBUG_LOG_GC,
+};
+
+// the heap has to double before we bother collecting again
+const GC_HEAP_GROW_FACTOR: usize = 2;
+const FIRST_GC_AT: usize = 1024 * 1024;
+
+struct HeapEntry {
+    object: ObjectType,
+    is_marked: bool,
+    // remembered so freeing subtracts exactly what allocating added
+    size: usize,
+}
+
+// every object the vm creates lives in here, handed out as ObjRef indices
+// the book threads objects through an intrusive linked list, we keep a slab with a free list instead
+pub struct Heap {
+    objects: Vec<Option<HeapEntry>>,
+    free_slots: Vec<usize>,
+    // marked objects whose references haven't been traced yet
+    gray_stack: Vec<ObjRef>,
+    bytes_allocated: usize,
+    next_gc: usize,
+}
+
+impl Heap {
+    pub fn init() -> Self {
+        Heap {
+            objects: Vec::new(),
+            free_slots: Vec::new(),
+            gray_stack: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: FIRST_GC_AT,
+        }
+    }
+
+    pub fn should_collect(&self) -> bool {
+        return self.bytes_allocated > self.next_gc;
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        return self.bytes_allocated;
+    }
+
+    // never collects on its own, the vm decides when it is safe to
+    pub fn alloc(&mut self, object: ObjectType) -> ObjRef {
+        let size = object.size();
+        let kind = object.kind();
+        let entry = HeapEntry {
+            object,
+            is_marked: false,
+            size,
+        };
+
+        let reference = match self.free_slots.pop() {
+            Some(slot) => {
+                self.objects[slot] = Some(entry);
+                ObjRef(slot)
+            }
+            None => {
+                self.objects.push(Some(entry));
+                ObjRef(self.objects.len() - 1)
+            }
+        };
+
+        self.bytes_allocated += size;
+        if DEBUG_LOG_GC {
+            println!("{} allocate {} for {}", reference, size, kind);
+        }
+
+        return reference;
+    }
+
+    // lists, maps and the tables in classes and instances grow after they're allocated
+    // measuring again after a change keeps bytes_allocated honest, so the garbage they hold still triggers a collection
+    pub fn resize(&mut self, reference: ObjRef) {
+        let Some(entry) = &mut self.objects[reference.0] else {
+            panic!("use of freed object {}", reference);
+        };
+
+        let size = entry.object.size();
+        self.bytes_allocated = self.bytes_allocated - entry.size + size;
+        if DEBUG_LOG_GC && size != entry.size {
+            println!("{} resize {} to {}", reference, entry.size, size);
+        }
+        entry.size = size;
+    }
+
+    pub fn get(&self, reference: ObjRef) -> &ObjectType {
+        match &self.objects[reference.0] {
+            Some(entry) => &entry.object,
+            None => panic!("use of freed object {}", reference),
+        }
+    }
+
+    pub fn get_mut(&mut self, reference: ObjRef) -> &mut ObjectType {
+        match &mut self.objects[reference.0] {
+            Some(entry) => &mut entry.object,
+            None => panic!("use of freed object {}", reference),
+        }
+    }
+
+    pub fn as_string(&self, reference: ObjRef) -> &ObjString {
+        match self.get(reference) {
+            ObjectType::String(s) => s,
+            _ => panic!("Incorrect usage of as_string"),
+        }
+    }
+
+    pub fn as_function(&self, reference: ObjRef) -> &ObjFunction {
+        match self.get(reference) {
+            ObjectType::Function(function) => function,
+            _ => panic!("Incorrect usage of as_function"),
+        }
+    }
+
+    pub fn as_closure(&self, reference: ObjRef) -> &ObjClosure {
+        match self.get(reference) {
+            ObjectType::Closure(closure) => closure,
+            _ => panic!("Incorrect usage of as_closure"),
+        }
+    }
+
+    pub fn as_upvalue(&self, reference: ObjRef) -> &ObjUpvalue {
+        match self.get(reference) {
+            ObjectType::Upvalue(upvalue) => upvalue,
+            _ => panic!("Incorrect usage of as_upvalue"),
+        }
+    }
+
+    pub fn as_upvalue_mut(&mut self, reference: ObjRef) -> &mut ObjUpvalue {
+        match self.get_mut(reference) {
+            ObjectType::Upvalue(upvalue) => upvalue,
+            _ => panic!("Incorrect usage of as_upvalue_mut"),
+        }
+    }
+
+    pub fn as_class(&self, reference: ObjRef) -> &ObjClass {
+        match self.get(reference) {
+            ObjectType::Class(class) => class,
+            _ => panic!("Incorrect usage of as_class"),
+        }
+    }
+
+    pub fn as_class_mut(&mut self, reference: ObjRef) -> &mut ObjClass {
+        match self.get_mut(reference) {
+            ObjectType::Class(class) => class,
+            _ => panic!("Incorrect usage of as_class_mut"),
+        }
+    }
+
+    pub fn as_instance(&self, reference: ObjRef) -> &ObjInstance {
+        match self.get(reference) {
+            ObjectType::Instance(instance) => instance,
+            _ => panic!("Incorrect usage of as_instance"),
+        }
+    }
+
+    pub fn as_instance_mut(&mut self, reference: ObjRef) -> &mut ObjInstance {
+        match self.get_mut(reference) {
+            ObjectType::Instance(instance) => instance,
+            _ => panic!("Incorrect usage of as_instance_mut"),
+        }
+    }
+
+    pub fn as_bound_method(&self, reference: ObjRef) -> &ObjBoundMethod {
+        match self.get(reference) {
+            ObjectType::BoundMethod(bound) => bound,
+            _ => panic!("Incorrect usage of as_bound_method"),
+        }
+    }
+
+    pub fn as_native(&self, reference: ObjRef) -> &ObjNative {
+        match self.get(reference) {
+            ObjectType::Native(native) => native,
+            _ => panic!("Incorrect usage of as_native"),
+        }
+    }
+
+    pub fn as_list(&self, reference: ObjRef) -> &ObjList {
+        match self.get(reference) {
+            ObjectType::List(list) => list,
+            _ => panic!("Incorrect usage of as_list"),
+        }
+    }
+
+    pub fn as_list_mut(&mut self, reference: ObjRef) -> &mut ObjList {
+        match self.get_mut(reference) {
+            ObjectType::List(list) => list,
+            _ => panic!("Incorrect usage of as_list_mut"),
+        }
+    }
+
+    pub fn as_map(&self, reference: ObjRef) -> &ObjMap {
+        match self.get(reference) {
+            ObjectType::Map(map) => map,
+            _ => panic!("Incorrect usage of as_map"),
+        }
+    }
+
+    pub fn as_map_mut(&mut self, reference: ObjRef) -> &mut ObjMap {
+        match self.get_mut(reference) {
+            ObjectType::Map(map) => map,
+            _ => panic!("Incorrect usage of as_map_mut"),
+        }
+    }
+
+    fn is_object_type(&self, value: &Value, matcher: fn(&ObjectType) -> bool) -> bool {
+        return value.is_object() && matcher(self.get(value.as_object()));
+    }
+
+    pub fn is_string(&self, value: &Value) -> bool {
+        return self.is_object_type(value, |o| matches!(o, ObjectType::String(_)));
+    }
+
+    pub fn is_function(&self, value: &Value) -> bool {
+        return self.is_object_type(value, |o| matches!(o, ObjectType::Function(_)));
+    }
+
+    pub fn is_closure(&self, value: &Value) -> bool {
+        return self.is_object_type(value, |o| matches!(o, ObjectType::Closure(_)));
+    }
+
+    pub fn is_class(&self, value: &Value) -> bool {
+        return self.is_object_type(value, |o| matches!(o, ObjectType::Class(_)));
+    }
+
+    pub fn is_instance(&self, value: &Value) -> bool {
+        return self.is_object_type(value, |o| matches!(o, ObjectType::Instance(_)));
+    }
+
+    pub fn is_bound_method(&self, value: &Value) -> bool {
+        return self.is_object_type(value, |o| matches!(o, ObjectType::BoundMethod(_)));
+    }
+
+    pub fn is_native(&self, value: &Value) -> bool {
+        return self.is_object_type(value, |o| matches!(o, ObjectType::Native(_)));
+    }
+
+    pub fn is_list(&self, value: &Value) -> bool {
+        return self.is_object_type(value, |o| matches!(o, ObjectType::List(_)));
+    }
+
+    pub fn is_map(&self, value: &Value) -> bool {
+        return self.is_object_type(value, |o| matches!(o, ObjectType::Map(_)));
+    }
+
+    pub fn format_value(&self, value: &Value) -> String {
+        return self.format_nested(value, &mut Vec::new());
+    }
+
+    // `enclosing` holds the lists and maps we're in the middle of printing, one that contains itself shows up as [...] or {...}
+    fn format_nested(&self, value: &Value, enclosing: &mut Vec<ObjRef>) -> String {
+        if value.is_bool() {
+            return value.as_bool().to_string();
+        } else if value.is_nil() {
+            return "Nil".to_string();
+        } else if value.is_number() {
+            return format_float(value.as_number());
+        } else if value.is_int() {
+            return value.as_int().to_string();
+        }
+
+        let reference = value.as_object();
+        if let ObjectType::List(list) = self.get(reference) {
+            if enclosing.contains(&reference) {
+                return "[...]".to_string();
+            }
+
+            enclosing.push(reference);
+            let items: Vec<String> = list
+                .items
+                .iter()
+                .map(|item| self.format_nested(item, enclosing))
+                .collect();
+            enclosing.pop();
+            return format!("[{}]", items.join(", "));
+        }
+
+        if let ObjectType::Map(map) = self.get(reference) {
+            if enclosing.contains(&reference) {
+                return "{...}".to_string();
+            }
+
+            enclosing.push(reference);
+            let entries: Vec<String> = map
+                .entries
+                .iter()
+                .map(|(key, value)| {
+                    let key = self.format_nested(key, enclosing);
+                    format!("{}: {}", key, self.format_nested(value, enclosing))
+                })
+                .collect();
+            enclosing.pop();
+            return format!("{{{}}}", entries.join(", "));
+        }
+
+        return self.format_object(reference);
+    }
+
+    fn format_object(&self, reference: ObjRef) -> String {
+        match self.get(reference) {
+            ObjectType::String(s) => s.content.to_string(),
+            ObjectType::Function(function) => function.to_string(),
+            ObjectType::Closure(closure) => self.as_function(closure.function).to_string(),
+            ObjectType::Upvalue(_) => "upvalue".to_string(),
+            ObjectType::Class(class) => self.as_string(class.name).content.to_string(),
+            ObjectType::Instance(instance) => {
+                let class = self.as_class(instance.class);
+                format!("{} instance", self.as_string(class.name))
+            }
+            ObjectType::BoundMethod(bound) => self.format_object(bound.method),
+            ObjectType::Native(_) => "<native fn>".to_string(),
+            ObjectType::List(list) => format!("list of {}", list.items.len()),
+            ObjectType::Map(map) => format!("map of {}", map.entries.len()),
+        }
+    }
+
+    pub fn is_marked(&self, reference: ObjRef) -> bool {
+        match &self.objects[reference.0] {
+            Some(entry) => entry.is_marked,
+            None => false,
+        }
+    }
+
+    // keys are strings too, they have to survive along with their values
+    pub fn mark_table(&mut self, table: &Table) {
+        for (key, value) in table.iter() {
+            self.mark_object(key);
+            self.mark_value(&value);
+        }
+    }
+
+    pub fn mark_value(&mut self, value: &Value) {
+        if value.is_object() {
+            self.mark_object(value.as_object());
+        }
+    }
+
+    pub fn mark_object(&mut self, reference: ObjRef) {
+        let entry = match &mut self.objects[reference.0] {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        // already marked objects have been (or will be) traced, this also stops us looping on cycles
+        if entry.is_marked {
+            return;
+        }
+
+        entry.is_marked = true;
+        if DEBUG_LOG_GC {
+            println!("{} mark {}", reference, self.format_object(reference));
+        }
+
+        self.gray_stack.push(reference);
+    }
+
+    // marks everything a gray object refers to, turning it black
+    fn blacken_object(&mut self, reference: ObjRef) {
+        if DEBUG_LOG_GC {
+            println!("{} blacken {}", reference, self.format_object(reference));
+        }
+
+        let mut values = Vec::new();
+        let mut objects = Vec::new();
+        match self.get(reference) {
+            ObjectType::String(_) | ObjectType::Native(_) => {}
+            ObjectType::Function(function) => {
+                values.extend(function.chunk.constants.values.iter().copied());
+            }
+            ObjectType::Closure(closure) => {
+                objects.push(closure.function);
+                objects.extend(closure.upvalues.iter().copied());
+            }
+            ObjectType::Upvalue(upvalue) => {
+                values.extend(upvalue.closed);
+            }
+            ObjectType::Class(class) => {
+                objects.push(class.name);
+                for (name, method) in class.methods.iter() {
+                    objects.push(name);
+                    values.push(method);
+                }
+            }
+            ObjectType::Instance(instance) => {
+                objects.push(instance.class);
+                for (name, value) in instance.fields.iter() {
+                    objects.push(name);
+                    values.push(value);
+                }
+            }
+            ObjectType::BoundMethod(bound) => {
+                values.push(bound.receiver);
+                objects.push(bound.method);
+            }
+            ObjectType::List(list) => {
+                values.extend(list.items.iter().copied());
+            }
+            ObjectType::Map(map) => {
+                for (key, value) in map.entries.iter() {
+                    values.push(*key);
+                    values.push(*value);
+                }
+            }
+        }
+
+        for value in &values {
+            self.mark_value(value);
+        }
+        for object in objects {
+            self.mark_object(object);
+        }
+    }
+
+    pub fn trace_references(&mut self) {
+        while let Some(reference) = self.gray_stack.pop() {
+            self.blacken_object(reference);
+        }
+    }
+
+    // frees every object that wasn't reached, and clears the marks on the survivors for next time
+    pub fn sweep(&mut self) {
+        for slot in 0..self.objects.len() {
+            let entry = match &mut self.objects[slot] {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            if entry.is_marked {
+                entry.is_marked = false;
+                continue;
+            }
+
+            if DEBUG_LOG_GC {
+                println!("{} free type {}", ObjRef(slot), entry.object.kind());
+            }
+
+            self.bytes_allocated -= entry.size;
+            self.objects[slot] = None;
+            self.free_slots.push(slot);
+        }
+
+        self.next_gc = (self.bytes_allocated * GC_HEAP_GROW_FACTOR).max(FIRST_GC_AT);
+    }
+
+    pub fn next_gc(&self) -> usize {
+        return self.next_gc;
+    }
+}
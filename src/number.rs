@@ -0,0 +1,222 @@
+use std::cmp::Ordering;
+
+use crate::values::{Value, INT_MAX, INT_MIN, INT_RANGE_NOTE};
+
+// the numeric rules, shared by the vm and both constant folders so folding can never disagree with running
+// ints stay ints, an operation with a float on either side is done in floats, `/` always gives a float
+
+pub enum Operation {
+    Greater,
+    Less,
+    Plus,
+    Minus,
+    Star,
+    Div,
+    FloorDiv,
+    Modulo,
+}
+
+pub fn is_numeric(value: &Value) -> bool {
+    return value.is_int() || value.is_number();
+}
+
+fn as_float(value: Value) -> f64 {
+    if value.is_int() {
+        return value.as_int() as f64;
+    }
+    return value.as_number();
+}
+
+// checked ints come back as None on overflow, and INT_MIN..=INT_MAX can be narrower than an i64
+fn checked_int(result: Option<i64>) -> Result<Value, String> {
+    match result {
+        Some(n) if (INT_MIN..=INT_MAX).contains(&n) => return Ok(Value::from_int(n)),
+        _ => return Err(format!("Integer overflow{INT_RANGE_NOTE}.")),
+    }
+}
+
+// `//` and `%` round towards negative infinity, so a == (a // b) * b + a % b holds for every sign
+fn floor_div(a: i64, b: i64) -> Option<i64> {
+    let quotient = a.checked_div(b)?;
+    if a % b != 0 && (a < 0) != (b < 0) {
+        return Some(quotient - 1);
+    }
+    return Some(quotient);
+}
+
+fn floor_mod(a: i64, b: i64) -> i64 {
+    // only i64::MIN % -1 fails, and it is 0
+    let remainder = a.checked_rem(b).unwrap_or(0);
+    if remainder != 0 && (remainder < 0) != (b < 0) {
+        return remainder + b;
+    }
+    return remainder;
+}
+
+fn float_mod(a: f64, b: f64) -> f64 {
+    let remainder = a % b;
+    if remainder != 0.0 && (remainder < 0.0) != (b < 0.0) {
+        return remainder + b;
+    }
+    return remainder;
+}
+
+// exact, turning a large int into a float could round it onto the float it's being compared with
+fn compare_int_float(int: i64, float: f64) -> Option<Ordering> {
+    if float.is_nan() {
+        return None;
+    }
+
+    // 2^63 is a float, every i64 is below it and at or above its negation
+    let limit = 9223372036854775808.0;
+    if float >= limit {
+        return Some(Ordering::Less);
+    } else if float < -limit {
+        return Some(Ordering::Greater);
+    }
+
+    // the whole parts decide it, if they tie the int is below the float exactly when there's a positive fraction
+    let whole = float.trunc() as i64;
+    return Some(int.cmp(&whole).then(0.0.partial_cmp(&float.fract())?));
+}
+
+pub fn compare(a: Value, b: Value) -> Option<Ordering> {
+    match (a.is_int(), b.is_int()) {
+        (true, true) => return Some(a.as_int().cmp(&b.as_int())),
+        (true, false) => return compare_int_float(a.as_int(), b.as_number()),
+        (false, true) => {
+            return compare_int_float(b.as_int(), a.as_number()).map(Ordering::reverse)
+        }
+        (false, false) => return a.as_number().partial_cmp(&b.as_number()),
+    }
+}
+
+// lox's ==, numbers are equal when their values are so 1 == 1.0, everything else goes by identity
+pub fn equal(a: Value, b: Value) -> bool {
+    if is_numeric(&a) && is_numeric(&b) {
+        return compare(a, b) == Some(Ordering::Equal);
+    }
+    return a == b;
+}
+
+pub fn negate(value: Value) -> Result<Value, String> {
+    if value.is_int() {
+        return checked_int(value.as_int().checked_neg());
+    } else if value.is_number() {
+        return Ok(Value::from_number(-value.as_number()));
+    }
+    return Err("Operand must be a number.".to_string());
+}
+
+pub fn binary(operation: Operation, a: Value, b: Value) -> Result<Value, String> {
+    if !is_numeric(&a) || !is_numeric(&b) {
+        return Err("Operands must be numbers.".to_string());
+    }
+
+    if a.is_int() && b.is_int() {
+        let (a, b) = (a.as_int(), b.as_int());
+        match operation {
+            Operation::Greater => return Ok(Value::from_bool(a > b)),
+            Operation::Less => return Ok(Value::from_bool(a < b)),
+            Operation::Plus => return checked_int(a.checked_add(b)),
+            Operation::Minus => return checked_int(a.checked_sub(b)),
+            Operation::Star => return checked_int(a.checked_mul(b)),
+            Operation::Div => return Ok(Value::from_number(a as f64 / b as f64)),
+            Operation::FloorDiv | Operation::Modulo if b == 0 => {
+                return Err("Division by zero.".to_string());
+            }
+            Operation::FloorDiv => return checked_int(floor_div(a, b)),
+            Operation::Modulo => return Ok(Value::from_int(floor_mod(a, b))),
+        }
+    }
+
+    let (x, y) = (as_float(a), as_float(b));
+    let result = match operation {
+        // an int against a float is compared exactly rather than as two floats
+        Operation::Greater => {
+            return Ok(Value::from_bool(compare(a, b) == Some(Ordering::Greater)))
+        }
+        Operation::Less => return Ok(Value::from_bool(compare(a, b) == Some(Ordering::Less))),
+        Operation::Plus => x + y,
+        Operation::Minus => x - y,
+        Operation::Star => x * y,
+        Operation::Div => x / y,
+        Operation::FloorDiv => (x / y).floor(),
+        Operation::Modulo => float_mod(x, y),
+    };
+    return Ok(Value::from_number(result));
+}
+
+// floats always show a fractional part, so 3.0 and the int 3 print differently
+pub fn format_float(number: f64) -> String {
+    if number.is_finite() && number.fract() == 0.0 {
+        return format!("{:.1}", number);
+    }
+    return number.to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(a: i64, operation: Operation, b: i64) -> Result<Value, String> {
+        return binary(operation, Value::from_int(a), Value::from_int(b));
+    }
+
+    #[test]
+    fn floor_division_rounds_down() {
+        let cases = [
+            (7, 2, 3),
+            (-7, 2, -4),
+            (7, -2, -4),
+            (-7, -2, 3),
+            (6, -3, -2),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(int(a, Operation::FloorDiv, b).unwrap().as_int(), expected);
+        }
+
+        let result = binary(
+            Operation::FloorDiv,
+            Value::from_number(-7.5),
+            Value::from_int(2),
+        );
+        assert_eq!(result.unwrap().as_number(), -4.0);
+    }
+
+    #[test]
+    fn modulo_takes_the_divisor_sign() {
+        let cases = [(7, 3, 1), (-7, 3, 2), (7, -3, -2), (-7, -3, -1), (6, -3, 0)];
+        for (a, b, expected) in cases {
+            assert_eq!(int(a, Operation::Modulo, b).unwrap().as_int(), expected);
+            // a == (a // b) * b + a % b
+            let quotient = int(a, Operation::FloorDiv, b).unwrap().as_int();
+            assert_eq!(quotient * b + expected, a);
+        }
+
+        let result = binary(
+            Operation::Modulo,
+            Value::from_number(-7.5),
+            Value::from_int(2),
+        );
+        assert_eq!(result.unwrap().as_number(), 0.5);
+    }
+
+    #[test]
+    fn division_by_zero() {
+        for operation in [Operation::FloorDiv, Operation::Modulo] {
+            assert_eq!(int(1, operation, 0).err().unwrap(), "Division by zero.");
+        }
+    }
+
+    #[test]
+    fn floor_division_overflow() {
+        // the one quotient that doesn't fit, the remainder is still fine
+        assert_eq!(floor_div(i64::MIN, -1), None);
+        assert_eq!(floor_mod(i64::MIN, -1), 0);
+
+        let message = format!("Integer overflow{INT_RANGE_NOTE}.");
+        assert_eq!(int(INT_MIN, Operation::FloorDiv, -1).err(), Some(message));
+        assert_eq!(int(INT_MIN, Operation::Modulo, -1).unwrap().as_int(), 0);
+    }
+}
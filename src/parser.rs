@@ -0,0 +1,844 @@
+use crate::{
+    ast::{
+        BinaryOp, Class, Expr, ExprKind, Function, FunctionType, Literal, LogicalOp, Name, Program,
+        Stmt, StmtKind, UnaryOp, Variable,
+    },
+    chunk::LONG_OPERAND_MAX,
+    compiler::Precedence,
+    error::CompileDiagnostic,
+    scanner::{Scanner, Span, Token, TokenType},
+};
+
+// same limit as the single pass compiler
+const MAX_ARGUMENTS: usize = 255;
+
+// the front half of the multi pass pipeline, builds the tree instead of emitting code
+// grammar, error messages and recovery all match compiler.rs so both pipelines report the same syntax errors
+pub struct Parser {
+    scanner: Scanner,
+    current: Token,
+    previous: Token,
+    diagnostics: Vec<CompileDiagnostic>,
+    panic_mode: bool,
+    // whether each enclosing class has a superclass, the resolver checks `super` but a bare one never gets that far
+    classes: Vec<bool>,
+}
+
+fn name_of(token: &Token) -> Name {
+    Name {
+        lexeme: token.content.clone(),
+        span: token.span,
+    }
+}
+
+// stands in for whatever failed to parse, nothing gets lowered once there's an error
+fn placeholder(span: Span) -> Expr {
+    Expr {
+        kind: ExprKind::Literal(Literal::Nil),
+        span,
+    }
+}
+
+impl Parser {
+    fn init(source: String) -> Self {
+        // both get replaced by the first advance
+        let start = Token {
+            t_type: TokenType::Eof,
+            start: 0,
+            content: String::new(),
+            length: 0,
+            line: 0,
+            span: Span::default(),
+            literal: None,
+        };
+
+        Parser {
+            scanner: Scanner::init(source),
+            current: start.clone(),
+            previous: start,
+            diagnostics: Vec::new(),
+            panic_mode: false,
+            classes: Vec::new(),
+        }
+    }
+
+    fn error_at_current(&mut self, message: &str) {
+        if self.panic_mode {
+            return;
+        }
+
+        self.panic_mode = true;
+        let diagnostic = CompileDiagnostic::at(&self.current, message.to_string());
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn error(&mut self, message: &str) {
+        if self.panic_mode {
+            return;
+        }
+
+        self.panic_mode = true;
+        let diagnostic = CompileDiagnostic::at(&self.previous, message.to_string());
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn advance(&mut self) {
+        self.previous = self.current.clone();
+
+        loop {
+            self.current = self.scanner.scan_token();
+            if self.current.t_type != TokenType::Error {
+                break;
+            }
+
+            let message = self.current.content.clone();
+            self.error_at_current(&message);
+        }
+    }
+
+    fn consume(&mut self, t_type: TokenType, message: &str) {
+        if self.check(t_type) {
+            self.advance();
+            return;
+        }
+
+        self.error_at_current(message);
+    }
+
+    fn check(&self, t_type: TokenType) -> bool {
+        return self.current.t_type == t_type;
+    }
+
+    fn match_token(&mut self, t_type: TokenType) -> bool {
+        if !self.check(t_type) {
+            return false;
+        }
+
+        self.advance();
+        return true;
+    }
+
+    fn stmt(&self, kind: StmtKind) -> Stmt {
+        Stmt {
+            kind,
+            span: self.previous.span,
+        }
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> Expr {
+        self.advance();
+        let can_assign = precedence <= Precedence::Assignment;
+        let mut expression = match get_rule(&self.previous.t_type).prefix {
+            Some(func) => func(self, can_assign),
+            None => {
+                self.error("Expect expression");
+                return placeholder(self.previous.span);
+            }
+        };
+
+        while precedence <= get_rule(&self.current.t_type).precedence {
+            self.advance();
+            match get_rule(&self.previous.t_type).infix {
+                Some(func) => expression = func(self, expression, can_assign),
+                None => panic!("this shouldn't error"),
+            }
+        }
+
+        if can_assign && self.match_token(TokenType::Equal) {
+            self.error("Invalid assignment target.");
+        }
+
+        return expression;
+    }
+
+    fn expression(&mut self) -> Expr {
+        return self.parse_precedence(Precedence::Assignment);
+    }
+
+    fn block(&mut self) -> Vec<Stmt> {
+        let mut statements = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            statements.push(self.declaration());
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block.");
+        return statements;
+    }
+
+    fn function(&mut self, function_type: FunctionType) -> Function {
+        let name = name_of(&self.previous);
+
+        self.consume(TokenType::LeftParen, "Expect '(' after function name.");
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() == MAX_ARGUMENTS {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                }
+
+                self.consume(TokenType::Identifier, "Expect parameter name.");
+                params.push(Variable::init(name_of(&self.previous)));
+
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.");
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.");
+        let body = self.block();
+
+        Function {
+            name,
+            function_type,
+            params,
+            body,
+            end: self.previous.span,
+            upvalues: Vec::new(),
+        }
+    }
+
+    fn method(&mut self) -> Function {
+        self.consume(TokenType::Identifier, "Expect method name.");
+        let function_type = if self.previous.content == "init" {
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+
+        return self.function(function_type);
+    }
+
+    fn class_declaration(&mut self) -> Stmt {
+        self.consume(TokenType::Identifier, "Expect class name.");
+        let name = Variable::init(name_of(&self.previous));
+
+        let mut superclass = None;
+        if self.match_token(TokenType::Less) {
+            self.consume(TokenType::Identifier, "Expect superclass name.");
+            superclass = Some(Variable::init(name_of(&self.previous)));
+        }
+
+        self.classes.push(superclass.is_some());
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.");
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            methods.push(self.method());
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.");
+        self.classes.pop();
+
+        return self.stmt(StmtKind::Class(Class {
+            name,
+            superclass,
+            methods,
+            super_captured: false,
+        }));
+    }
+
+    fn fun_declaration(&mut self) -> Stmt {
+        self.consume(TokenType::Identifier, "Expect function name.");
+        let name = Variable::init(name_of(&self.previous));
+        let function = self.function(FunctionType::Function);
+        return self.stmt(StmtKind::Fun { name, function });
+    }
+
+    fn argument_list(&mut self) -> Vec<Expr> {
+        let mut arguments = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                arguments.push(self.expression());
+                if arguments.len() == MAX_ARGUMENTS + 1 {
+                    self.error("Can't have more than 255 arguments.");
+                }
+
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+        return arguments;
+    }
+
+    fn var_declaration(&mut self) -> Stmt {
+        self.consume(TokenType::Identifier, "Expect variable name.");
+        let name = Variable::init(name_of(&self.previous));
+
+        let initializer = if self.match_token(TokenType::Equal) {
+            Some(self.expression())
+        } else {
+            None
+        };
+
+        self.consume(
+            TokenType::SemiColon,
+            "Expect ';' after variable declaration.",
+        );
+        return self.stmt(StmtKind::Var { name, initializer });
+    }
+
+    fn return_statement(&mut self) -> Stmt {
+        let keyword = name_of(&self.previous);
+
+        let value = if self.match_token(TokenType::SemiColon) {
+            None
+        } else {
+            let value = self.expression();
+            self.consume(TokenType::SemiColon, "Expect ';' after return value.");
+            Some(value)
+        };
+
+        return self.stmt(StmtKind::Return { keyword, value });
+    }
+
+    fn print_statement(&mut self) -> Stmt {
+        let value = self.expression();
+        self.consume(TokenType::SemiColon, "Expect ';' after value.");
+        return self.stmt(StmtKind::Print(value));
+    }
+
+    fn expression_statement(&mut self) -> Stmt {
+        let expression = self.expression();
+        self.consume(TokenType::SemiColon, "Expect ';' after expression.");
+        return self.stmt(StmtKind::Expression(expression));
+    }
+
+    // skips tokens until we reach something that looks like a statement boundary
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+
+        while !self.check(TokenType::Eof) {
+            if self.previous.t_type == TokenType::SemiColon {
+                return;
+            }
+
+            match self.current.t_type {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    fn declaration(&mut self) -> Stmt {
+        let statement = if self.match_token(TokenType::Class) {
+            self.class_declaration()
+        } else if self.match_token(TokenType::Fun) {
+            self.fun_declaration()
+        } else if self.match_token(TokenType::Var) {
+            self.var_declaration()
+        } else {
+            self.statement()
+        };
+
+        if self.panic_mode {
+            self.synchronize();
+        }
+        return statement;
+    }
+
+    fn if_statement(&mut self) -> Stmt {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.");
+        let condition = self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after condition.");
+        let condition_end = self.previous.span;
+
+        let then_branch = Box::new(self.statement());
+        let else_branch = if self.match_token(TokenType::Else) {
+            Some(Box::new(self.statement()))
+        } else {
+            None
+        };
+
+        return self.stmt(StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+            condition_end,
+        });
+    }
+
+    fn while_statement(&mut self) -> Stmt {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.");
+        let condition = self.expression();
+        self.consume(TokenType::RightParen, "Expect ')' after condition.");
+        let condition_end = self.previous.span;
+
+        let body = Box::new(self.statement());
+        return self.stmt(StmtKind::While {
+            condition,
+            body,
+            condition_end,
+        });
+    }
+
+    fn for_statement(&mut self) -> Stmt {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.");
+        let initializer = if self.match_token(TokenType::SemiColon) {
+            None
+        } else if self.match_token(TokenType::Var) {
+            Some(Box::new(self.var_declaration()))
+        } else {
+            Some(Box::new(self.expression_statement()))
+        };
+
+        let mut condition = None;
+        if !self.match_token(TokenType::SemiColon) {
+            condition = Some(self.expression());
+            self.consume(TokenType::SemiColon, "Expect ';' after loop condition.");
+        }
+        let condition_end = self.previous.span;
+
+        let mut increment = None;
+        let mut increment_end = condition_end;
+        if !self.match_token(TokenType::RightParen) {
+            increment = Some(Box::new(self.expression()));
+            increment_end = self.previous.span;
+            self.consume(TokenType::RightParen, "Expect ')' after for clauses.");
+        }
+        let clauses_end = self.previous.span;
+
+        let body = Box::new(self.statement());
+        return self.stmt(StmtKind::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            captured: Vec::new(),
+            condition_end,
+            increment_end,
+            clauses_end,
+        });
+    }
+
+    fn statement(&mut self) -> Stmt {
+        if self.match_token(TokenType::Print) {
+            return self.print_statement();
+        } else if self.match_token(TokenType::Return) {
+            return self.return_statement();
+        } else if self.match_token(TokenType::If) {
+            return self.if_statement();
+        } else if self.match_token(TokenType::While) {
+            return self.while_statement();
+        } else if self.match_token(TokenType::For) {
+            return self.for_statement();
+        } else if self.check(TokenType::LeftBrace) && !self.scanner.brace_opens_map() {
+            self.advance();
+            let statements = self.block();
+            return self.stmt(StmtKind::Block {
+                statements,
+                captured: Vec::new(),
+            });
+        } else {
+            return self.expression_statement();
+        }
+    }
+}
+
+type PrefixFn = fn(&mut Parser, bool) -> Expr;
+type InfixFn = fn(&mut Parser, Expr, bool) -> Expr;
+
+struct ParseRule {
+    prefix: Option<PrefixFn>,
+    infix: Option<InfixFn>,
+    precedence: Precedence,
+}
+
+fn get_rule(operator_type: &TokenType) -> ParseRule {
+    let (prefix, infix, precedence): (Option<PrefixFn>, Option<InfixFn>, Precedence) =
+        match operator_type {
+            TokenType::LeftParen => (Some(grouping), Some(call), Precedence::Call),
+            TokenType::LeftBrace => (Some(map), None, Precedence::None),
+            TokenType::LeftBracket => (Some(list), Some(subscript), Precedence::Call),
+            TokenType::Dot => (None, Some(dot), Precedence::Call),
+            TokenType::Super => (Some(super_), None, Precedence::None),
+            TokenType::This => (Some(this), None, Precedence::None),
+            TokenType::Minus => (Some(unary), Some(binary), Precedence::Term),
+            TokenType::Plus => (None, Some(binary), Precedence::Term),
+            TokenType::Slash | TokenType::SlashSlash | TokenType::Percent | TokenType::Star => {
+                (None, Some(binary), Precedence::Factor)
+            }
+            TokenType::Number => (Some(number), None, Precedence::None),
+            TokenType::Int => (Some(int), None, Precedence::None),
+            TokenType::Bang => (Some(unary), None, Precedence::None),
+            TokenType::BangEqual | TokenType::EqualEqual => {
+                (None, Some(binary), Precedence::Equality)
+            }
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => (None, Some(binary), Precedence::Comparison),
+            TokenType::True | TokenType::Nil | TokenType::False => {
+                (Some(literal), None, Precedence::None)
+            }
+            TokenType::And => (None, Some(and), Precedence::And),
+            TokenType::Or => (None, Some(or), Precedence::Or),
+            TokenType::Identifier => (Some(variable), None, Precedence::None),
+            TokenType::String => (Some(string), None, Precedence::None),
+            TokenType::Interpolation => (Some(interpolation), None, Precedence::None),
+            _ => (None, None, Precedence::None),
+        };
+
+    ParseRule {
+        prefix,
+        infix,
+        precedence,
+    }
+}
+
+fn number(parser: &mut Parser, _can_assign: bool) -> Expr {
+    let value: f64 = parser.previous.content.parse().unwrap();
+    Expr {
+        kind: ExprKind::Literal(Literal::Number(value)),
+        span: parser.previous.span,
+    }
+}
+
+fn string(parser: &mut Parser, _can_assign: bool) -> Expr {
+    Expr {
+        kind: ExprKind::Literal(Literal::String(
+            parser.previous.literal.clone().unwrap_or_default(),
+        )),
+        span: parser.previous.span,
+    }
+}
+
+// the segments keep their tokens' spans, the concatenation points at the segment before each expression
+fn interpolation(parser: &mut Parser, can_assign: bool) -> Expr {
+    let span = parser.previous.span;
+    let mut segments = Vec::new();
+    let mut expressions = Vec::new();
+
+    loop {
+        segments.push(string(parser, can_assign));
+        if parser.previous.t_type == TokenType::InterpolationEnd {
+            break;
+        }
+
+        expressions.push(parser.expression());
+        if !parser.match_token(TokenType::Interpolation)
+            && !parser.match_token(TokenType::InterpolationEnd)
+        {
+            parser.error_at_current("Expect '}' after interpolated expression.");
+            break;
+        }
+    }
+
+    Expr {
+        kind: ExprKind::Interpolation {
+            segments,
+            expressions,
+        },
+        span,
+    }
+}
+
+// the scanner already made sure it fits
+fn int(parser: &mut Parser, _can_assign: bool) -> Expr {
+    let value: i64 = parser.previous.content.parse().unwrap();
+    Expr {
+        kind: ExprKind::Literal(Literal::Int(value)),
+        span: parser.previous.span,
+    }
+}
+
+fn literal(parser: &mut Parser, _can_assign: bool) -> Expr {
+    let literal = match parser.previous.t_type {
+        TokenType::False => Literal::Bool(false),
+        TokenType::True => Literal::Bool(true),
+        _ => Literal::Nil,
+    };
+
+    Expr {
+        kind: ExprKind::Literal(literal),
+        span: parser.previous.span,
+    }
+}
+
+fn variable(parser: &mut Parser, can_assign: bool) -> Expr {
+    let target = Variable::init(name_of(&parser.previous));
+    let span = target.name.span;
+
+    if can_assign && parser.match_token(TokenType::Equal) {
+        let value = Box::new(parser.expression());
+        return Expr {
+            kind: ExprKind::Assign { target, value },
+            span,
+        };
+    }
+
+    Expr {
+        kind: ExprKind::Variable(target),
+        span,
+    }
+}
+
+fn grouping(parser: &mut Parser, _can_assign: bool) -> Expr {
+    let inner = parser.expression();
+    parser.consume(TokenType::RightParen, "Expect ')' after expression");
+
+    // the closing paren, a fold around the grouping puts its result there like the single pass compiler does
+    let span = parser.previous.span;
+    Expr {
+        kind: ExprKind::Grouping(Box::new(inner)),
+        span,
+    }
+}
+
+fn call(parser: &mut Parser, callee: Expr, _can_assign: bool) -> Expr {
+    let span = parser.previous.span;
+    let arguments = parser.argument_list();
+
+    Expr {
+        kind: ExprKind::Call {
+            callee: Box::new(callee),
+            arguments,
+        },
+        span,
+    }
+}
+
+fn list(parser: &mut Parser, _can_assign: bool) -> Expr {
+    let span = parser.previous.span;
+    let mut items = Vec::new();
+    if !parser.check(TokenType::RightBracket) {
+        loop {
+            items.push(parser.expression());
+            if items.len() == LONG_OPERAND_MAX + 1 {
+                parser.error(&format!(
+                    "Can't have more than {} items in a list literal.",
+                    LONG_OPERAND_MAX
+                ));
+            }
+
+            if !parser.match_token(TokenType::Comma) {
+                break;
+            }
+        }
+    }
+
+    parser.consume(TokenType::RightBracket, "Expect ']' after list items.");
+    Expr {
+        kind: ExprKind::List(items),
+        span,
+    }
+}
+
+// a brace starting a statement is a block unless Scanner::brace_opens_map says otherwise
+fn map(parser: &mut Parser, _can_assign: bool) -> Expr {
+    let span = parser.previous.span;
+    let mut entries = Vec::new();
+    if !parser.check(TokenType::RightBrace) {
+        loop {
+            let key = parser.expression();
+            parser.consume(TokenType::Colon, "Expect ':' after map key.");
+            entries.push((key, parser.expression()));
+            if entries.len() == LONG_OPERAND_MAX + 1 {
+                parser.error(&format!(
+                    "Can't have more than {} entries in a map literal.",
+                    LONG_OPERAND_MAX
+                ));
+            }
+
+            if !parser.match_token(TokenType::Comma) {
+                break;
+            }
+        }
+    }
+
+    parser.consume(TokenType::RightBrace, "Expect '}' after map entries.");
+    Expr {
+        kind: ExprKind::Map(entries),
+        span,
+    }
+}
+
+fn subscript(parser: &mut Parser, object: Expr, can_assign: bool) -> Expr {
+    let span = parser.previous.span;
+    let object = Box::new(object);
+    let index = Box::new(parser.expression());
+    parser.consume(TokenType::RightBracket, "Expect ']' after index.");
+
+    if can_assign && parser.match_token(TokenType::Equal) {
+        let value = Box::new(parser.expression());
+        return Expr {
+            kind: ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            },
+            span,
+        };
+    }
+
+    Expr {
+        kind: ExprKind::Index { object, index },
+        span,
+    }
+}
+
+fn dot(parser: &mut Parser, object: Expr, can_assign: bool) -> Expr {
+    parser.consume(TokenType::Identifier, "Expect property name after '.'.");
+    let name = name_of(&parser.previous);
+    let span = name.span;
+    let object = Box::new(object);
+
+    if can_assign && parser.match_token(TokenType::Equal) {
+        let value = Box::new(parser.expression());
+        return Expr {
+            kind: ExprKind::Set {
+                object,
+                name,
+                value,
+            },
+            span,
+        };
+    }
+
+    Expr {
+        kind: ExprKind::Get { object, name },
+        span,
+    }
+}
+
+// `this` is checked by the resolver, the parser only records where it was
+fn this(parser: &mut Parser, _can_assign: bool) -> Expr {
+    let this = Variable::init(name_of(&parser.previous));
+    let span = this.name.span;
+
+    Expr {
+        kind: ExprKind::This(this),
+        span,
+    }
+}
+
+fn super_(parser: &mut Parser, _can_assign: bool) -> Expr {
+    let keyword = parser.previous.span;
+    // the compiler reports a misplaced `super` before the missing '.', which it then skips in panic mode
+    if !parser.check(TokenType::Dot) {
+        match parser.classes.last() {
+            None => parser.error("Can't use 'super' outside of a class."),
+            Some(false) => parser.error("Can't use 'super' in a class with no superclass."),
+            Some(true) => {}
+        }
+    }
+    parser.consume(TokenType::Dot, "Expect '.' after 'super'.");
+    parser.consume(TokenType::Identifier, "Expect superclass method name.");
+    let method = name_of(&parser.previous);
+    let span = method.span;
+
+    // both hidden locals point back at the `super` keyword
+    let hidden = |lexeme: &str| {
+        Variable::init(Name {
+            lexeme: lexeme.to_string(),
+            span: keyword,
+        })
+    };
+
+    Expr {
+        kind: ExprKind::Super {
+            this: hidden("this"),
+            superclass: hidden("super"),
+            method,
+        },
+        span,
+    }
+}
+
+fn unary(parser: &mut Parser, _can_assign: bool) -> Expr {
+    let op = match parser.previous.t_type {
+        TokenType::Bang => UnaryOp::Not,
+        TokenType::Minus => UnaryOp::Negate,
+        _ => panic!("unary rule used on a token that isn't a unary operator"),
+    };
+    let span = parser.previous.span;
+
+    let operand = Box::new(parser.parse_precedence(Precedence::Unary));
+    Expr {
+        kind: ExprKind::Unary { op, operand },
+        span,
+    }
+}
+
+fn binary(parser: &mut Parser, left: Expr, _can_assign: bool) -> Expr {
+    let operator_type = parser.previous.t_type.clone();
+    let span = parser.previous.span;
+
+    let rule = get_rule(&operator_type);
+    let right = parser.parse_precedence(rule.precedence.next());
+
+    let op = match operator_type {
+        TokenType::BangEqual => BinaryOp::NotEqual,
+        TokenType::EqualEqual => BinaryOp::Equal,
+        TokenType::Greater => BinaryOp::Greater,
+        TokenType::GreaterEqual => BinaryOp::GreaterEqual,
+        TokenType::Less => BinaryOp::Less,
+        TokenType::LessEqual => BinaryOp::LessEqual,
+        TokenType::Plus => BinaryOp::Add,
+        TokenType::Minus => BinaryOp::Subtract,
+        TokenType::Star => BinaryOp::Multiply,
+        TokenType::Slash => BinaryOp::Divide,
+        TokenType::SlashSlash => BinaryOp::FloorDivide,
+        TokenType::Percent => BinaryOp::Modulo,
+        _ => panic!("binary rule used on a token that isn't a binary operator"),
+    };
+
+    Expr {
+        kind: ExprKind::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        },
+        span,
+    }
+}
+
+fn logical(parser: &mut Parser, left: Expr, op: LogicalOp, precedence: Precedence) -> Expr {
+    let span = parser.previous.span;
+    let right = parser.parse_precedence(precedence);
+
+    Expr {
+        kind: ExprKind::Logical {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        },
+        span,
+    }
+}
+
+fn and(parser: &mut Parser, left: Expr, _can_assign: bool) -> Expr {
+    return logical(parser, left, LogicalOp::And, Precedence::And);
+}
+
+fn or(parser: &mut Parser, left: Expr, _can_assign: bool) -> Expr {
+    return logical(parser, left, LogicalOp::Or, Precedence::Or);
+}
+
+// parses the whole source, every syntax error is reported before giving up
+pub fn parse(source: String) -> Result<Program, Vec<CompileDiagnostic>> {
+    let mut parser = Parser::init(source);
+
+    parser.advance();
+    let mut statements = Vec::new();
+    while !parser.match_token(TokenType::Eof) {
+        statements.push(parser.declaration());
+    }
+
+    if !parser.diagnostics.is_empty() {
+        return Err(parser.diagnostics);
+    }
+
+    Ok(Program {
+        statements,
+        end: parser.previous.span,
+    })
+}
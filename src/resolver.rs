@@ -0,0 +1,470 @@
+use crate::{
+    ast::{
+        Class, Expr, ExprKind, Function, FunctionType, Name, Program, Resolution, Stmt, StmtKind,
+        Variable,
+    },
+    chunk::BYTE_OPERAND_MAX,
+    error::{CompileDiagnostic, ErrorToken},
+    values::UpvalueDescriptor,
+};
+
+// works out where every variable lives, the same way compiler.rs does while it parses
+// the scoping rules and error messages have to stay in step with it, both pipelines must agree on slots
+
+struct Local {
+    name: String,
+    // None while the initializer is being resolved, so `var a = a;` can be caught
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+struct FunctionState {
+    function_type: FunctionType,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    upvalues: Vec<UpvalueDescriptor>,
+}
+
+impl FunctionState {
+    fn init(function_type: FunctionType) -> Self {
+        // slot zero is the callee, or the receiver for methods
+        let slot_zero = match function_type {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            FunctionType::Function | FunctionType::Script => "",
+        };
+
+        FunctionState {
+            function_type,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: Some(0),
+                is_captured: false,
+            }],
+            scope_depth: 0,
+            upvalues: Vec::new(),
+        }
+    }
+}
+
+struct ClassState {
+    has_superclass: bool,
+}
+
+pub struct Resolver {
+    states: Vec<FunctionState>,
+    classes: Vec<ClassState>,
+    diagnostics: Vec<CompileDiagnostic>,
+}
+
+impl Resolver {
+    fn init() -> Self {
+        Resolver {
+            states: vec![FunctionState::init(FunctionType::Script)],
+            classes: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn current(&self) -> &FunctionState {
+        return self.states.last().unwrap();
+    }
+
+    fn current_mut(&mut self) -> &mut FunctionState {
+        return self.states.last_mut().unwrap();
+    }
+
+    fn error(&mut self, name: &Name, message: &str) {
+        self.diagnostics.push(CompileDiagnostic {
+            message: message.to_string(),
+            line: name.span.line,
+            span: name.span,
+            token: ErrorToken::Lexeme(name.lexeme.clone()),
+        });
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_mut().scope_depth += 1;
+    }
+
+    // drops the scope's locals, handing back which of them were captured in declaration order
+    fn end_scope(&mut self) -> Vec<bool> {
+        let state = self.current_mut();
+        state.scope_depth -= 1;
+
+        let mut captured = Vec::new();
+        while let Some(local) = state.locals.last() {
+            if local.depth.is_none_or(|depth| depth <= state.scope_depth) {
+                break;
+            }
+
+            captured.push(local.is_captured);
+            state.locals.pop();
+        }
+
+        captured.reverse();
+        return captured;
+    }
+
+    fn add_local(&mut self, name: &Name) -> usize {
+        // slots are one byte operands
+        if self.current().locals.len() > BYTE_OPERAND_MAX {
+            self.error(name, "Too many local variables in function.");
+            return 0;
+        }
+
+        self.current_mut().locals.push(Local {
+            name: name.lexeme.clone(),
+            depth: None,
+            is_captured: false,
+        });
+        return self.current().locals.len() - 1;
+    }
+
+    fn declare(&mut self, variable: &mut Variable) {
+        let scope_depth = self.current().scope_depth;
+        if scope_depth == 0 {
+            variable.resolution = Resolution::Global;
+            return;
+        }
+
+        let duplicate = self
+            .current()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= scope_depth))
+            .any(|local| local.name == variable.name.lexeme);
+
+        if duplicate {
+            self.error(
+                &variable.name,
+                "Already a variable with this name in this scope.",
+            );
+        }
+
+        let slot = self.add_local(&variable.name);
+        variable.resolution = Resolution::Local(slot);
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.current_mut();
+        if state.scope_depth == 0 {
+            return;
+        }
+
+        let depth = state.scope_depth;
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    fn resolve_local(&mut self, state_index: usize, name: &Name) -> Option<usize> {
+        let found = self.states[state_index]
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name == name.lexeme)
+            .map(|(slot, local)| (slot, local.depth.is_none()));
+
+        let (slot, uninitialized) = found?;
+        if uninitialized {
+            self.error(name, "Can't read local variable in its own initializer.");
+        }
+
+        return Some(slot);
+    }
+
+    fn add_upvalue(
+        &mut self,
+        state_index: usize,
+        name: &Name,
+        index: usize,
+        is_local: bool,
+    ) -> usize {
+        let upvalues = &self.states[state_index].upvalues;
+        if let Some(existing) = upvalues
+            .iter()
+            .position(|upvalue| upvalue.index == index && upvalue.is_local == is_local)
+        {
+            return existing;
+        }
+
+        if upvalues.len() > BYTE_OPERAND_MAX {
+            self.error(name, "Too many closure variables in function.");
+            return 0;
+        }
+
+        let upvalues = &mut self.states[state_index].upvalues;
+        upvalues.push(UpvalueDescriptor { is_local, index });
+        return upvalues.len() - 1;
+    }
+
+    fn resolve_upvalue(&mut self, state_index: usize, name: &Name) -> Option<usize> {
+        if state_index == 0 {
+            return None;
+        }
+
+        if let Some(local) = self.resolve_local(state_index - 1, name) {
+            self.states[state_index - 1].locals[local].is_captured = true;
+            return Some(self.add_upvalue(state_index, name, local, true));
+        }
+
+        if let Some(upvalue) = self.resolve_upvalue(state_index - 1, name) {
+            return Some(self.add_upvalue(state_index, name, upvalue, false));
+        }
+
+        return None;
+    }
+
+    fn resolve_variable(&mut self, variable: &mut Variable) {
+        let state_index = self.states.len() - 1;
+        variable.resolution = if let Some(slot) = self.resolve_local(state_index, &variable.name) {
+            Resolution::Local(slot)
+        } else if let Some(index) = self.resolve_upvalue(state_index, &variable.name) {
+            Resolution::Upvalue(index)
+        } else {
+            Resolution::Global
+        };
+    }
+
+    fn function(&mut self, function: &mut Function) {
+        self.states
+            .push(FunctionState::init(function.function_type));
+        // never closed, like in compiler.rs
+        self.begin_scope();
+
+        for param in function.params.iter_mut() {
+            self.declare(param);
+            self.mark_initialized();
+        }
+        for statement in function.body.iter_mut() {
+            self.statement(statement);
+        }
+
+        let state = self.states.pop().unwrap();
+        function.upvalues = state.upvalues;
+    }
+
+    fn class(&mut self, class: &mut Class) {
+        self.declare(&mut class.name);
+        self.mark_initialized();
+        self.classes.push(ClassState {
+            has_superclass: false,
+        });
+
+        if let Some(superclass) = class.superclass.as_mut() {
+            self.resolve_variable(superclass);
+            if superclass.name.lexeme == class.name.name.lexeme {
+                self.error(&superclass.name, "A class can't inherit from itself.");
+            }
+
+            // the hidden `super` local gets a scope of its own around the methods
+            self.begin_scope();
+            self.add_local(&Name {
+                lexeme: "super".to_string(),
+                span: superclass.name.span,
+            });
+            self.mark_initialized();
+            self.classes.last_mut().unwrap().has_superclass = true;
+        }
+
+        for method in class.methods.iter_mut() {
+            self.function(method);
+        }
+
+        if self.classes.last().unwrap().has_superclass {
+            let captured = self.end_scope();
+            class.super_captured = captured.first().copied().unwrap_or(false);
+        }
+
+        self.classes.pop();
+    }
+
+    fn statement(&mut self, statement: &mut Stmt) {
+        match &mut statement.kind {
+            StmtKind::Expression(expression) | StmtKind::Print(expression) => {
+                self.expression(expression)
+            }
+            StmtKind::Var { name, initializer } => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                self.mark_initialized();
+            }
+            StmtKind::Block {
+                statements,
+                captured,
+                ..
+            } => {
+                self.begin_scope();
+                for statement in statements.iter_mut() {
+                    self.statement(statement);
+                }
+                *captured = self.end_scope();
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            StmtKind::While {
+                condition, body, ..
+            } => {
+                self.expression(condition);
+                self.statement(body);
+            }
+            StmtKind::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                captured,
+                ..
+            } => {
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.statement(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.expression(condition);
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                }
+                self.statement(body);
+                *captured = self.end_scope();
+            }
+            StmtKind::Return { keyword, value } => {
+                if self.current().function_type == FunctionType::Script {
+                    self.error(keyword, "Can't return from top-level code.");
+                }
+
+                if let Some(value) = value {
+                    if self.current().function_type == FunctionType::Initializer {
+                        self.error(keyword, "Can't return a value from an initializer.");
+                    }
+                    self.expression(value);
+                }
+            }
+            StmtKind::Fun { name, function } => {
+                self.declare(name);
+                // functions can refer to themselves
+                self.mark_initialized();
+                self.function(function);
+            }
+            StmtKind::Class(class) => self.class(class),
+        }
+    }
+
+    fn check_super(&mut self, keyword: &Name) {
+        match self.classes.last() {
+            None => self.error(keyword, "Can't use 'super' outside of a class."),
+            Some(class) if !class.has_superclass => {
+                self.error(keyword, "Can't use 'super' in a class with no superclass.")
+            }
+            _ => {}
+        }
+    }
+
+    fn expression(&mut self, expression: &mut Expr) {
+        match &mut expression.kind {
+            ExprKind::Literal(_) => {}
+            ExprKind::Grouping(inner) => self.expression(inner),
+            ExprKind::Unary { operand, .. } => self.expression(operand),
+            ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExprKind::Variable(variable) => self.resolve_variable(variable),
+            ExprKind::Assign { target, value } => {
+                self.resolve_variable(target);
+                self.expression(value);
+            }
+            ExprKind::Call { callee, arguments } => {
+                // a super call loads the receiver, then the arguments, then the superclass
+                if let ExprKind::Super {
+                    this, superclass, ..
+                } = &mut callee.kind
+                {
+                    self.check_super(&superclass.name);
+                    self.resolve_variable(this);
+                    for argument in arguments.iter_mut() {
+                        self.expression(argument);
+                    }
+                    self.resolve_variable(superclass);
+                    return;
+                }
+
+                self.expression(callee);
+                for argument in arguments.iter_mut() {
+                    self.expression(argument);
+                }
+            }
+            ExprKind::Get { object, .. } => self.expression(object),
+            ExprKind::Set { object, value, .. } => {
+                self.expression(object);
+                self.expression(value);
+            }
+            ExprKind::This(this) => {
+                if self.classes.is_empty() {
+                    self.error(&this.name, "Can't use 'this' outside of a class.");
+                    return;
+                }
+                self.resolve_variable(this);
+            }
+            ExprKind::Super {
+                this, superclass, ..
+            } => {
+                self.check_super(&superclass.name);
+                self.resolve_variable(this);
+                self.resolve_variable(superclass);
+            }
+            ExprKind::Interpolation { expressions, .. } | ExprKind::List(expressions) => {
+                for expression in expressions.iter_mut() {
+                    self.expression(expression);
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries.iter_mut() {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+        }
+    }
+}
+
+// fills in every variable's resolution, the upvalues each function captures and which scoped locals need closing
+pub fn resolve(program: &mut Program) -> Result<(), Vec<CompileDiagnostic>> {
+    let mut resolver = Resolver::init();
+    for statement in program.statements.iter_mut() {
+        resolver.statement(statement);
+    }
+
+    if !resolver.diagnostics.is_empty() {
+        return Err(resolver.diagnostics);
+    }
+    return Ok(());
+}
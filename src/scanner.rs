@@ -1,308 +1,573 @@
-#[derive(Debug, PartialEq, Clone)]
-pub enum TokenType {
-    LeftParen,
-    RightParen,
-    LeftBrace,
-    RightBrace,
-    Comma,
-    Dot,
-    Minus,
-    Plus,
-    SemiColon,
-    Slash,
-    Star,
-    Bang,
-    BangEqual,
-    Equal,
-    EqualEqual,
-    Greater,
-    GreaterEqual,
-    Less,
-    LessEqual,
-    Identifier,
-    String,
-    Number,
-    And,
-    Class,
-    Else,
-    False,
-    For,
-    Fun,
-    If,
-    Nil,
-    Or,
-    Print,
-    Return,
-    Super,
-    This,
-    True,
-    Var,
-    While,
-    Error,
-    Eof,
-}
-
-#[derive(Debug, Clone)]
-pub struct Token {
-    pub t_type: TokenType,
-    pub start: usize,
-    pub content: String,
-    pub length: usize,
-    pub line: i32,
-}
-
-pub struct Scanner {
-    source: Box<Vec<char>>,
-    start: usize,
-    current: usize,
-    line: i32,
-}
-
-impl Scanner {
-    pub fn init(source: String) -> Self {
-        let mut scanner = Scanner {
-            source: Box::new(source.chars().collect()),
-            start: 0,
-            current: 0,
-            line: 1,
-        };
-
-        scanner.source.push('\0');
-        return scanner;
-    }
-
-    fn error_token(&self, message: &str) -> Token {
-        Token {
-            t_type: TokenType::Error,
-            start: 0,
-            length: message.len(),
-            content: message.to_string(),
-            line: self.line,
-        }
-    }
-
-    fn match_token(&mut self, expected: char) -> bool {
-        if self.at_end() {
-            return false;
-        }
-
-        if self.source[self.current] != expected {
-            return false;
-        }
-
-        self.current += 1;
-        return true;
-    }
-
-    fn make_token(&self, token_type: TokenType) -> Token {
-        let content = if token_type == TokenType::Eof {
-            "".to_string()
-        } else {
-            self.source[self.start..self.current].iter().collect()
-        };
-
-        Token {
-            t_type: token_type,
-            start: self.start,
-            length: self.current - self.start,
-            content,
-            line: self.line,
-        }
-    }
-
-    fn at_end(&self) -> bool {
-        return self.source[self.current] == '\0';
-    }
-
-    fn advance(&mut self) -> char {
-        self.current += 1;
-        return self.source[self.current - 1];
-    }
-
-    fn peak(&self) -> char {
-        return self.source[self.current];
-    }
-
-    fn peak_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-
-        return self.source[self.current + 1];
-    }
-
-    fn skip_whitespace(&mut self) {
-        loop {
-            let c = self.peak();
-            match c {
-                ' ' | '\r' | '\t' => _ = self.advance(),
-                '\n' => {
-                    self.line += 1;
-                    _ = self.advance();
-                }
-                '/' => {
-                    if self.peak_next() == '/' {
-                        while self.peak() != '\n' && !self.at_end() {
-                            _ = self.advance();
-                        }
-                    } else {
-                        break;
-                    }
-                }
-                _ => {
-                    break;
-                }
-            }
-        }
-    }
-
-    fn string(&mut self) -> Token {
-        while self.peak() != '"' && !self.at_end() {
-            if self.peak() == '\n' {
-                self.line += 1;
-            }
-            self.advance();
-        }
-
-        if self.at_end() {
-            return self.error_token("Unterminated string.");
-        }
-
-        self.advance();
-        return self.make_token(TokenType::String);
-    }
-
-    fn number(&mut self) -> Token {
-        while self.peak().is_digit(10) {
-            self.advance();
-        }
-
-        if self.peak() == '.' && self.peak_next().is_digit(10) {
-            self.advance();
-
-            while self.peak().is_digit(10) {
-                self.advance();
-            }
-        }
-
-        return self.make_token(TokenType::Number);
-    }
-
-    fn match_keyword(&self, start: usize, rest: &str, token: TokenType) -> TokenType {
-        if self.current - self.start != start + rest.len() {
-            return TokenType::Identifier;
-        }
-
-        for (i, c) in rest.chars().enumerate() {
-            if self.source[self.start + start + i] != c {
-                return TokenType::Identifier;
-            }
-        }
-
-        return token;
-    }
-
-    fn identifier_type(&self) -> TokenType {
-        let c = self.source[self.start];
-        match c {
-            'a' => self.match_keyword(1, "nd", TokenType::And),
-            'c' => self.match_keyword(1, "lass", TokenType::Class),
-            'e' => self.match_keyword(1, "lse", TokenType::Else),
-            'f' => {
-                if self.current - self.start > 1 {
-                    match self.source[self.start + 1] {
-                        'a' => self.match_keyword(2, "lse", TokenType::False),
-                        'o' => self.match_keyword(2, "r", TokenType::For),
-                        'u' => self.match_keyword(2, "n", TokenType::Fun),
-                        _ => TokenType::Identifier,
-                    }
-                } else {
-                    TokenType::Identifier
-                }
-            }
-            'i' => self.match_keyword(1, "f", TokenType::If),
-            'n' => self.match_keyword(1, "il", TokenType::Nil),
-            'o' => self.match_keyword(1, "r", TokenType::Or),
-            'p' => self.match_keyword(1, "rint", TokenType::Print),
-            'r' => self.match_keyword(1, "eturn", TokenType::Return),
-            's' => self.match_keyword(1, "uper", TokenType::Super),
-            't' => {
-                if self.current - self.start > 1 {
-                    match self.source[self.start + 1] {
-                        'h' => self.match_keyword(2, "is", TokenType::This),
-                        'r' => self.match_keyword(2, "ue", TokenType::True),
-                        _ => TokenType::Identifier,
-                    }
-                } else {
-                    TokenType::Identifier
-                }
-            }
-            'v' => self.match_keyword(1, "ar", TokenType::Var),
-            'w' => self.match_keyword(1, "hile", TokenType::While),
-            _ => TokenType::Identifier,
-        }
-    }
-
-    fn identifier(&mut self) -> Token {
-        while self.peak().is_alphabetic() || self.peak().is_digit(10) {
-            self.advance();
-        }
-
-        return self.make_token(self.identifier_type());
-    }
-
-    pub fn scan_token(&mut self) -> Token {
-        self.skip_whitespace();
-        self.start = self.current;
-
-        if self.at_end() {
-            return self.make_token(TokenType::Eof);
-        }
-
-        match self.advance() {
-            '(' => return self.make_token(TokenType::LeftParen),
-            ')' => return self.make_token(TokenType::RightParen),
-            '{' => return self.make_token(TokenType::LeftBrace),
-            '}' => return self.make_token(TokenType::RightBrace),
-            ',' => return self.make_token(TokenType::Comma),
-            '.' => return self.make_token(TokenType::Dot),
-            ';' => return self.make_token(TokenType::SemiColon),
-            '-' => return self.make_token(TokenType::Minus),
-            '+' => return self.make_token(TokenType::Plus),
-            '*' => return self.make_token(TokenType::Star),
-            '/' => return self.make_token(TokenType::Slash),
-            '!' => {
-                if self.match_token('=') {
-                    return self.make_token(TokenType::BangEqual);
-                } else {
-                    return self.make_token(TokenType::Bang);
-                }
-            }
-            '=' => {
-                if self.match_token('=') {
-                    return self.make_token(TokenType::EqualEqual);
-                } else {
-                    return self.make_token(TokenType::Equal);
-                }
-            }
-            '<' => {
-                if self.match_token('=') {
-                    return self.make_token(TokenType::LessEqual);
-                } else {
-                    return self.make_token(TokenType::Less);
-                }
-            }
-            '>' => {
-                if self.match_token('=') {
-                    return self.make_token(TokenType::GreaterEqual);
-                } else {
-                    return self.make_token(TokenType::Greater);
-                }
-            }
-            '"' => return self.string(),
-            c if c.is_digit(10) => return self.number(),
-            c if c.is_alphabetic() => return self.identifier(),
-            _ => return self.error_token("Unexpected character."),
-        }
-    }
-}
+use crate::values::INT_MAX;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TokenType {
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Colon,
+    Dot,
+    Minus,
+    Plus,
+    SemiColon,
+    Slash,
+    SlashSlash,
+    Percent,
+    Star,
+    Bang,
+    BangEqual,
+    Equal,
+    EqualEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Identifier,
+    String,
+    Number,
+    // a number without a decimal point
+    Int,
+    // `"text ${`, the text before an interpolated expression
+    Interpolation,
+    // `} text"`, the text after the last interpolated expression
+    InterpolationEnd,
+    And,
+    Class,
+    Else,
+    False,
+    For,
+    Fun,
+    If,
+    Nil,
+    Or,
+    Print,
+    Return,
+    Super,
+    This,
+    True,
+    Var,
+    While,
+    Error,
+    Eof,
+}
+
+// where a piece of source starts and how many characters it covers, columns count from one
+// a span never crosses lines on its own, anything longer gets clipped at the end of its first line when shown
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: i32,
+    pub column: usize,
+    pub length: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub t_type: TokenType,
+    pub start: usize,
+    pub content: String,
+    pub length: usize,
+    // the line the token ends on, like the book, the span has the line it starts on
+    pub line: i32,
+    pub span: Span,
+    // what a string token stands for, quotes stripped and escapes decoded, content keeps the lexeme for errors
+    pub literal: Option<String>,
+}
+
+pub struct Scanner {
+    source: Vec<char>,
+    start: usize,
+    current: usize,
+    line: i32,
+    // index of the first character on the current line, columns are measured from it
+    line_start: usize,
+    start_line: i32,
+    start_column: usize,
+    // one entry per `${` we're inside, counting the braces opened within it
+    // the `}` that brings a count back below zero goes back to scanning the string
+    interpolations: Vec<usize>,
+    // where the last token ended, if it can end an operand
+    // `//` straight after one, with nothing in between, is floor division rather than a comment
+    operand_end: Option<usize>,
+}
+
+impl Scanner {
+    pub fn init(source: String) -> Self {
+        let mut scanner = Scanner {
+            source: source.chars().collect(),
+            start: 0,
+            current: 0,
+            line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            interpolations: Vec::new(),
+            operand_end: None,
+        };
+
+        scanner.source.push('\0');
+        return scanner;
+    }
+
+    // covers everything scanned for the current token
+    fn span(&self) -> Span {
+        return Span {
+            line: self.start_line,
+            column: self.start_column,
+            length: self.current - self.start,
+        };
+    }
+
+    fn error_token(&self, message: &str) -> Token {
+        return self.error_token_at(message, self.span());
+    }
+
+    // for errors inside a token, like a bad escape in a string, so they point at the part that's wrong
+    fn error_token_at(&self, message: &str, span: Span) -> Token {
+        Token {
+            t_type: TokenType::Error,
+            start: 0,
+            length: message.len(),
+            content: message.to_string(),
+            line: span.line,
+            span,
+            literal: None,
+        }
+    }
+
+    fn match_token(&mut self, expected: char) -> bool {
+        if self.at_end() {
+            return false;
+        }
+
+        if self.source[self.current] != expected {
+            return false;
+        }
+
+        self.current += 1;
+        return true;
+    }
+
+    fn make_token(&self, token_type: TokenType) -> Token {
+        let content = if token_type == TokenType::Eof {
+            "".to_string()
+        } else {
+            self.source[self.start..self.current].iter().collect()
+        };
+
+        Token {
+            t_type: token_type,
+            start: self.start,
+            length: self.current - self.start,
+            content,
+            line: self.line,
+            span: self.span(),
+            literal: None,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        return self.source[self.current] == '\0';
+    }
+
+    fn advance(&mut self) -> char {
+        self.current += 1;
+        return self.source[self.current - 1];
+    }
+
+    fn peak(&self) -> char {
+        return self.source[self.current];
+    }
+
+    fn peak_next(&self) -> char {
+        if self.current + 1 >= self.source.len() {
+            return '\0';
+        }
+
+        return self.source[self.current + 1];
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            let c = self.peak();
+            match c {
+                ' ' | '\r' | '\t' => _ = self.advance(),
+                '\n' => {
+                    self.line += 1;
+                    _ = self.advance();
+                    self.line_start = self.current;
+                }
+                '/' if self.peak_next() == '/' && self.operand_end != Some(self.current) => {
+                    while self.peak() != '\n' && !self.at_end() {
+                        _ = self.advance();
+                    }
+                }
+                _ => {
+                    break;
+                }
+            }
+        }
+    }
+
+    // resumed is set when we're picking the string back up after an interpolated expression
+    fn string(&mut self, resumed: bool) -> Token {
+        let mut literal = String::new();
+        // the rest of the string is still scanned after a bad escape, so the error doesn't spill into the code after it
+        let mut escape_error = None;
+
+        while self.peak() != '"' && !self.at_end() {
+            match self.advance() {
+                '\\' => match self.escape() {
+                    Ok(c) => literal.push(c),
+                    Err(error) => {
+                        escape_error.get_or_insert(error);
+                    }
+                },
+                '\n' => {
+                    self.line += 1;
+                    self.line_start = self.current;
+                    literal.push('\n');
+                }
+                '$' if self.peak() == '{' => {
+                    self.advance();
+                    self.interpolations.push(0);
+                    return self.string_token(TokenType::Interpolation, literal, escape_error);
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if self.at_end() {
+            return self.error_token("Unterminated string.");
+        }
+
+        self.advance();
+        let token_type = if resumed {
+            TokenType::InterpolationEnd
+        } else {
+            TokenType::String
+        };
+        return self.string_token(token_type, literal, escape_error);
+    }
+
+    fn string_token(
+        &self,
+        token_type: TokenType,
+        literal: String,
+        escape_error: Option<(&str, Span)>,
+    ) -> Token {
+        if let Some((message, span)) = escape_error {
+            return self.error_token_at(message, span);
+        }
+
+        let mut token = self.make_token(token_type);
+        token.literal = Some(literal);
+        return token;
+    }
+
+    // from the backslash at start up to what has been scanned of the escape so far
+    fn escape_span(&self, start: usize) -> Span {
+        return Span {
+            line: self.line,
+            column: start - self.line_start + 1,
+            length: self.current - start,
+        };
+    }
+
+    // decodes the escape after a backslash, the error comes with the span of the whole escape
+    fn escape(&mut self) -> Result<char, (&'static str, Span)> {
+        let start = self.current - 1;
+
+        // a backslash right before the end leaves the string unterminated, which gets reported instead
+        if self.at_end() {
+            return Ok('\\');
+        }
+
+        let c = match self.advance() {
+            'n' => '\n',
+            't' => '\t',
+            '"' => '"',
+            '\\' => '\\',
+            '$' => '$',
+            '0' => '\0',
+            'u' => return self.unicode_escape(start),
+            '\n' => {
+                // don't lose track of lines, the error still points at the backslash
+                self.current -= 1;
+                return Err(("Invalid escape sequence.", self.escape_span(start)));
+            }
+            _ => return Err(("Invalid escape sequence.", self.escape_span(start))),
+        };
+
+        return Ok(c);
+    }
+
+    // `\u{...}` takes one to six hex digits naming a unicode scalar value
+    fn unicode_escape(&mut self, start: usize) -> Result<char, (&'static str, Span)> {
+        if !self.match_token('{') {
+            return Err(("Invalid unicode escape.", self.escape_span(start)));
+        }
+
+        let digits_start = self.current;
+        while self.peak().is_ascii_hexdigit() {
+            self.advance();
+        }
+        let digits: String = self.source[digits_start..self.current].iter().collect();
+
+        if !self.match_token('}') {
+            let message = match self.peak() {
+                '"' | '\n' | '\0' => "Unterminated unicode escape.",
+                _ => "Invalid unicode escape.",
+            };
+            return Err((message, self.escape_span(start)));
+        }
+
+        if digits.is_empty() || digits.len() > 6 {
+            return Err(("Invalid unicode escape.", self.escape_span(start)));
+        }
+
+        // surrogates and anything past U+10FFFF aren't characters
+        return u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or(("Invalid unicode escape.", self.escape_span(start)));
+    }
+
+    fn number(&mut self) -> Token {
+        while self.peak().is_ascii_digit() {
+            self.advance();
+        }
+
+        if self.peak() == '.' && self.peak_next().is_ascii_digit() {
+            self.advance();
+
+            while self.peak().is_ascii_digit() {
+                self.advance();
+            }
+
+            return self.make_token(TokenType::Number);
+        }
+
+        // checked here so the compiler can parse every int token without failing
+        // literals too wide for an int are still numbers, they just become floats
+        let digits: String = self.source[self.start..self.current].iter().collect();
+        match digits.parse::<i64>() {
+            Ok(int) if (0..=INT_MAX).contains(&int) => return self.make_token(TokenType::Int),
+            _ => return self.make_token(TokenType::Number),
+        }
+    }
+
+    fn match_keyword(&self, start: usize, rest: &str, token: TokenType) -> TokenType {
+        if self.current - self.start != start + rest.len() {
+            return TokenType::Identifier;
+        }
+
+        for (i, c) in rest.chars().enumerate() {
+            if self.source[self.start + start + i] != c {
+                return TokenType::Identifier;
+            }
+        }
+
+        return token;
+    }
+
+    fn identifier_type(&self) -> TokenType {
+        let c = self.source[self.start];
+        match c {
+            'a' => self.match_keyword(1, "nd", TokenType::And),
+            'c' => self.match_keyword(1, "lass", TokenType::Class),
+            'e' => self.match_keyword(1, "lse", TokenType::Else),
+            'f' => {
+                if self.current - self.start > 1 {
+                    match self.source[self.start + 1] {
+                        'a' => self.match_keyword(2, "lse", TokenType::False),
+                        'o' => self.match_keyword(2, "r", TokenType::For),
+                        'u' => self.match_keyword(2, "n", TokenType::Fun),
+                        _ => TokenType::Identifier,
+                    }
+                } else {
+                    TokenType::Identifier
+                }
+            }
+            'i' => self.match_keyword(1, "f", TokenType::If),
+            'n' => self.match_keyword(1, "il", TokenType::Nil),
+            'o' => self.match_keyword(1, "r", TokenType::Or),
+            'p' => self.match_keyword(1, "rint", TokenType::Print),
+            'r' => self.match_keyword(1, "eturn", TokenType::Return),
+            's' => self.match_keyword(1, "uper", TokenType::Super),
+            't' => {
+                if self.current - self.start > 1 {
+                    match self.source[self.start + 1] {
+                        'h' => self.match_keyword(2, "is", TokenType::This),
+                        'r' => self.match_keyword(2, "ue", TokenType::True),
+                        _ => TokenType::Identifier,
+                    }
+                } else {
+                    TokenType::Identifier
+                }
+            }
+            'v' => self.match_keyword(1, "ar", TokenType::Var),
+            'w' => self.match_keyword(1, "hile", TokenType::While),
+            _ => TokenType::Identifier,
+        }
+    }
+
+    fn identifier(&mut self) -> Token {
+        while self.peak().is_alphabetic() || self.peak().is_ascii_digit() {
+            self.advance();
+        }
+
+        return self.make_token(self.identifier_type());
+    }
+
+    // a statement starting with `{` is a block unless the brace opens a map literal
+    // it's a map when a `:` turns up before whatever ends the first statement of a block could, so `{}` stays a block
+    // called with the `{` just scanned, everything is put back afterwards so scanning carries on from the brace
+    pub fn brace_opens_map(&mut self) -> bool {
+        let (start, current, line, line_start) =
+            (self.start, self.current, self.line, self.line_start);
+        let (start_line, start_column) = (self.start_line, self.start_column);
+        let (interpolations, operand_end) = (self.interpolations.clone(), self.operand_end);
+
+        let mut depth = 0;
+        let opens_map = loop {
+            match self.scan_token().t_type {
+                TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => depth += 1,
+                TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace
+                    if depth > 0 =>
+                {
+                    depth -= 1
+                }
+                TokenType::Colon if depth == 0 => break true,
+                TokenType::RightParen
+                | TokenType::RightBracket
+                | TokenType::RightBrace
+                | TokenType::SemiColon
+                | TokenType::Var
+                | TokenType::Fun
+                | TokenType::Class
+                | TokenType::Print
+                | TokenType::If
+                | TokenType::While
+                | TokenType::For
+                | TokenType::Return
+                    if depth == 0 =>
+                {
+                    break false
+                }
+                TokenType::Error | TokenType::Eof => break false,
+                _ => {}
+            }
+        };
+
+        (self.start, self.current, self.line, self.line_start) = (start, current, line, line_start);
+        (self.start_line, self.start_column) = (start_line, start_column);
+        (self.interpolations, self.operand_end) = (interpolations, operand_end);
+        return opens_map;
+    }
+
+    pub fn scan_token(&mut self) -> Token {
+        let token = self.next_token();
+        let ends_operand = matches!(
+            token.t_type,
+            TokenType::Number
+                | TokenType::Int
+                | TokenType::String
+                | TokenType::InterpolationEnd
+                | TokenType::Identifier
+                | TokenType::True
+                | TokenType::False
+                | TokenType::Nil
+                | TokenType::This
+                | TokenType::RightParen
+                | TokenType::RightBracket
+        );
+        self.operand_end = ends_operand.then_some(self.current);
+        return token;
+    }
+
+    fn next_token(&mut self) -> Token {
+        self.skip_whitespace();
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.start - self.line_start + 1;
+
+        if self.at_end() {
+            return self.make_token(TokenType::Eof);
+        }
+
+        match self.advance() {
+            '(' => return self.make_token(TokenType::LeftParen),
+            ')' => return self.make_token(TokenType::RightParen),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                return self.make_token(TokenType::LeftBrace);
+            }
+            '}' => {
+                match self.interpolations.last_mut() {
+                    Some(0) => {
+                        self.interpolations.pop();
+                        return self.string(true);
+                    }
+                    Some(depth) => *depth -= 1,
+                    None => {}
+                }
+                return self.make_token(TokenType::RightBrace);
+            }
+            '[' => return self.make_token(TokenType::LeftBracket),
+            ']' => return self.make_token(TokenType::RightBracket),
+            ',' => return self.make_token(TokenType::Comma),
+            ':' => return self.make_token(TokenType::Colon),
+            '.' => return self.make_token(TokenType::Dot),
+            ';' => return self.make_token(TokenType::SemiColon),
+            '-' => return self.make_token(TokenType::Minus),
+            '+' => return self.make_token(TokenType::Plus),
+            '*' => return self.make_token(TokenType::Star),
+            '/' => {
+                // only reached when skip_whitespace left it alone, so this `//` is right after an operand
+                if self.match_token('/') {
+                    return self.make_token(TokenType::SlashSlash);
+                }
+                return self.make_token(TokenType::Slash);
+            }
+            '%' => return self.make_token(TokenType::Percent),
+            '!' => {
+                if self.match_token('=') {
+                    return self.make_token(TokenType::BangEqual);
+                } else {
+                    return self.make_token(TokenType::Bang);
+                }
+            }
+            '=' => {
+                if self.match_token('=') {
+                    return self.make_token(TokenType::EqualEqual);
+                } else {
+                    return self.make_token(TokenType::Equal);
+                }
+            }
+            '<' => {
+                if self.match_token('=') {
+                    return self.make_token(TokenType::LessEqual);
+                } else {
+                    return self.make_token(TokenType::Less);
+                }
+            }
+            '>' => {
+                if self.match_token('=') {
+                    return self.make_token(TokenType::GreaterEqual);
+                } else {
+                    return self.make_token(TokenType::Greater);
+                }
+            }
+            '"' => return self.string(false),
+            c if c.is_ascii_digit() => return self.number(),
+            c if c.is_alphabetic() => return self.identifier(),
+            _ => return self.error_token("Unexpected character."),
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+
+use crate::{
+    chunk::{Chunk, OpCode, SpanRun},
+    memory::Heap,
+    scanner::Span,
+    values::{ObjFunction, ObjectType, UpvalueDescriptor, Value, INT_MAX, INT_MIN, INT_RANGE_NOTE},
+    vm::VM,
+};
+
+// the .loxc format, everything little endian
+//   header    "LOXC" then a u16 format version
+//   chunk     u32 code length and the code bytes
+//             u32 span run count, each run an i32 line, u32 column, u32 length and a u32 byte count
+//             u32 constant count, each constant a tag byte and its payload
+//   constants nil, bool (u8), number (f64 bits), int (i64), string (u32 length and utf8),
+//             function (name string or empty, u32 arity, u32 upvalue count with
+//             an is_local u8 and u32 index each, then the function's own chunk)
+const MAGIC: &[u8; 4] = b"LOXC";
+// bump whenever the encoding of instructions or the file layout changes
+pub const FORMAT_VERSION: u16 = 4;
+
+// nested functions are read recursively, a hostile file shouldn't be able to blow the rust stack
+const MAX_FUNCTION_DEPTH: usize = 256;
+
+const TAG_NIL: u8 = 0;
+const TAG_BOOL: u8 = 1;
+const TAG_NUMBER: u8 = 2;
+const TAG_STRING: u8 = 3;
+const TAG_FUNCTION: u8 = 4;
+const TAG_INT: u8 = 5;
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+fn write_string(out: &mut Vec<u8>, content: &str) {
+    write_u32(out, content.len());
+    out.extend_from_slice(content.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| "Unexpected end of bytecode file.".to_string())?;
+
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        return Ok(taken);
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        return Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()));
+    }
+
+    fn u32(&mut self) -> Result<usize, String> {
+        return Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize);
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        return Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()));
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        return Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()));
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        return Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()));
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.u32()?;
+        let bytes = self.take(length)?;
+        return String::from_utf8(bytes.to_vec())
+            .map_err(|_| "String constant is not valid utf-8.".to_string());
+    }
+}
+
+impl Chunk {
+    // the whole file, header included, for the top level script chunk
+    pub fn serialize(&self, heap: &Heap) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        self.write_body(&mut out, heap);
+        return out;
+    }
+
+    fn write_body(&self, out: &mut Vec<u8>, heap: &Heap) {
+        write_u32(out, self.code.len());
+        out.extend_from_slice(&self.code);
+
+        write_u32(out, self.spans.len());
+        for run in &self.spans {
+            out.extend_from_slice(&run.span.line.to_le_bytes());
+            write_u32(out, run.span.column);
+            write_u32(out, run.span.length);
+            write_u32(out, run.count);
+        }
+
+        write_u32(out, self.constants.values.len());
+        for constant in &self.constants.values {
+            if constant.is_nil() {
+                out.push(TAG_NIL);
+            } else if constant.is_bool() {
+                out.push(TAG_BOOL);
+                out.push(constant.as_bool() as u8);
+            } else if constant.is_number() {
+                out.push(TAG_NUMBER);
+                out.extend_from_slice(&constant.as_number().to_le_bytes());
+            } else if constant.is_int() {
+                out.push(TAG_INT);
+                out.extend_from_slice(&constant.as_int().to_le_bytes());
+            } else if heap.is_string(constant) {
+                out.push(TAG_STRING);
+                write_string(out, &heap.as_string(constant.as_object()).content);
+            } else if heap.is_function(constant) {
+                let function = heap.as_function(constant.as_object());
+                out.push(TAG_FUNCTION);
+                write_string(out, function.name.as_deref().unwrap_or(""));
+                write_u32(out, function.arity);
+                write_u32(out, function.upvalues.len());
+                for upvalue in &function.upvalues {
+                    out.push(upvalue.is_local as u8);
+                    write_u32(out, upvalue.index);
+                }
+                function.chunk.write_body(out, heap);
+            } else {
+                panic!("the compiler only puts strings and functions in the constant pool");
+            }
+        }
+    }
+
+    // loads a file written by serialize, anything malformed is an Err rather than a panic later on
+    // strings and functions are allocated in the vm's heap and stay rooted until the vm takes the chunk
+    pub fn deserialize(bytes: &[u8], vm: &mut VM) -> Result<Chunk, String> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err("Not a rustlox bytecode file.".to_string());
+        }
+
+        let version = reader.u16()?;
+        if version != FORMAT_VERSION {
+            return Err(format!(
+                "Bytecode format version {} is not supported, expected {}.",
+                version, FORMAT_VERSION
+            ));
+        }
+
+        let mut chunk = Chunk::read_body(&mut reader, vm, 0, 0, 0);
+        if chunk.is_ok() && reader.position != bytes.len() {
+            chunk = Err("Trailing bytes after the end of the script.".to_string());
+        }
+
+        // nothing is going to run, so whatever did get loaded can be collected
+        if chunk.is_err() {
+            vm.compiler_roots.clear();
+        }
+        return chunk;
+    }
+
+    // arity and upvalue_count describe the function this chunk belongs to
+    fn read_body(
+        reader: &mut Reader,
+        vm: &mut VM,
+        arity: usize,
+        upvalue_count: usize,
+        depth: usize,
+    ) -> Result<Chunk, String> {
+        if depth > MAX_FUNCTION_DEPTH {
+            return Err("Functions are nested too deeply.".to_string());
+        }
+
+        let mut chunk = Chunk::init();
+        let code_length = reader.u32()?;
+        chunk.code = reader.take(code_length)?.to_vec();
+
+        let run_count = reader.u32()?;
+        let mut covered: usize = 0;
+        for _ in 0..run_count {
+            let span = Span {
+                line: reader.i32()?,
+                column: reader.u32()?,
+                length: reader.u32()?,
+            };
+            let count = reader.u32()?;
+            covered = covered.saturating_add(count);
+            chunk.spans.push(SpanRun { span, count });
+        }
+        if covered != chunk.code.len() {
+            return Err("Span table doesn't match the code.".to_string());
+        }
+
+        let constant_count = reader.u32()?;
+        for _ in 0..constant_count {
+            let constant = match reader.u8()? {
+                TAG_NIL => Value::from_nil(),
+                TAG_BOOL => Value::from_bool(reader.u8()? != 0),
+                TAG_NUMBER => Value::from_number(reader.f64()?),
+                // a file written without nan boxing can hold ints too wide for a nan boxed value
+                TAG_INT => match reader.i64()? {
+                    int if (INT_MIN..=INT_MAX).contains(&int) => Value::from_int(int),
+                    _ => return Err(format!("Integer constant out of range{INT_RANGE_NOTE}.")),
+                },
+                TAG_STRING => {
+                    let string = vm.alloc_string(reader.string()?);
+                    vm.compiler_roots.push(string);
+                    Value::from_object(string)
+                }
+                TAG_FUNCTION => {
+                    let name = reader.string()?;
+                    let mut function =
+                        ObjFunction::init(if name.is_empty() { None } else { Some(name) });
+                    function.arity = reader.u32()?;
+
+                    let count = reader.u32()?;
+                    for _ in 0..count {
+                        let is_local = reader.u8()? != 0;
+                        let index = reader.u32()?;
+                        // captured upvalues come from the function doing the capturing
+                        if !is_local && index >= upvalue_count {
+                            return Err(
+                                "Function captures an upvalue that doesn't exist.".to_string()
+                            );
+                        }
+                        function
+                            .upvalues
+                            .push(UpvalueDescriptor { is_local, index });
+                    }
+
+                    function.chunk =
+                        Chunk::read_body(reader, vm, function.arity, count, depth + 1)?;
+                    let function = vm.alloc(ObjectType::Function(function));
+                    vm.compiler_roots.push(function);
+                    Value::from_object(function)
+                }
+                tag => return Err(format!("Unknown constant tag {}.", tag)),
+            };
+            chunk.constants.write(constant);
+        }
+
+        chunk.validate(&vm.heap, arity, upvalue_count)?;
+        return Ok(chunk);
+    }
+
+    // follows every path through the code tracking how many values the frame has on the stack,
+    // so constants, slots, upvalues and call arguments all exist by the time the vm gets to them
+    fn validate(&self, heap: &Heap, arity: usize, upvalue_count: usize) -> Result<(), String> {
+        let constants = &self.constants.values;
+        let is_string = |index: usize| index < constants.len() && heap.is_string(&constants[index]);
+        let malformed = |offset: usize| Err(format!("Malformed instruction at offset {}.", offset));
+
+        // the stack height on entry to each instruction, every path reaching it has to agree
+        let mut heights: HashMap<usize, usize> = HashMap::new();
+        // slot zero holds the callee, then come the arguments
+        let mut pending = vec![(0, arity + 1)];
+
+        while let Some((offset, height)) = pending.pop() {
+            match heights.get(&offset) {
+                Some(seen) if *seen == height => continue,
+                Some(_) => return malformed(offset),
+                None => heights.insert(offset, height),
+            };
+
+            // running off the end of the code, or jumping past it, lands here too
+            let instruction = match self.read(offset) {
+                Some(instruction) => instruction,
+                None => return malformed(offset),
+            };
+            let next = offset + instruction.size();
+
+            // how many values the instruction needs, and how many it leaves in their place
+            let (pops, pushes) = match instruction {
+                OpCode::OpReturn => (1, 0),
+                OpCode::OpConstant(index) | OpCode::OpConstantLong(index)
+                    if index < constants.len() =>
+                {
+                    (0, 1)
+                }
+                OpCode::OpNil | OpCode::OpTrue | OpCode::OpFalse => (0, 1),
+                OpCode::OpAdd
+                | OpCode::OpSubtract
+                | OpCode::OpMultiply
+                | OpCode::OpDivide
+                | OpCode::OpFloorDivide
+                | OpCode::OpModulo
+                | OpCode::OpEqual
+                | OpCode::OpGreater
+                | OpCode::OpLess
+                | OpCode::OpGetIndex => (2, 1),
+                OpCode::OpSetIndex => (3, 1),
+                OpCode::OpBuildList(count) => (count, 1),
+                OpCode::OpBuildMap(count) => (count * 2, 1),
+                OpCode::OpNegate | OpCode::OpNot | OpCode::OpStringify => (1, 1),
+                OpCode::OpPrint | OpCode::OpPop | OpCode::OpCloseUpvalue => (1, 0),
+                OpCode::OpDefineGlobal(index) if is_string(index) => (1, 0),
+                OpCode::OpGetGlobal(index) | OpCode::OpClass(index) if is_string(index) => (0, 1),
+                OpCode::OpSetGlobal(index) | OpCode::OpGetProperty(index) if is_string(index) => {
+                    (1, 1)
+                }
+                OpCode::OpSetProperty(index)
+                | OpCode::OpMethod(index)
+                | OpCode::OpGetSuper(index)
+                    if is_string(index) =>
+                {
+                    (2, 1)
+                }
+                OpCode::OpInvoke(index, arg_count) if is_string(index) => (arg_count + 1, 1),
+                OpCode::OpSuperInvoke(index, arg_count) if is_string(index) => (arg_count + 2, 1),
+                OpCode::OpInherit => (2, 1),
+                OpCode::OpCall(arg_count) => (arg_count + 1, 1),
+                OpCode::OpGetLocal(slot) if slot < height => (0, 1),
+                OpCode::OpSetLocal(slot) if slot < height => (1, 1),
+                OpCode::OpGetUpvalue(slot) if slot < upvalue_count => (0, 1),
+                OpCode::OpSetUpvalue(slot) if slot < upvalue_count => (1, 1),
+                OpCode::OpClosure(index)
+                    if index < constants.len() && heap.is_function(&constants[index]) =>
+                {
+                    // captured locals have to be slots that exist in this frame
+                    let function = heap.as_function(constants[index].as_object());
+                    if function
+                        .upvalues
+                        .iter()
+                        .any(|upvalue| upvalue.is_local && upvalue.index >= height)
+                    {
+                        return malformed(offset);
+                    }
+                    (0, 1)
+                }
+                OpCode::OpJump(_) | OpCode::OpLoop(_) => (0, 0),
+                OpCode::OpJumpIfFalse(_) => (1, 1),
+                _ => return malformed(offset),
+            };
+
+            if height < pops {
+                return malformed(offset);
+            }
+            let height = height - pops + pushes;
+
+            match instruction {
+                OpCode::OpReturn => {}
+                OpCode::OpJump(jump) => pending.push((next + jump, height)),
+                OpCode::OpJumpIfFalse(jump) => {
+                    pending.push((next + jump, height));
+                    pending.push((next, height));
+                }
+                OpCode::OpLoop(jump) => match next.checked_sub(jump) {
+                    Some(target) => pending.push((target, height)),
+                    None => return malformed(offset),
+                },
+                _ => pending.push((next, height)),
+            }
+        }
+
+        return Ok(());
+    }
+}
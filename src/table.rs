@@ -0,0 +1,315 @@
+use crate::{
+    memory::Heap,
+    values::{ObjRef, Value},
+};
+
+// grow once the table is three quarters full, tombstones count towards the load
+const TABLE_MAX_LOAD: f64 = 0.75;
+const TABLE_MIN_CAPACITY: usize = 8;
+
+// the book's FNV-1a, computed once when a string is made and kept in the ObjString
+pub fn hash_string(key: &str) -> u32 {
+    let mut hash: u32 = 2166136261;
+    for byte in key.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(16777619);
+    }
+    return hash;
+}
+
+// an empty slot has no key and a nil value, a tombstone has no key and a true value
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    key: Option<ObjRef>,
+    hash: u32,
+    value: Value,
+}
+
+impl Entry {
+    fn empty() -> Self {
+        Entry {
+            key: None,
+            hash: 0,
+            value: Value::from_nil(),
+        }
+    }
+
+    fn is_tombstone(&self) -> bool {
+        return self.key.is_none() && !self.value.is_nil();
+    }
+}
+
+// open addressing with linear probing, keyed by interned strings
+// strings are interned so keys compare by handle, the caller hands in the string's hash
+#[derive(Debug, Clone)]
+pub struct Table {
+    // live entries plus tombstones, so probing always finds an empty slot
+    count: usize,
+    entries: Vec<Entry>,
+}
+
+impl Table {
+    pub fn init() -> Self {
+        Table {
+            count: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn allocated_bytes(&self) -> usize {
+        return self.entries.capacity() * std::mem::size_of::<Entry>();
+    }
+
+    // the capacity is always a power of two, so wrapping is a mask instead of a modulo
+    fn find_entry(entries: &[Entry], key: ObjRef, hash: u32) -> usize {
+        let mask = entries.len() - 1;
+        let mut index = hash as usize & mask;
+        let mut tombstone = None;
+
+        loop {
+            let entry = &entries[index];
+            match entry.key {
+                Some(entry_key) if entry_key == key => return index,
+                Some(_) => {}
+                None if entry.is_tombstone() => {
+                    // keep going, but reuse the first tombstone if the key turns out to be missing
+                    if tombstone.is_none() {
+                        tombstone = Some(index);
+                    }
+                }
+                None => return tombstone.unwrap_or(index),
+            }
+
+            index = (index + 1) & mask;
+        }
+    }
+
+    fn adjust_capacity(&mut self, capacity: usize) {
+        let old_entries = std::mem::replace(&mut self.entries, vec![Entry::empty(); capacity]);
+
+        // tombstones aren't carried over, so the count is rebuilt from the live entries
+        self.count = 0;
+        for entry in old_entries {
+            if let Some(key) = entry.key {
+                let index = Self::find_entry(&self.entries, key, entry.hash);
+                self.entries[index] = entry;
+                self.count += 1;
+            }
+        }
+    }
+
+    pub fn get(&self, key: ObjRef, hash: u32) -> Option<Value> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let entry = &self.entries[Self::find_entry(&self.entries, key, hash)];
+        return entry.key.map(|_| entry.value);
+    }
+
+    // returns true when the key wasn't in the table before
+    pub fn set(&mut self, key: ObjRef, hash: u32, value: Value) -> bool {
+        if (self.count + 1) as f64 > self.entries.len() as f64 * TABLE_MAX_LOAD {
+            let capacity = (self.entries.len() * 2).max(TABLE_MIN_CAPACITY);
+            self.adjust_capacity(capacity);
+        }
+
+        let index = Self::find_entry(&self.entries, key, hash);
+        let entry = &mut self.entries[index];
+        let is_new_key = entry.key.is_none();
+        // reusing a tombstone doesn't change the count, it was already counted
+        if is_new_key && !entry.is_tombstone() {
+            self.count += 1;
+        }
+
+        *entry = Entry {
+            key: Some(key),
+            hash,
+            value,
+        };
+        return is_new_key;
+    }
+
+    pub fn delete(&mut self, key: ObjRef, hash: u32) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+
+        let index = Self::find_entry(&self.entries, key, hash);
+        let entry = &mut self.entries[index];
+        if entry.key.is_none() {
+            return false;
+        }
+
+        // leave a tombstone so probe sequences running through this slot keep going
+        *entry = Entry {
+            key: None,
+            hash: 0,
+            value: Value::from_bool(true),
+        };
+        return true;
+    }
+
+    pub fn add_all(&self, to: &mut Table) {
+        for (key, hash, value) in self.entries() {
+            to.set(key, hash, value);
+        }
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (ObjRef, u32, Value)> + '_ {
+        return self
+            .entries
+            .iter()
+            .filter_map(|entry| entry.key.map(|key| (key, entry.hash, entry.value)));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ObjRef, Value)> + '_ {
+        return self.entries().map(|(key, _, value)| (key, value));
+    }
+
+    // the one lookup that compares contents, it's how interning finds out a string already exists
+    pub fn find_string(&self, heap: &Heap, content: &str, hash: u32) -> Option<ObjRef> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let mask = self.entries.len() - 1;
+        let mut index = hash as usize & mask;
+        loop {
+            let entry = &self.entries[index];
+            match entry.key {
+                Some(key) if entry.hash == hash && heap.as_string(key).content == content => {
+                    return Some(key);
+                }
+                Some(_) => {}
+                None if entry.is_tombstone() => {}
+                None => return None,
+            }
+
+            index = (index + 1) & mask;
+        }
+    }
+
+    // drops every entry whose key didn't get marked, used to keep the intern table weak
+    pub fn remove_unmarked(&mut self, heap: &Heap) {
+        let dead: Vec<(ObjRef, u32)> = self
+            .entries()
+            .filter(|(key, _, _)| !heap.is_marked(*key))
+            .map(|(key, hash, _)| (key, hash))
+            .collect();
+
+        for (key, hash) in dead {
+            self.delete(key, hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::values::{ObjString, ObjectType};
+
+    // every key on the same hash, so they all share one probe sequence
+    const COLLIDING: u32 = 3;
+
+    fn get_int(table: &Table, key: usize, hash: u32) -> Option<i64> {
+        return table.get(ObjRef(key), hash).map(|value| value.as_int());
+    }
+
+    #[test]
+    fn set_get_and_overwrite() {
+        let mut table = Table::init();
+        assert_eq!(get_int(&table, 1, 1), None);
+        assert!(table.set(ObjRef(1), 1, Value::from_int(10)));
+        assert!(!table.set(ObjRef(1), 1, Value::from_int(11)));
+        assert_eq!(get_int(&table, 1, 1), Some(11));
+        assert_eq!(get_int(&table, 2, 1), None);
+    }
+
+    // a deleted key in the middle of a probe sequence mustn't hide the keys after it
+    #[test]
+    fn tombstones_keep_probing() {
+        let mut table = Table::init();
+        for key in 0..4 {
+            table.set(ObjRef(key), COLLIDING, Value::from_int(key as i64));
+        }
+
+        assert!(table.delete(ObjRef(1), COLLIDING));
+        assert!(!table.delete(ObjRef(1), COLLIDING));
+        assert_eq!(get_int(&table, 1, COLLIDING), None);
+        assert_eq!(get_int(&table, 3, COLLIDING), Some(3));
+
+        // the tombstone is reused, and was already counted
+        let count = table.count;
+        assert!(table.set(ObjRef(9), COLLIDING, Value::from_int(9)));
+        assert_eq!(table.count, count);
+        assert_eq!(get_int(&table, 9, COLLIDING), Some(9));
+        assert_eq!(get_int(&table, 3, COLLIDING), Some(3));
+    }
+
+    #[test]
+    fn grows_past_the_load_factor() {
+        let mut table = Table::init();
+        for key in 0..100 {
+            table.set(ObjRef(key), key as u32 % 7, Value::from_int(key as i64));
+            assert!(table.entries.len().is_power_of_two());
+            assert!(table.count as f64 <= table.entries.len() as f64 * TABLE_MAX_LOAD);
+        }
+
+        for key in 0..100 {
+            assert_eq!(get_int(&table, key, key as u32 % 7), Some(key as i64));
+        }
+        assert_eq!(table.iter().count(), 100);
+    }
+
+    // tombstones count towards the load, so churning through keys can't fill every slot and leave lookups probing forever
+    #[test]
+    fn churn_never_fills_the_table() {
+        let mut table = Table::init();
+        for key in 0..1000 {
+            table.set(ObjRef(key), COLLIDING, Value::from_nil());
+            table.delete(ObjRef(key), COLLIDING);
+        }
+
+        assert!(table
+            .entries
+            .iter()
+            .any(|entry| entry.key.is_none() && !entry.is_tombstone()));
+        assert_eq!(get_int(&table, 1000, COLLIDING), None);
+        assert_eq!(table.iter().count(), 0);
+    }
+
+    #[test]
+    fn find_string_compares_contents() {
+        fn intern(heap: &mut Heap, table: &mut Table, content: &str) -> ObjRef {
+            let hash = hash_string(content);
+            let string = ObjString::allocate(content.to_string(), hash);
+            let key = heap.alloc(ObjectType::String(string));
+            table.set(key, hash, Value::from_nil());
+            return key;
+        }
+
+        let mut heap = Heap::init();
+        let mut table = Table::init();
+        let first = intern(&mut heap, &mut table, "first");
+        let second = intern(&mut heap, &mut table, "second");
+        assert_eq!(
+            table.find_string(&heap, "first", hash_string("first")),
+            Some(first)
+        );
+        assert_eq!(
+            table.find_string(&heap, "third", hash_string("third")),
+            None
+        );
+
+        table.delete(first, hash_string("first"));
+        assert_eq!(
+            table.find_string(&heap, "first", hash_string("first")),
+            None
+        );
+        assert_eq!(
+            table.find_string(&heap, "second", hash_string("second")),
+            Some(second)
+        );
+    }
+}
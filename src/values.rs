@@ -1,162 +1,568 @@
-use std::fmt::Display;
-
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct ObjString {
-    pub content: Box<String>,
-}
-
-impl ObjString {
-    fn allocate(chars: Box<String>) -> Self {
-        ObjString { content: chars }
-    }
-}
-
-impl Display for ObjString {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.content)
-    }
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum ObjectType {
-    String(ObjString),
-}
-
-impl ObjectType {
-    fn print(&self) -> String {
-        match self {
-            Self::String(s) => s.content.to_string(),
-        }
-    }
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum Value {
-    Bool(bool),
-    Nil,
-    Number(f64),
-    Object(ObjectType),
-}
-
-impl Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Value::Bool(value) => write!(f, "{}", value),
-            Value::Nil => write!(f, "Nil"),
-            Value::Number(value) => write!(f, "{}", value),
-            Value::Object(obj) => write!(f, "{}", obj.print()),
-        }
-    }
-}
-
-impl Value {
-    pub fn from_bool(b: bool) -> Self {
-        return Self::Bool(b);
-    }
-
-    pub fn from_number(n: f64) -> Self {
-        return Self::Number(n);
-    }
-
-    pub fn from_string(a: String) -> Self {
-        return Self::Object(ObjectType::String(ObjString {
-            content: Box::new(a),
-        }));
-    }
-
-    pub fn from_nil() -> Self {
-        return Self::Nil;
-    }
-
-    pub fn as_number(&self) -> f64 {
-        match self {
-            Value::Number(n) => *n,
-            _ => panic!("incorrect usage of as_number"),
-        }
-    }
-
-    pub fn as_bool(&self) -> bool {
-        match self {
-            Value::Bool(b) => *b,
-            _ => panic!("incorrect usage of as_bool"),
-        }
-    }
-
-    pub fn as_object(&self) -> ObjectType {
-        match self {
-            Value::Object(o) => o.clone(),
-            _ => panic!("Incorrect usage of as_object"),
-        }
-    }
-
-    pub fn as_string(&self) -> ObjString {
-        match self.as_object() {
-            ObjectType::String(s) => s,
-        }
-    }
-
-    pub fn is_bool(&self) -> bool {
-        match self {
-            Value::Bool(_) => true,
-            _ => false,
-        }
-    }
-
-    pub fn is_string(&self) -> bool {
-        if let Self::Object(s) = self {
-            if let ObjectType::String(_) = s {
-                return true;
-            }
-        }
-
-        false
-    }
-
-    pub fn is_number(&self) -> bool {
-        match self {
-            Value::Number(_) => true,
-            _ => false,
-        }
-    }
-
-    pub fn is_nil(&self) -> bool {
-        match self {
-            Value::Nil => true,
-            _ => false,
-        }
-    }
-
-    pub fn is_falsey(&self) -> bool {
-        return self.is_nil() || (self.is_bool() && !self.as_bool());
-    }
-}
-
-pub fn print_value(val: &Value) {
-    print!("{}", val);
-}
-
-pub struct ValueArray {
-    pub values: Vec<Value>,
-}
-
-impl ValueArray {
-    pub fn init() -> Self {
-        ValueArray { values: Vec::new() }
-    }
-
-    pub fn write(&mut self, value: Value) {
-        self.values.push(value);
-    }
-
-    pub fn get(&self, index: &usize) -> &Value {
-        return self.values.get(*index).unwrap_or(&Value::Nil);
-    }
-
-    pub fn take(&mut self, index: &usize) -> Value {
-        if self.values.get(*index).is_none() {
-            return Value::from_number(-1.0);
-        } else {
-            return self.values.swap_remove(*index);
-        }
-    }
-}
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{chunk::Chunk, memory::Heap, table::Table, vm::VM};
+
+// a handle to an object living in the vm's heap
+// values only ever hold these, the heap owns the objects and the collector decides when they die
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(pub usize);
+
+impl Display for ObjRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ObjString {
+    pub content: String,
+    pub hash: u32,
+}
+
+impl ObjString {
+    pub fn allocate(chars: String, hash: u32) -> Self {
+        ObjString {
+            content: chars,
+            hash,
+        }
+    }
+}
+
+impl Display for ObjString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.content)
+    }
+}
+
+// tells OpClosure where to find each captured variable
+// is_local means a slot in the enclosing function's frame, otherwise an upvalue of the enclosing closure
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpvalueDescriptor {
+    pub is_local: bool,
+    pub index: usize,
+}
+
+#[derive(Debug)]
+pub struct ObjFunction {
+    pub arity: usize,
+    pub chunk: Chunk,
+    // the top level script is the only function without a name
+    pub name: Option<String>,
+    pub upvalues: Vec<UpvalueDescriptor>,
+}
+
+impl ObjFunction {
+    pub fn init(name: Option<String>) -> Self {
+        ObjFunction {
+            arity: 0,
+            chunk: Chunk::init(),
+            name,
+            upvalues: Vec::new(),
+        }
+    }
+}
+
+impl Display for ObjFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjUpvalue {
+    // the stack slot of the variable while it is still in scope
+    pub location: usize,
+    // once the variable's frame is gone the value is moved in here
+    pub closed: Option<Value>,
+}
+
+impl ObjUpvalue {
+    pub fn init(location: usize) -> Self {
+        ObjUpvalue {
+            location,
+            closed: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjClosure {
+    pub function: ObjRef,
+    // upvalues are shared between every closure that captured the same variable
+    pub upvalues: Vec<ObjRef>,
+}
+
+impl ObjClosure {
+    pub fn init(function: ObjRef) -> Self {
+        ObjClosure {
+            function,
+            upvalues: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjClass {
+    pub name: ObjRef,
+    // every method is a closure
+    pub methods: Table,
+}
+
+impl ObjClass {
+    pub fn init(name: ObjRef) -> Self {
+        ObjClass {
+            name,
+            methods: Table::init(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjInstance {
+    pub class: ObjRef,
+    pub fields: Table,
+}
+
+impl ObjInstance {
+    pub fn init(class: ObjRef) -> Self {
+        ObjInstance {
+            class,
+            fields: Table::init(),
+        }
+    }
+}
+
+// a method pulled off an instance, remembers which instance `this` should be
+#[derive(Debug)]
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+// host functions get the vm so they can allocate or call back into it, an Err becomes a lox runtime error
+pub type NativeFn = fn(&mut VM, &[Value]) -> Result<Value, String>;
+
+pub struct ObjNative {
+    pub name: String,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl std::fmt::Debug for ObjNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ObjNative({})", self.name)
+    }
+}
+
+// a growable list, items are shared by reference like everything else on the heap
+#[derive(Debug)]
+pub struct ObjList {
+    pub items: Vec<Value>,
+}
+
+impl ObjList {
+    pub fn init(items: Vec<Value>) -> Self {
+        ObjList { items }
+    }
+}
+
+// what a map key hashes and compares as, only values with a stable identity can be keys
+// strings are interned so the handle stands in for the contents, non-integral floats go by their bits
+// a float holding a whole number keys as the int it equals, so `m[1]` and `m[1.0]` are the same entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Number(u64),
+    String(ObjRef),
+}
+
+// entries stay in insertion order, `positions` finds a key's entry without scanning
+#[derive(Debug)]
+pub struct ObjMap {
+    pub entries: Vec<(Value, Value)>,
+    positions: HashMap<MapKey, usize>,
+}
+
+impl ObjMap {
+    pub fn init() -> Self {
+        ObjMap {
+            entries: Vec::new(),
+            positions: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: MapKey) -> Option<Value> {
+        return self
+            .positions
+            .get(&key)
+            .map(|&position| self.entries[position].1);
+    }
+
+    pub fn has(&self, key: MapKey) -> bool {
+        return self.positions.contains_key(&key);
+    }
+
+    // an existing key keeps its place and only has its value replaced
+    pub fn set(&mut self, key: MapKey, key_value: Value, value: Value) {
+        match self.positions.get(&key) {
+            Some(&position) => self.entries[position].1 = value,
+            None => {
+                self.positions.insert(key, self.entries.len());
+                self.entries.push((key_value, value));
+            }
+        }
+    }
+
+    // shifts every later entry down to keep the order, so deleting is linear
+    pub fn delete(&mut self, key: MapKey) -> bool {
+        let position = match self.positions.remove(&key) {
+            Some(position) => position,
+            None => return false,
+        };
+
+        self.entries.remove(position);
+        for later in self.positions.values_mut() {
+            if *later > position {
+                *later -= 1;
+            }
+        }
+        return true;
+    }
+}
+
+#[derive(Debug)]
+pub enum ObjectType {
+    String(ObjString),
+    Function(ObjFunction),
+    Closure(ObjClosure),
+    Upvalue(ObjUpvalue),
+    Class(ObjClass),
+    Instance(ObjInstance),
+    BoundMethod(ObjBoundMethod),
+    Native(ObjNative),
+    List(ObjList),
+    Map(ObjMap),
+}
+
+impl ObjectType {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::String(_) => "string",
+            Self::Function(_) => "function",
+            Self::Closure(_) => "closure",
+            Self::Upvalue(_) => "upvalue",
+            Self::Class(_) => "class",
+            Self::Instance(_) => "instance",
+            Self::BoundMethod(_) => "bound method",
+            Self::Native(_) => "native",
+            Self::List(_) => "list",
+            Self::Map(_) => "map",
+        }
+    }
+
+    // a rough count of the bytes this object keeps alive, used to decide when to collect
+    pub fn size(&self) -> usize {
+        let owned = match self {
+            Self::String(s) => s.content.capacity(),
+            Self::Function(function) => {
+                function.chunk.code.capacity()
+                    + function.chunk.spans.capacity() * std::mem::size_of::<crate::chunk::SpanRun>()
+                    + function.chunk.constants.values.capacity() * std::mem::size_of::<Value>()
+            }
+            Self::Closure(closure) => closure.upvalues.capacity() * std::mem::size_of::<ObjRef>(),
+            Self::Class(class) => class.methods.allocated_bytes(),
+            Self::Instance(instance) => instance.fields.allocated_bytes(),
+            Self::List(list) => list.items.capacity() * std::mem::size_of::<Value>(),
+            Self::Map(map) => {
+                map.entries.capacity() * std::mem::size_of::<(Value, Value)>()
+                    + map.positions.capacity() * std::mem::size_of::<(MapKey, usize)>()
+            }
+            Self::Upvalue(_) | Self::BoundMethod(_) | Self::Native(_) => 0,
+        };
+
+        return std::mem::size_of::<ObjectType>() + owned;
+    }
+}
+
+// the plain representation, a tagged enum
+// the derived equality is exact, 1 and 1.0 differ, lox's own == lives in number.rs
+#[cfg(not(feature = "nan-boxing"))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Nil,
+    Number(f64),
+    Int(i64),
+    Object(ObjRef),
+}
+
+// the range ints can hold, arithmetic leaving it is an overflow error
+#[cfg(not(feature = "nan-boxing"))]
+pub const INT_MIN: i64 = i64::MIN;
+#[cfg(not(feature = "nan-boxing"))]
+pub const INT_MAX: i64 = i64::MAX;
+
+#[cfg(not(feature = "nan-boxing"))]
+impl Value {
+    pub fn from_bool(b: bool) -> Self {
+        return Self::Bool(b);
+    }
+
+    pub fn from_number(n: f64) -> Self {
+        return Self::Number(n);
+    }
+
+    pub fn from_int(n: i64) -> Self {
+        return Self::Int(n);
+    }
+
+    pub fn from_object(object: ObjRef) -> Self {
+        return Self::Object(object);
+    }
+
+    pub fn from_nil() -> Self {
+        return Self::Nil;
+    }
+
+    pub fn as_number(&self) -> f64 {
+        match self {
+            Value::Number(n) => *n,
+            _ => panic!("incorrect usage of as_number"),
+        }
+    }
+
+    pub fn as_int(&self) -> i64 {
+        match self {
+            Value::Int(n) => *n,
+            _ => panic!("incorrect usage of as_int"),
+        }
+    }
+
+    pub fn as_bool(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            _ => panic!("incorrect usage of as_bool"),
+        }
+    }
+
+    pub fn as_object(&self) -> ObjRef {
+        match self {
+            Value::Object(o) => *o,
+            _ => panic!("Incorrect usage of as_object"),
+        }
+    }
+
+    pub fn is_bool(&self) -> bool {
+        matches!(self, Value::Bool(_))
+    }
+
+    pub fn is_object(&self) -> bool {
+        matches!(self, Value::Object(_))
+    }
+
+    // only floats, ints answer to is_int
+    pub fn is_number(&self) -> bool {
+        matches!(self, Value::Number(_))
+    }
+
+    pub fn is_int(&self) -> bool {
+        matches!(self, Value::Int(_))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+}
+
+// the book's nan boxing, every value is a single u64
+// anything that isn't a quiet nan is a number, the rest use the spare mantissa bits as a tag
+// objects set the sign bit and keep their heap index in the low 48 bits
+// ints set INT_BIT and keep a 49 bit two's complement number below it, so they're narrower than an i64
+#[cfg(feature = "nan-boxing")]
+const SIGN_BIT: u64 = 0x8000000000000000;
+#[cfg(feature = "nan-boxing")]
+const QNAN: u64 = 0x7ffc000000000000;
+#[cfg(feature = "nan-boxing")]
+const TAG_NIL: u64 = 1;
+#[cfg(feature = "nan-boxing")]
+const TAG_FALSE: u64 = 2;
+#[cfg(feature = "nan-boxing")]
+const TAG_TRUE: u64 = 3;
+#[cfg(feature = "nan-boxing")]
+const INT_BIT: u64 = 1 << 49;
+#[cfg(feature = "nan-boxing")]
+const INT_PAYLOAD: u64 = INT_BIT - 1;
+
+#[cfg(feature = "nan-boxing")]
+pub const INT_MIN: i64 = -(1 << 48);
+#[cfg(feature = "nan-boxing")]
+pub const INT_MAX: i64 = (1 << 48) - 1;
+
+// the narrower range means this build turns away ints the plain one accepts, errors about them say so
+#[cfg(not(feature = "nan-boxing"))]
+pub const INT_RANGE_NOTE: &str = "";
+#[cfg(feature = "nan-boxing")]
+pub const INT_RANGE_NOTE: &str = " in the nan-boxing build";
+
+#[cfg(feature = "nan-boxing")]
+#[derive(Clone, Copy)]
+pub struct Value(u64);
+
+#[cfg(feature = "nan-boxing")]
+impl Value {
+    const NIL: Value = Value(QNAN | TAG_NIL);
+    const FALSE: Value = Value(QNAN | TAG_FALSE);
+    const TRUE: Value = Value(QNAN | TAG_TRUE);
+
+    pub fn from_bool(b: bool) -> Self {
+        return if b { Self::TRUE } else { Self::FALSE };
+    }
+
+    // a nan can carry any payload, and some payloads look like our tags, so every nan becomes the plain one
+    pub fn from_number(n: f64) -> Self {
+        if n.is_nan() {
+            return Value(f64::NAN.to_bits());
+        }
+        return Value(n.to_bits());
+    }
+
+    // callers keep n between INT_MIN and INT_MAX, anything wider would lose its top bits
+    pub fn from_int(n: i64) -> Self {
+        return Value(QNAN | INT_BIT | (n as u64 & INT_PAYLOAD));
+    }
+
+    pub fn from_object(object: ObjRef) -> Self {
+        return Value(SIGN_BIT | QNAN | object.0 as u64);
+    }
+
+    pub fn from_nil() -> Self {
+        return Self::NIL;
+    }
+
+    pub fn as_number(&self) -> f64 {
+        if !self.is_number() {
+            panic!("incorrect usage of as_number");
+        }
+        return f64::from_bits(self.0);
+    }
+
+    pub fn as_int(&self) -> i64 {
+        if !self.is_int() {
+            panic!("incorrect usage of as_int");
+        }
+        // shifting the payload up to the sign bit and back down sign extends it
+        return ((self.0 << 15) as i64) >> 15;
+    }
+
+    pub fn as_bool(&self) -> bool {
+        if !self.is_bool() {
+            panic!("incorrect usage of as_bool");
+        }
+        return self.0 == Self::TRUE.0;
+    }
+
+    pub fn as_object(&self) -> ObjRef {
+        if !self.is_object() {
+            panic!("Incorrect usage of as_object");
+        }
+        return ObjRef((self.0 & !(SIGN_BIT | QNAN)) as usize);
+    }
+
+    pub fn is_bool(&self) -> bool {
+        // true and false only differ in the lowest bit
+        return (self.0 | 1) == Self::TRUE.0;
+    }
+
+    pub fn is_object(&self) -> bool {
+        return self.0 & (QNAN | SIGN_BIT) == (QNAN | SIGN_BIT);
+    }
+
+    // only floats, ints answer to is_int
+    pub fn is_number(&self) -> bool {
+        return self.0 & QNAN != QNAN;
+    }
+
+    pub fn is_int(&self) -> bool {
+        return self.0 & (SIGN_BIT | QNAN | INT_BIT) == (QNAN | INT_BIT);
+    }
+
+    pub fn is_nil(&self) -> bool {
+        return self.0 == Self::NIL.0;
+    }
+}
+
+// floats compare as floats, so nan != nan just like with the enum, ints compare by their bits
+#[cfg(feature = "nan-boxing")]
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        if self.is_number() && other.is_number() {
+            return self.as_number() == other.as_number();
+        }
+        return self.0 == other.0;
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_bool() {
+            write!(f, "Bool({})", self.as_bool())
+        } else if self.is_nil() {
+            write!(f, "Nil")
+        } else if self.is_number() {
+            write!(f, "Number({})", self.as_number())
+        } else if self.is_int() {
+            write!(f, "Int({})", self.as_int())
+        } else {
+            write!(f, "Object({:?})", self.as_object())
+        }
+    }
+}
+
+// shared by both representations, only ever goes through the accessors
+impl Value {
+    pub fn is_falsey(&self) -> bool {
+        return self.is_nil() || (self.is_bool() && !self.as_bool());
+    }
+}
+
+// objects only know how to print themselves with the heap at hand
+pub fn print_value(val: &Value, heap: &Heap) {
+    print!("{}", heap.format_value(val));
+}
+
+#[derive(Debug)]
+pub struct ValueArray {
+    pub values: Vec<Value>,
+}
+
+impl ValueArray {
+    pub fn init() -> Self {
+        ValueArray { values: Vec::new() }
+    }
+
+    pub fn write(&mut self, value: Value) {
+        self.values.push(value);
+    }
+
+    pub fn get(&self, index: &usize) -> Value {
+        return self
+            .values
+            .get(*index)
+            .copied()
+            .unwrap_or(Value::from_nil());
+    }
+
+    pub fn take(&mut self, index: &usize) -> Value {
+        if self.values.get(*index).is_none() {
+            return Value::from_number(-1.0);
+        } else {
+            return self.values.swap_remove(*index);
+        }
+    }
+}
@@ -1,250 +1,1142 @@
-use std::{collections::HashMap, ops::Deref};
-
-use crate::{
-    chunk::{Chunk, OpCode},
-    compiler::compile,
-    debug::disassemble_instruction,
-    values::{print_value, ObjString, ObjectType, Value},
-};
-
-#[derive(Debug)]
-pub enum InterpretResult {
-    InterpretOk,
-    InterpretCompileError,
-    InterpretRuntimeError,
-}
-
-pub enum Operation {
-    Greater,
-    Less,
-    Plus,
-    Minus,
-    Star,
-    Div,
-}
-
-// one key thing to note here is that the books implementation uses an ip pointer
-// we don't do this, we just iterate through the code vector
-// pointer fuckery isn't that useful in rust, nor is it suggested due to the memory model
-
-// we don't keep an IP because the chunk is just the start
-pub struct VM {
-    pub chunk: Box<Chunk>,
-    debug: bool,
-
-    stack: Vec<Value>,
-    strings: HashMap<String, Box<String>>,
-}
-
-impl VM {
-    pub fn init() -> Self {
-        VM {
-            chunk: Box::new(Chunk::init()),
-            debug: false,
-            stack: Vec::new(),
-            strings: HashMap::new(),
-        }
-    }
-
-    pub fn set_debug(&mut self) {
-        self.debug = true
-    }
-
-    pub fn interpret(&mut self, source: String) -> InterpretResult {
-        let mut chunk = Chunk::init();
-        if !compile(source, &mut chunk) {
-            return InterpretResult::InterpretCompileError;
-        }
-
-        self.chunk = Box::new(chunk);
-        self.run()
-    }
-
-    fn peak(&self, distance: usize) -> Option<&Value> {
-        return self.stack.get(self.stack.len() - 1 - distance);
-    }
-
-    fn runtime_error(&mut self, format: &str) {
-        eprint!("{format} ");
-
-        let index = self.chunk.code.len() - 1;
-        let line = self.chunk.lines[index];
-        eprintln!("[line {}] in script", line);
-        // reset stack
-        self.stack.clear();
-    }
-
-    fn binary_op(&mut self, operation: Operation) -> InterpretResult {
-        if let Some(peak_value) = self.peak(0) {
-            if !peak_value.is_number() {
-                self.runtime_error("Operands must be numbers.");
-                return InterpretResult::InterpretRuntimeError;
-            }
-        } else {
-            return InterpretResult::InterpretRuntimeError;
-        }
-
-        let b = match self.stack.pop() {
-            Some(val) => val,
-            None => return InterpretResult::InterpretCompileError,
-        }
-        .as_number();
-
-        let a = match self.stack.pop() {
-            Some(val) => val,
-            None => return InterpretResult::InterpretCompileError,
-        }
-        .as_number();
-
-        match operation {
-            Operation::Plus => self.stack.push(Value::from_number(a + b)),
-            Operation::Minus => self.stack.push(Value::from_number(a - b)),
-            Operation::Star => self.stack.push(Value::from_number(a * b)),
-            Operation::Div => self.stack.push(Value::from_number(a / b)),
-            Operation::Greater => self.stack.push(Value::from_bool(a > b)),
-            Operation::Less => self.stack.push(Value::from_bool(a < b)),
-        }
-
-        InterpretResult::InterpretOk
-    }
-
-    fn allocate_object(&mut self, object_type: ObjectType) {
-        // i don't believe i need to handle garbage collection on our object types yet, as they are automatically cleaned up due to rust's memory management
-        self.stack.push(Value::Object(object_type));
-    }
-
-    fn allocate_string(&mut self, mut string: ObjString) -> ObjString {
-        if self.strings.contains_key(string.content.deref()) {
-            string.content = self.strings.get(string.content.deref()).unwrap().clone();
-        }
-
-        string
-    }
-
-    fn concatenate(&mut self) -> InterpretResult {
-        let b = match self.stack.pop() {
-            Some(val) => val,
-            None => return InterpretResult::InterpretCompileError,
-        }
-        .as_string();
-
-        let a = match self.stack.pop() {
-            Some(val) => val,
-            None => return InterpretResult::InterpretCompileError,
-        }
-        .as_string();
-
-        self.stack
-            .push(Value::from_string(*a.content + b.content.deref()));
-        InterpretResult::InterpretOk
-    }
-
-    fn run(&mut self) -> InterpretResult {
-        for instruction in &self.chunk.code {
-            if self.debug {
-                for element in &self.stack {
-                    print!("[{element}]");
-                }
-                println!("");
-
-                disassemble_instruction(&self.chunk.lines, &self.chunk.constants, &instruction, 0);
-            }
-
-            match instruction {
-                OpCode::OpReturn => {
-                    let pop_val = match self.stack.pop() {
-                        Some(val) => val,
-                        None => return InterpretResult::InterpretCompileError,
-                    };
-
-                    print_value(&pop_val);
-                    println!("");
-                    return InterpretResult::InterpretOk;
-                }
-                OpCode::OpNegate => {
-                    if let Some(peak_value) = self.peak(0) {
-                        if !peak_value.is_number() {
-                            self.runtime_error("Operands must be numbers.");
-                            return InterpretResult::InterpretRuntimeError;
-                        }
-                    } else {
-                        return InterpretResult::InterpretRuntimeError;
-                    }
-
-                    let pop_val = match self.stack.pop() {
-                        Some(val) => val,
-                        None => return InterpretResult::InterpretCompileError,
-                    };
-
-                    // as_number can panic if we do not have the above check
-                    self.stack.push(Value::from_number(-pop_val.as_number()));
-                }
-                OpCode::OpConstant(index) => {
-                    let mut constant = self.chunk.constants.take(index);
-                    if let Value::Object(o) = constant {
-                        if let ObjectType::String(mut s) = o {
-                            if self.strings.contains_key(s.content.deref()) {
-                                s.content = self.strings.get(s.content.deref()).unwrap().clone();
-                                constant = Value::Object(ObjectType::String(s));
-                                self.stack.push(constant);
-                                return InterpretResult::InterpretOk;
-                            }
-                        }
-                    } else {
-                        // push value
-                        self.stack.push(constant);
-                    }
-                }
-                // definitely some way to not have all this repeated code, but we're prototyping
-                OpCode::OpGreater => return self.binary_op(Operation::Greater),
-                OpCode::OpLess => return self.binary_op(Operation::Less),
-                OpCode::OpDivide => return self.binary_op(Operation::Div),
-                OpCode::OpMultiply => return self.binary_op(Operation::Star),
-                OpCode::OpAdd => {
-                    let peak_0 = self.peak(0);
-                    let peak_1 = self.peak(1);
-                    if peak_0.is_some() && peak_1.is_some() {
-                        let value_0 = peak_0.unwrap();
-                        let value_1 = peak_1.unwrap();
-
-                        if value_0.is_string() && value_1.is_string() {
-                            return self.concatenate();
-                        } else if value_0.is_number() && value_1.is_number() {
-                            return self.binary_op(Operation::Plus);
-                        }
-                    }
-
-                    self.runtime_error("Operands must be two numbers or two strings.");
-                    return InterpretResult::InterpretRuntimeError;
-                }
-                OpCode::OpSubtract => return self.binary_op(Operation::Minus),
-                OpCode::OpNil => self.stack.push(Value::from_nil()),
-                OpCode::OpTrue => self.stack.push(Value::from_bool(true)),
-                OpCode::OpFalse => self.stack.push(Value::from_bool(false)),
-                OpCode::OpNot => {
-                    let pop_val = match self.stack.pop() {
-                        Some(val) => val,
-                        None => return InterpretResult::InterpretCompileError,
-                    };
-
-                    self.stack.push(Value::from_bool(pop_val.is_falsey()));
-                }
-                OpCode::OpEqual => {
-                    let b = match self.stack.pop() {
-                        Some(val) => val,
-                        None => return InterpretResult::InterpretCompileError,
-                    };
-
-                    let a = match self.stack.pop() {
-                        Some(val) => val,
-                        None => return InterpretResult::InterpretCompileError,
-                    };
-
-                    self.stack.push(Value::from_bool(a == b));
-                }
-            }
-        }
-
-        InterpretResult::InterpretOk
-    }
-}
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    compiler,
+    debug::disassemble_instruction,
+    error::{LoxError, RuntimeError, TraceFrame},
+    list::{list_index, LIST_METHODS},
+    lower,
+    map::{map_key, MAP_METHODS},
+    memory::Heap,
+    number::{self, Operation},
+    table::{hash_string, Table},
+    values::{
+        print_value, NativeFn, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance,
+        ObjList, ObjMap, ObjNative, ObjRef, ObjString, ObjUpvalue, ObjectType, Value,
+    },
+    DEBUG_LOG_GC, DEBUG_STRESS_GC,
+};
+
+// deep enough for reasonable recursion, shallow enough that runaway recursion is reported quickly
+const FRAMES_MAX: usize = 64;
+
+// the method called when a class is invoked to build an instance
+const INIT_STRING: &str = "init";
+
+// the script's value on success, otherwise what went wrong for the embedder to report
+pub type InterpretResult = Result<Value, LoxError>;
+
+// seconds since the unix epoch, good enough for timing scripts
+fn clock_native(_vm: &mut VM, _args: &[Value]) -> Result<Value, String> {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => Ok(Value::from_number(duration.as_secs_f64())),
+        Err(_) => Err("System clock is set before the unix epoch.".to_string()),
+    }
+}
+
+// one key thing to note here is that the books implementation uses an ip pointer
+// we keep an index into the code vector instead, jumps just move the index around
+// pointer fuckery isn't that useful in rust, nor is it suggested due to the memory model
+pub struct CallFrame {
+    closure: ObjRef,
+    // the closure's function, kept here so every instruction fetch doesn't go through the closure
+    function: ObjRef,
+    ip: usize,
+    // where this call's window starts in the vm stack, slot zero is the callee itself
+    slot_base: usize,
+}
+
+pub struct VM {
+    frames: Vec<CallFrame>,
+    debug: bool,
+    // compile through the ast passes instead of the single pass compiler
+    ast_pipeline: bool,
+    // collect before every allocation, DEBUG_STRESS_GC turns it on for every vm
+    stress_gc: bool,
+
+    stack: Vec<Value>,
+    // every live string, so each distinct string exists exactly once
+    // entries don't keep their strings alive, the collector drops the ones nothing else reaches
+    strings: Table,
+    // lives on the vm rather than the chunk so repl lines can see each other's variables
+    globals: Table,
+    // interned once up front, class calls look it up on every instantiation
+    init_string: Option<ObjRef>,
+    // natives that `list.name(...)` and `map.name(...)` call, each takes the receiver as its first argument
+    list_methods: Table,
+    map_methods: Table,
+    // upvalues still pointing into the stack, ordered by stack slot
+    open_upvalues: Vec<ObjRef>,
+
+    pub heap: Heap,
+    // objects the compiler or bytecode loader made that aren't reachable from the vm yet
+    pub(crate) compiler_roots: Vec<ObjRef>,
+}
+
+impl VM {
+    pub fn init() -> Self {
+        let mut vm = VM {
+            frames: Vec::new(),
+            debug: false,
+            ast_pipeline: false,
+            stress_gc: DEBUG_STRESS_GC,
+            stack: Vec::new(),
+            strings: Table::init(),
+            globals: Table::init(),
+            init_string: None,
+            list_methods: Table::init(),
+            map_methods: Table::init(),
+            open_upvalues: Vec::new(),
+            heap: Heap::init(),
+            compiler_roots: Vec::new(),
+        };
+
+        vm.init_string = Some(vm.alloc_string(INIT_STRING.to_string()));
+
+        vm.define_native("clock", 0, clock_native);
+        vm.list_methods = vm.define_builtin_methods(&LIST_METHODS);
+        vm.map_methods = vm.define_builtin_methods(&MAP_METHODS);
+        vm
+    }
+
+    // both end up on the stack, they stay there until they're in a table since either allocation can collect
+    fn alloc_native(&mut self, name: &str, arity: usize, function: NativeFn) -> (ObjRef, Value) {
+        let native = ObjNative {
+            name: name.to_string(),
+            arity,
+            function,
+        };
+
+        let name = self.alloc_string(name.to_string());
+        self.stack.push(Value::from_object(name));
+        let native = self.alloc(ObjectType::Native(native));
+        self.stack.push(Value::from_object(native));
+        return (name, Value::from_object(native));
+    }
+
+    // exposes a rust function to scripts as a global
+    pub fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        let (name, native) = self.alloc_native(name, arity, function);
+        let hash = self.heap.as_string(name).hash;
+        self.globals.set(name, hash, native);
+        self.stack.pop();
+        self.stack.pop();
+    }
+
+    // arity doesn't count the receiver the method is called on
+    // the table isn't a root until it's stored, so everything stays on the stack until it's built
+    fn define_builtin_methods(&mut self, methods: &[(&str, usize, NativeFn)]) -> Table {
+        let stack_base = self.stack.len();
+        let mut table = Table::init();
+        for &(name, arity, function) in methods {
+            let (name, native) = self.alloc_native(name, arity, function);
+            let hash = self.heap.as_string(name).hash;
+            table.set(name, hash, native);
+        }
+
+        self.stack.truncate(stack_base);
+        return table;
+    }
+
+    pub fn set_debug(&mut self) {
+        self.debug = true
+    }
+
+    pub fn set_ast_pipeline(&mut self) {
+        self.ast_pipeline = true
+    }
+
+    pub fn set_stress_gc(&mut self) {
+        self.stress_gc = true
+    }
+
+    // both pipelines produce the same bytecode and spans, the ast one just gets there in separate passes
+    fn compile(&mut self, source: String) -> Result<ObjRef, LoxError> {
+        let result = if self.ast_pipeline {
+            lower::compile(source, self)
+        } else {
+            compiler::compile(source, self)
+        };
+        return result.map_err(LoxError::CompileError);
+    }
+
+    pub fn interpret(&mut self, source: String) -> InterpretResult {
+        let function = self.compile(source)?;
+
+        self.run_script(function)
+    }
+
+    // compiles without running anything, the bytes are what `Chunk::deserialize` reads back
+    pub fn compile_bytecode(&mut self, source: String) -> Result<Vec<u8>, LoxError> {
+        let function = self.compile(source)?;
+        return Ok(self.heap.as_function(function).chunk.serialize(&self.heap));
+    }
+
+    // runs a chunk loaded from a .loxc file as the top level script
+    pub fn interpret_chunk(&mut self, chunk: Chunk) -> InterpretResult {
+        let function = ObjFunction {
+            chunk,
+            ..ObjFunction::init(None)
+        };
+        let function = self.alloc(ObjectType::Function(function));
+
+        // the loader kept the chunk's constants rooted, the function holds them now
+        self.compiler_roots.clear();
+        self.run_script(function)
+    }
+
+    fn run_script(&mut self, function: ObjRef) -> InterpretResult {
+        // the script gets wrapped like any other function so every frame holds a closure
+        // the function sits on the stack while the closure is allocated so a collection can't take it
+        self.stack.push(Value::from_object(function));
+        let closure = self.alloc(ObjectType::Closure(ObjClosure::init(function)));
+        self.stack.pop();
+        self.stack.push(Value::from_object(closure));
+        self.call(closure, 0)?;
+
+        self.run()
+    }
+
+    // every object goes through here, so this is where the collector gets its chance to run
+    // anything the caller still needs has to be reachable from a root before calling
+    // crate only, strings from outside have to come through alloc_string so they get interned
+    pub(crate) fn alloc(&mut self, object: ObjectType) -> ObjRef {
+        if self.stress_gc || self.heap.should_collect() {
+            self.collect_garbage();
+        }
+
+        return self.heap.alloc(object);
+    }
+
+    // the only way strings should be made, hands back the existing object when the content is already interned
+    pub fn alloc_string(&mut self, content: String) -> ObjRef {
+        let hash = hash_string(&content);
+        if let Some(interned) = self.strings.find_string(&self.heap, &content, hash) {
+            return interned;
+        }
+
+        let string = self.alloc(ObjectType::String(ObjString::allocate(content, hash)));
+        self.strings.set(string, hash, Value::from_nil());
+        return string;
+    }
+
+    pub fn collect_garbage(&mut self) {
+        let before = self.heap.bytes_allocated();
+        if DEBUG_LOG_GC {
+            println!("-- gc begin");
+        }
+
+        self.mark_roots();
+        self.heap.trace_references();
+        // the intern table is weak, forget strings that are about to be freed
+        self.strings.remove_unmarked(&self.heap);
+        self.heap.sweep();
+
+        if DEBUG_LOG_GC {
+            println!("-- gc end");
+            println!(
+                "   collected {} bytes (from {} to {}) next at {}",
+                before - self.heap.bytes_allocated(),
+                before,
+                self.heap.bytes_allocated(),
+                self.heap.next_gc()
+            );
+        }
+    }
+
+    fn mark_roots(&mut self) {
+        for value in &self.stack {
+            self.heap.mark_value(value);
+        }
+
+        for frame in &self.frames {
+            self.heap.mark_object(frame.closure);
+        }
+
+        for upvalue in &self.open_upvalues {
+            self.heap.mark_object(*upvalue);
+        }
+
+        self.heap.mark_table(&self.globals);
+        self.heap.mark_table(&self.list_methods);
+        self.heap.mark_table(&self.map_methods);
+        if let Some(init_string) = self.init_string {
+            self.heap.mark_object(init_string);
+        }
+
+        for object in &self.compiler_roots {
+            self.heap.mark_object(*object);
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        return self.frames.last().unwrap();
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        return self.frames.last_mut().unwrap();
+    }
+
+    fn read_constant(&self, index: usize) -> Value {
+        return self
+            .heap
+            .as_function(self.frame().function)
+            .chunk
+            .constants
+            .get(&index);
+    }
+
+    // names in the constant pool are always interned strings
+    fn read_string(&self, index: usize) -> ObjRef {
+        return self.read_constant(index).as_object();
+    }
+
+    fn string_hash(&self, string: ObjRef) -> u32 {
+        return self.heap.as_string(string).hash;
+    }
+
+    fn call(&mut self, closure: ObjRef, arg_count: usize) -> Result<(), LoxError> {
+        let function = self.heap.as_closure(closure).function;
+        let arity = self.heap.as_function(function).arity;
+        if arg_count != arity {
+            return Err(self.runtime_error(&format!(
+                "Expected {} arguments but got {}.",
+                arity, arg_count
+            )));
+        }
+
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.runtime_error("Stack overflow."));
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            slot_base: self.stack.len() - arg_count - 1,
+        });
+        return Ok(());
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), LoxError> {
+        // the callee sits right below its arguments
+        let callee_slot = self.stack.len() - arg_count - 1;
+
+        if self.heap.is_closure(&callee) {
+            return self.call(callee.as_object(), arg_count);
+        } else if self.heap.is_native(&callee) {
+            return self.call_native(callee.as_object(), arg_count, false);
+        } else if self.heap.is_bound_method(&callee) {
+            let bound = self.heap.as_bound_method(callee.as_object());
+            let method = bound.method;
+            self.stack[callee_slot] = bound.receiver;
+            return self.call(method, arg_count);
+        } else if self.heap.is_class(&callee) {
+            // the class is still in the callee slot, so it survives the allocation
+            let class = callee.as_object();
+            let instance = self.alloc(ObjectType::Instance(ObjInstance::init(class)));
+            self.stack[callee_slot] = Value::from_object(instance);
+
+            let init_string = self.init_string.unwrap();
+            let initializer = self
+                .heap
+                .as_class(class)
+                .methods
+                .get(init_string, self.string_hash(init_string));
+            if let Some(initializer) = initializer {
+                return self.call(initializer.as_object(), arg_count);
+            } else if arg_count != 0 {
+                return Err(
+                    self.runtime_error(&format!("Expected 0 arguments but got {}.", arg_count))
+                );
+            }
+
+            return Ok(());
+        }
+
+        return Err(self.runtime_error("Can only call functions and classes."));
+    }
+
+    // with_receiver hands a method native the value it was called on ahead of the arguments
+    fn call_native(
+        &mut self,
+        native: ObjRef,
+        arg_count: usize,
+        with_receiver: bool,
+    ) -> Result<(), LoxError> {
+        let native = self.heap.as_native(native);
+        let (arity, function) = (native.arity, native.function);
+        if arg_count != arity {
+            return Err(self.runtime_error(&format!(
+                "Expected {} arguments but got {}.",
+                arity, arg_count
+            )));
+        }
+
+        // copied out so the native is free to use the vm, stack included
+        let callee_slot = self.stack.len() - arg_count - 1;
+        let args_start = if with_receiver {
+            callee_slot
+        } else {
+            callee_slot + 1
+        };
+        let args = self.stack[args_start..].to_vec();
+
+        match function(self, &args) {
+            Ok(result) => {
+                // natives don't get a frame, so clean up the callee and arguments here
+                self.stack.truncate(callee_slot);
+                self.stack.push(result);
+                return Ok(());
+            }
+            Err(message) => {
+                return Err(self.runtime_error(&message));
+            }
+        }
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: ObjRef,
+        name: ObjRef,
+        arg_count: usize,
+    ) -> Result<(), LoxError> {
+        let hash = self.string_hash(name);
+        match self.heap.as_class(class).methods.get(name, hash) {
+            Some(method) => return self.call(method.as_object(), arg_count),
+            None => {
+                return Err(self.runtime_error(&format!(
+                    "Undefined property '{}'.",
+                    self.heap.as_string(name)
+                )));
+            }
+        }
+    }
+
+    // `object[index]`, lists take integer positions and maps take any key
+    fn get_index(&self, object: Value, index: Value) -> Result<Value, String> {
+        if self.heap.is_list(&object) {
+            let items = &self.heap.as_list(object.as_object()).items;
+            return Ok(items[list_index(index, items.len(), false)?]);
+        }
+
+        if self.heap.is_map(&object) {
+            let key = map_key(&self.heap, index)?;
+            match self.heap.as_map(object.as_object()).get(key) {
+                Some(value) => return Ok(value),
+                None => {
+                    return Err(format!(
+                        "Undefined key '{}'.",
+                        self.heap.format_value(&index)
+                    ))
+                }
+            }
+        }
+
+        return Err("Only lists and maps can be indexed.".to_string());
+    }
+
+    // lists can only replace existing items, maps add the key if it's missing
+    fn set_index(&mut self, object: Value, index: Value, value: Value) -> Result<(), String> {
+        if self.heap.is_list(&object) {
+            let items = &mut self.heap.as_list_mut(object.as_object()).items;
+            let position = list_index(index, items.len(), false)?;
+            items[position] = value;
+            return Ok(());
+        }
+
+        if self.heap.is_map(&object) {
+            let key = map_key(&self.heap, index)?;
+            self.heap
+                .as_map_mut(object.as_object())
+                .set(key, index, value);
+            self.heap.resize(object.as_object());
+            return Ok(());
+        }
+
+        return Err("Only lists and maps can be indexed.".to_string());
+    }
+
+    fn invoke(&mut self, name: ObjRef, arg_count: usize) -> Result<(), LoxError> {
+        let receiver = match self.peak(arg_count) {
+            Some(val) => *val,
+            None => return Err(self.malformed_bytecode()),
+        };
+
+        let builtin = if self.heap.is_list(&receiver) {
+            Some(("list", &self.list_methods))
+        } else if self.heap.is_map(&receiver) {
+            Some(("map", &self.map_methods))
+        } else {
+            None
+        };
+
+        if let Some((kind, methods)) = builtin {
+            match methods.get(name, self.string_hash(name)) {
+                Some(method) => {
+                    self.call_native(method.as_object(), arg_count, true)?;
+                    // push and insert grow the receiver in place
+                    self.heap.resize(receiver.as_object());
+                    return Ok(());
+                }
+                None => {
+                    return Err(self.runtime_error(&format!(
+                        "Undefined {} method '{}'.",
+                        kind,
+                        self.heap.as_string(name)
+                    )));
+                }
+            }
+        }
+
+        if !self.heap.is_instance(&receiver) {
+            return Err(self.runtime_error("Only instances have methods."));
+        }
+
+        let instance = self.heap.as_instance(receiver.as_object());
+        // a field holding a function shadows a method with the same name
+        if let Some(field) = instance.fields.get(name, self.string_hash(name)) {
+            let callee_slot = self.stack.len() - arg_count - 1;
+            self.stack[callee_slot] = field;
+            return self.call_value(field, arg_count);
+        }
+
+        let class = instance.class;
+        return self.invoke_from_class(class, name, arg_count);
+    }
+
+    // replaces the instance on top of the stack with the named method bound to it
+    fn bind_method(&mut self, class: ObjRef, name: ObjRef) -> Result<(), LoxError> {
+        let hash = self.string_hash(name);
+        let method = match self.heap.as_class(class).methods.get(name, hash) {
+            Some(method) => method.as_object(),
+            None => {
+                return Err(self.runtime_error(&format!(
+                    "Undefined property '{}'.",
+                    self.heap.as_string(name)
+                )));
+            }
+        };
+
+        // the receiver stays on the stack until the bound method exists
+        let receiver = *self.peak(0).unwrap();
+        let bound = self.alloc(ObjectType::BoundMethod(ObjBoundMethod { receiver, method }));
+        self.stack.pop();
+        self.stack.push(Value::from_object(bound));
+        return Ok(());
+    }
+
+    fn capture_upvalue(&mut self, location: usize) -> ObjRef {
+        // closures capturing the same variable have to share the upvalue so they see each other's writes
+        let mut insert_at = self.open_upvalues.len();
+        for (index, upvalue) in self.open_upvalues.iter().enumerate() {
+            let upvalue_location = self.heap.as_upvalue(*upvalue).location;
+            if upvalue_location == location {
+                return *upvalue;
+            }
+
+            if upvalue_location > location {
+                insert_at = index;
+                break;
+            }
+        }
+
+        let upvalue = self.alloc(ObjectType::Upvalue(ObjUpvalue::init(location)));
+        self.open_upvalues.insert(insert_at, upvalue);
+        return upvalue;
+    }
+
+    // moves every captured variable at or above `last` off the stack and into its upvalue
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(upvalue) = self.open_upvalues.last().copied() {
+            let location = self.heap.as_upvalue(upvalue).location;
+            if location < last {
+                break;
+            }
+
+            self.heap.as_upvalue_mut(upvalue).closed = self.stack.get(location).copied();
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn peak(&self, distance: usize) -> Option<&Value> {
+        let index = self.stack.len().checked_sub(1 + distance)?;
+        return self.stack.get(index);
+    }
+
+    // decodes the instruction at the ip, operands included, and steps past all of its bytes
+    fn read_instruction(&mut self) -> OpCode {
+        let frame = self.frames.last_mut().unwrap();
+        let instruction = self
+            .heap
+            .as_function(frame.function)
+            .chunk
+            .read(frame.ip)
+            .unwrap();
+        frame.ip += instruction.size();
+        return instruction;
+    }
+
+    fn runtime_error(&mut self, message: &str) -> LoxError {
+        // innermost call first, like a regular stack trace
+        let trace = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                // the ip has already moved past the instruction that failed
+                let function = self.heap.as_function(frame.function);
+                TraceFrame {
+                    span: function.chunk.get_span(frame.ip - 1),
+                    function: function.name.clone(),
+                }
+            })
+            .collect();
+
+        // reset stack
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+
+        return LoxError::RuntimeError(RuntimeError {
+            message: message.to_string(),
+            trace,
+        });
+    }
+
+    // the compiler never emits code that gets here, only a hand edited .loxc file can
+    fn malformed_bytecode(&mut self) -> LoxError {
+        return self.runtime_error("Malformed bytecode.");
+    }
+
+    // the promotion and overflow rules live in number.rs, folding uses them too
+    fn binary_op(&mut self, operation: Operation) -> Result<(), LoxError> {
+        let (b, a) = match (self.peak(0), self.peak(1)) {
+            (Some(b), Some(a)) => (*b, *a),
+            _ => return Err(self.malformed_bytecode()),
+        };
+
+        let result = match number::binary(operation, a, b) {
+            Ok(result) => result,
+            Err(message) => return Err(self.runtime_error(&message)),
+        };
+
+        self.stack.pop();
+        self.stack.pop();
+        self.stack.push(result);
+        Ok(())
+    }
+
+    fn concatenate(&mut self) -> Result<(), LoxError> {
+        // both operands stay on the stack until the result is allocated
+        let (b, a) = match (self.peak(0), self.peak(1)) {
+            (Some(b), Some(a)) => (b.as_object(), a.as_object()),
+            _ => return Err(self.malformed_bytecode()),
+        };
+
+        let content = self.heap.as_string(a).content.clone() + &self.heap.as_string(b).content;
+        let result = self.alloc_string(content);
+        self.stack.pop();
+        self.stack.pop();
+        self.stack.push(Value::from_object(result));
+        Ok(())
+    }
+
+    fn run(&mut self) -> InterpretResult {
+        loop {
+            if self.debug {
+                for element in &self.stack {
+                    print!("[{}]", self.heap.format_value(element));
+                }
+                println!();
+
+                let frame = self.frame();
+                let chunk = &self.heap.as_function(frame.function).chunk;
+                disassemble_instruction(chunk, frame.ip, &self.heap);
+            }
+
+            let instruction = self.read_instruction();
+
+            let result: Result<(), LoxError> = match instruction {
+                OpCode::OpReturn => {
+                    let result = match self.stack.pop() {
+                        Some(val) => val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.slot_base);
+                    // throw away the callee and everything it left on the stack
+                    self.stack.truncate(frame.slot_base);
+
+                    if self.frames.is_empty() {
+                        return Ok(result);
+                    }
+
+                    self.stack.push(result);
+                    Ok(())
+                }
+                OpCode::OpCall(arg_count) => {
+                    let callee = match self.peak(arg_count) {
+                        Some(val) => *val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    self.call_value(callee, arg_count)?;
+
+                    Ok(())
+                }
+                OpCode::OpPrint => {
+                    let pop_val = match self.stack.pop() {
+                        Some(val) => val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    print_value(&pop_val, &self.heap);
+                    println!();
+                    Ok(())
+                }
+                OpCode::OpPop => {
+                    if self.stack.pop().is_none() {
+                        return Err(self.malformed_bytecode());
+                    }
+
+                    Ok(())
+                }
+                OpCode::OpNegate => {
+                    let value = match self.peak(0) {
+                        Some(val) => *val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    let negated = match number::negate(value) {
+                        Ok(negated) => negated,
+                        Err(message) => return Err(self.runtime_error(&message)),
+                    };
+
+                    self.stack.pop();
+                    self.stack.push(negated);
+                    Ok(())
+                }
+                OpCode::OpDefineGlobal(index) => {
+                    let name = self.read_string(index);
+                    let value = match self.stack.pop() {
+                        Some(val) => val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    self.globals.set(name, self.string_hash(name), value);
+                    Ok(())
+                }
+                OpCode::OpGetGlobal(index) => {
+                    let name = self.read_string(index);
+                    match self.globals.get(name, self.string_hash(name)) {
+                        Some(value) => {
+                            self.stack.push(value);
+                            Ok(())
+                        }
+                        None => Err(self.runtime_error(&format!(
+                            "Undefined variable '{}'.",
+                            self.heap.as_string(name)
+                        ))),
+                    }
+                }
+                OpCode::OpSetGlobal(index) => {
+                    let name = self.read_string(index);
+                    // assignment is an expression, so the value stays on the stack
+                    let value = match self.peak(0) {
+                        Some(val) => *val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    // setting a new key means the variable was never declared, take it back out
+                    let hash = self.string_hash(name);
+                    if self.globals.set(name, hash, value) {
+                        self.globals.delete(name, hash);
+                        return Err(self.runtime_error(&format!(
+                            "Undefined variable '{}'.",
+                            self.heap.as_string(name)
+                        )));
+                    }
+
+                    Ok(())
+                }
+                OpCode::OpClosure(index) => {
+                    let function = self.read_constant(index).as_object();
+                    let mut closure = ObjClosure::init(function);
+
+                    // upvalues are gathered before the closure is allocated, open ones are already rooted
+                    let slot_base = self.frame().slot_base;
+                    let descriptors = self.heap.as_function(function).upvalues.clone();
+                    for upvalue in descriptors {
+                        let captured = if upvalue.is_local {
+                            self.capture_upvalue(slot_base + upvalue.index)
+                        } else {
+                            self.heap.as_closure(self.frame().closure).upvalues[upvalue.index]
+                        };
+                        closure.upvalues.push(captured);
+                    }
+
+                    let closure = self.alloc(ObjectType::Closure(closure));
+                    self.stack.push(Value::from_object(closure));
+                    Ok(())
+                }
+                OpCode::OpGetUpvalue(slot) => {
+                    let upvalue = self.heap.as_closure(self.frame().closure).upvalues[slot];
+                    let upvalue = self.heap.as_upvalue(upvalue);
+                    let value = match upvalue.closed {
+                        Some(value) => value,
+                        // loaded bytecode can pop a captured slot without closing it first
+                        None => match self.stack.get(upvalue.location) {
+                            Some(value) => *value,
+                            None => return Err(self.malformed_bytecode()),
+                        },
+                    };
+
+                    self.stack.push(value);
+                    Ok(())
+                }
+                OpCode::OpSetUpvalue(slot) => {
+                    let value = match self.peak(0) {
+                        Some(val) => *val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    let upvalue = self.heap.as_closure(self.frame().closure).upvalues[slot];
+                    let upvalue = self.heap.as_upvalue_mut(upvalue);
+                    match upvalue.closed {
+                        Some(_) => upvalue.closed = Some(value),
+                        None => match self.stack.get_mut(upvalue.location) {
+                            Some(slot) => *slot = value,
+                            None => return Err(self.malformed_bytecode()),
+                        },
+                    }
+                    Ok(())
+                }
+                OpCode::OpCloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.stack.pop();
+                    Ok(())
+                }
+                OpCode::OpClass(index) => {
+                    let name = self.read_string(index);
+                    let class = self.alloc(ObjectType::Class(ObjClass::init(name)));
+                    self.stack.push(Value::from_object(class));
+                    Ok(())
+                }
+                OpCode::OpGetProperty(index) => {
+                    let receiver = match self.peak(0) {
+                        Some(val) => *val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    if !self.heap.is_instance(&receiver) {
+                        return Err(self.runtime_error("Only instances have properties."));
+                    }
+
+                    let name = self.read_string(index);
+                    let instance = self.heap.as_instance(receiver.as_object());
+                    let field = instance.fields.get(name, self.string_hash(name));
+
+                    // fields win over methods
+                    match field {
+                        Some(value) => {
+                            self.stack.pop();
+                            self.stack.push(value);
+                        }
+                        None => {
+                            let class = instance.class;
+                            self.bind_method(class, name)?;
+                        }
+                    }
+                    Ok(())
+                }
+                OpCode::OpSetProperty(index) => {
+                    let receiver = match self.peak(1) {
+                        Some(val) => *val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    if !self.heap.is_instance(&receiver) {
+                        return Err(self.runtime_error("Only instances have fields."));
+                    }
+
+                    let name = self.read_string(index);
+                    let value = self.stack.pop().unwrap();
+                    let hash = self.string_hash(name);
+                    self.heap
+                        .as_instance_mut(receiver.as_object())
+                        .fields
+                        .set(name, hash, value);
+                    self.heap.resize(receiver.as_object());
+
+                    // swap the instance for the assigned value, assignment is an expression
+                    self.stack.pop();
+                    self.stack.push(value);
+                    Ok(())
+                }
+                OpCode::OpMethod(index) => {
+                    let name = self.read_string(index);
+                    // the compiler always emits these after a closure and a class, loaded bytecode might not
+                    match (self.peak(0), self.peak(1)) {
+                        (Some(method), Some(class))
+                            if self.heap.is_closure(method) && self.heap.is_class(class) => {}
+                        _ => return Err(self.malformed_bytecode()),
+                    }
+
+                    let method = self.stack.pop().unwrap().as_object();
+                    let class = self.peak(0).unwrap().as_object();
+
+                    let hash = self.string_hash(name);
+                    self.heap.as_class_mut(class).methods.set(
+                        name,
+                        hash,
+                        Value::from_object(method),
+                    );
+                    self.heap.resize(class);
+                    Ok(())
+                }
+                OpCode::OpInvoke(index, arg_count) => {
+                    let name = self.read_string(index);
+                    self.invoke(name, arg_count)?;
+
+                    Ok(())
+                }
+                OpCode::OpInherit => {
+                    let superclass = match self.peak(1) {
+                        Some(val) => *val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    if !self.heap.is_class(&superclass) {
+                        return Err(self.runtime_error("Superclass must be a class."));
+                    }
+
+                    if !self
+                        .peak(0)
+                        .is_some_and(|subclass| self.heap.is_class(subclass))
+                    {
+                        return Err(self.malformed_bytecode());
+                    }
+
+                    // methods are copied down once, so lookups never have to walk the class chain
+                    // the subclass's own methods are defined afterwards and override these
+                    let subclass = self.stack.pop().unwrap().as_object();
+                    let methods = self.heap.as_class(superclass.as_object()).methods.clone();
+                    methods.add_all(&mut self.heap.as_class_mut(subclass).methods);
+                    self.heap.resize(subclass);
+                    Ok(())
+                }
+                OpCode::OpGetSuper(index) => {
+                    if !self
+                        .peak(0)
+                        .is_some_and(|superclass| self.heap.is_class(superclass))
+                    {
+                        return Err(self.malformed_bytecode());
+                    }
+
+                    let name = self.read_string(index);
+                    let superclass = self.stack.pop().unwrap().as_object();
+
+                    self.bind_method(superclass, name)?;
+
+                    Ok(())
+                }
+                OpCode::OpSuperInvoke(index, arg_count) => {
+                    if !self
+                        .peak(0)
+                        .is_some_and(|superclass| self.heap.is_class(superclass))
+                    {
+                        return Err(self.malformed_bytecode());
+                    }
+
+                    let name = self.read_string(index);
+                    let superclass = self.stack.pop().unwrap().as_object();
+
+                    self.invoke_from_class(superclass, name, arg_count)?;
+
+                    Ok(())
+                }
+                OpCode::OpGetLocal(slot) => {
+                    let value = self.stack[self.frame().slot_base + slot];
+                    self.stack.push(value);
+                    Ok(())
+                }
+                OpCode::OpSetLocal(slot) => {
+                    let value = match self.peak(0) {
+                        Some(val) => *val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    let slot_base = self.frame().slot_base;
+                    self.stack[slot_base + slot] = value;
+                    Ok(())
+                }
+                OpCode::OpJump(offset) => {
+                    self.frame_mut().ip += offset;
+                    Ok(())
+                }
+                OpCode::OpJumpIfFalse(offset) => {
+                    // the condition is left on the stack, the compiler emits the pop
+                    match self.peak(0) {
+                        Some(condition) if condition.is_falsey() => self.frame_mut().ip += offset,
+                        Some(_) => {}
+                        None => return Err(self.malformed_bytecode()),
+                    }
+                    Ok(())
+                }
+                OpCode::OpLoop(offset) => {
+                    self.frame_mut().ip -= offset;
+                    Ok(())
+                }
+                OpCode::OpConstant(index) | OpCode::OpConstantLong(index) => {
+                    // constants have to stay in the pool, later instructions can refer to the same slot
+                    let constant = self.read_constant(index);
+                    self.stack.push(constant);
+                    Ok(())
+                }
+                // definitely some way to not have all this repeated code, but we're prototyping
+                OpCode::OpGreater => self.binary_op(Operation::Greater),
+                OpCode::OpLess => self.binary_op(Operation::Less),
+                OpCode::OpDivide => self.binary_op(Operation::Div),
+                OpCode::OpFloorDivide => self.binary_op(Operation::FloorDiv),
+                OpCode::OpModulo => self.binary_op(Operation::Modulo),
+                OpCode::OpMultiply => self.binary_op(Operation::Star),
+                OpCode::OpAdd => {
+                    if let (Some(value_0), Some(value_1)) = (self.peak(0), self.peak(1)) {
+                        if self.heap.is_string(value_0) && self.heap.is_string(value_1) {
+                            self.concatenate()
+                        } else if number::is_numeric(value_0) && number::is_numeric(value_1) {
+                            self.binary_op(Operation::Plus)
+                        } else {
+                            Err(self.runtime_error("Operands must be two numbers or two strings."))
+                        }
+                    } else {
+                        Err(self.malformed_bytecode())
+                    }
+                }
+                OpCode::OpSubtract => self.binary_op(Operation::Minus),
+                OpCode::OpNil => {
+                    self.stack.push(Value::from_nil());
+                    Ok(())
+                }
+                OpCode::OpTrue => {
+                    self.stack.push(Value::from_bool(true));
+                    Ok(())
+                }
+                OpCode::OpFalse => {
+                    self.stack.push(Value::from_bool(false));
+                    Ok(())
+                }
+                OpCode::OpNot => {
+                    let pop_val = match self.stack.pop() {
+                        Some(val) => val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    self.stack.push(Value::from_bool(pop_val.is_falsey()));
+                    Ok(())
+                }
+                OpCode::OpBuildList(count) => {
+                    let items_start = match self.stack.len().checked_sub(count) {
+                        Some(start) => start,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    // the items stay on the stack until the list holding them exists
+                    let items = self.stack[items_start..].to_vec();
+                    let list = self.alloc(ObjectType::List(ObjList::init(items)));
+                    self.stack.truncate(items_start);
+                    self.stack.push(Value::from_object(list));
+                    Ok(())
+                }
+                OpCode::OpBuildMap(count) => {
+                    let entries_start = match self.stack.len().checked_sub(count * 2) {
+                        Some(start) => start,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    let mut map = ObjMap::init();
+                    for entry in self.stack[entries_start..].chunks(2) {
+                        match map_key(&self.heap, entry[0]) {
+                            Ok(key) => map.set(key, entry[0], entry[1]),
+                            Err(message) => return Err(self.runtime_error(&message)),
+                        }
+                    }
+
+                    // same as lists, the entries stay on the stack until the map holding them exists
+                    let map = self.alloc(ObjectType::Map(map));
+                    self.stack.truncate(entries_start);
+                    self.stack.push(Value::from_object(map));
+                    Ok(())
+                }
+                OpCode::OpGetIndex => {
+                    let (object, index) = match (self.peak(1), self.peak(0)) {
+                        (Some(object), Some(index)) => (*object, *index),
+                        _ => return Err(self.malformed_bytecode()),
+                    };
+
+                    let item = match self.get_index(object, index) {
+                        Ok(item) => item,
+                        Err(message) => return Err(self.runtime_error(&message)),
+                    };
+
+                    self.stack.pop();
+                    self.stack.pop();
+                    self.stack.push(item);
+                    Ok(())
+                }
+                OpCode::OpSetIndex => {
+                    let (object, index, value) = match (self.peak(2), self.peak(1), self.peak(0)) {
+                        (Some(object), Some(index), Some(value)) => (*object, *index, *value),
+                        _ => return Err(self.malformed_bytecode()),
+                    };
+
+                    if let Err(message) = self.set_index(object, index, value) {
+                        return Err(self.runtime_error(&message));
+                    }
+
+                    // like property assignment, the expression's value is what was assigned
+                    self.stack.truncate(self.stack.len() - 3);
+                    self.stack.push(value);
+                    Ok(())
+                }
+                OpCode::OpStringify => {
+                    let value = match self.peak(0) {
+                        Some(val) => *val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    // strings are already what they'd print as, everything else gets formatted
+                    // the value stays on the stack until the string is allocated
+                    if !self.heap.is_string(&value) {
+                        let content = self.heap.format_value(&value);
+                        let string = self.alloc_string(content);
+                        self.stack.pop();
+                        self.stack.push(Value::from_object(string));
+                    }
+                    Ok(())
+                }
+                OpCode::OpEqual => {
+                    let b = match self.stack.pop() {
+                        Some(val) => val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    let a = match self.stack.pop() {
+                        Some(val) => val,
+                        None => return Err(self.malformed_bytecode()),
+                    };
+
+                    // strings are interned, so comparing handles compares contents too
+                    self.stack.push(Value::from_bool(number::equal(a, b)));
+                    Ok(())
+                }
+            };
+
+            // arithmetic and friends report failures through their result, anything else keeps the loop going
+            result?;
+        }
+    }
+}
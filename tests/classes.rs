@@ -0,0 +1,110 @@
+#![allow(clippy::needless_return)]
+
+use rustlox::error::LoxError;
+
+mod common;
+
+// the first thing that went wrong, whichever stage caught it
+fn error_message(source: &str, ast_pipeline: bool) -> String {
+    match common::vm(ast_pipeline).interpret(source.to_string()) {
+        Ok(_) => panic!("script ran without an error: {source}"),
+        Err(LoxError::CompileError(diagnostics)) => return diagnostics[0].message.clone(),
+        Err(LoxError::RuntimeError(error)) => return error.message,
+    }
+}
+
+#[test]
+fn fields_methods_and_initializers() {
+    let source = r#"
+class Counter {
+  init(start) { this.count = start; }
+  add(n) { this.count = this.count + n; return this; }
+}
+var c = Counter(1);
+record(c.add(2).add(3).count);
+var add = c.add;
+add(10);
+record(c.count);
+c.extra = "field";
+record(c.extra);
+record(c.init(0) == c);
+record(c.count);
+"#;
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(
+            common::run(source, ast_pipeline),
+            ["6", "16", "field", "true", "0"]
+        );
+    }
+}
+
+// methods are copied down at definition, super looks up from the superclass but keeps the receiver
+#[test]
+fn inheritance_and_super() {
+    let source = r#"
+class A {
+  name() { return "A"; }
+  greet() { return "hi " + this.name(); }
+  inherited() { return "from A"; }
+}
+class B < A {
+  name() { return "B"; }
+  greet() { return super.greet() + "!"; }
+}
+class C < B {
+  greet() {
+    var bound = super.greet;
+    return bound();
+  }
+}
+record(B().greet());
+record(C().greet());
+record(C().inherited());
+"#;
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(
+            common::run(source, ast_pipeline),
+            ["hi B!", "hi B!", "from A"]
+        );
+    }
+}
+
+#[test]
+fn class_errors() {
+    let cases = [
+        (
+            "class A { init(a) {} }\nA();",
+            "Expected 1 arguments but got 0.",
+        ),
+        ("class A {}\nA(1);", "Expected 0 arguments but got 1."),
+        ("class A < A {}", "A class can't inherit from itself."),
+        (
+            "var NotClass = 1;\nclass A < NotClass {}",
+            "Superclass must be a class.",
+        ),
+        (
+            "class A { init() { return 1; } }",
+            "Can't return a value from an initializer.",
+        ),
+        ("var x = 1;\nx.y;", "Only instances have properties."),
+        ("class A {}\nA().missing;", "Undefined property 'missing'."),
+        ("this;", "Can't use 'this' outside of a class."),
+        ("super.f();", "Can't use 'super' outside of a class."),
+        (
+            "class A { f() { return super.f(); } }",
+            "Can't use 'super' in a class with no superclass.",
+        ),
+        (
+            "class A {}\nclass B < A { f() { return super.missing(); } }\nB().f();",
+            "Undefined property 'missing'.",
+        ),
+    ];
+
+    for (source, expected) in cases {
+        for ast_pipeline in [false, true] {
+            assert_eq!(error_message(source, ast_pipeline), expected, "{source}");
+        }
+    }
+}
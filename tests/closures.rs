@@ -0,0 +1,100 @@
+#![allow(clippy::needless_return)]
+
+mod common;
+
+// each call to counter gets its own count, which outlives the call that declared it
+#[test]
+fn counters_keep_their_own_state() {
+    let source = r#"
+fun counter() {
+  var count = 0;
+  fun next() {
+    count = count + 1;
+    return count;
+  }
+  return next;
+}
+var a = counter();
+var b = counter();
+record(a());
+record(a());
+record(b());
+"#;
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(common::run(source, ast_pipeline), ["1", "2", "1"]);
+    }
+}
+
+// closures over the same variable share it, before and after it's closed
+#[test]
+fn captured_by_reference() {
+    let source = r#"
+fun pair() {
+  var value = "start";
+  fun get() { return value; }
+  fun set(v) { value = v; }
+  set("open");
+  record(get());
+  return [get, set];
+}
+var both = pair();
+both[1]("closed");
+record(both[0]());
+{
+  var block = "block";
+  fun f() { return block; }
+  block = "reassigned";
+  record(f());
+}
+"#;
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(
+            common::run(source, ast_pipeline),
+            ["open", "closed", "reassigned"]
+        );
+    }
+}
+
+#[test]
+fn captured_through_enclosing_functions() {
+    let source = r#"
+fun outer() {
+  var x = "outer";
+  fun middle() {
+    fun inner() { return x; }
+    return inner;
+  }
+  return middle;
+}
+record(outer()()());
+"#;
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(common::run(source, ast_pipeline), ["outer"]);
+    }
+}
+
+// a local declared in the body is closed when each iteration's scope ends, the loop variable is one slot for the whole loop
+#[test]
+fn closed_at_the_end_of_each_iteration() {
+    let source = r#"
+var body = [];
+var loop = [];
+for (var i = 0; i < 3; i = i + 1) {
+  var j = i;
+  fun showJ() { return j; }
+  fun showI() { return i; }
+  body.push(showJ);
+  loop.push(showI);
+}
+record(body[0]());
+record(body[2]());
+record(loop[0]());
+"#;
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(common::run(source, ast_pipeline), ["0", "2", "3"]);
+    }
+}
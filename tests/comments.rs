@@ -0,0 +1,37 @@
+#![allow(clippy::needless_return)]
+
+mod common;
+
+// `//` is floor division only when it touches the operand before it, with any space in between it's a comment
+const SCRIPTS: [(&str, &[&str]); 7] = [
+    ("var x = 1;\nif (x > 0) // positive\n  record(x);\n", &["1"]),
+    (
+        "var x = 0;\nwhile (x < 3) // count up\n  x = x + 1;\nrecord(x);\n",
+        &["3"],
+    ),
+    (
+        "var a = 1;\nvar b = 2;\nvar c = a\n  // the sum\n  + b;\nrecord(c);\n",
+        &["3"],
+    ),
+    ("record(7//2); // floor division\n", &["3"]),
+    (
+        "var a = 7;\nrecord((a + 2)//2);\nrecord(a//-2);\n",
+        &["4", "-4"],
+    ),
+    ("var x = 7 // 2\n;\nrecord(x);\n", &["7"]),
+    ("record(\"${7//2}\"); // interpolated\n", &["3"]),
+];
+
+#[test]
+fn comments_after_operands() {
+    for (source, expected) in SCRIPTS {
+        assert_eq!(common::run(source, false), expected, "{source}");
+    }
+}
+
+#[test]
+fn comments_after_operands_ast_pipeline() {
+    for (source, expected) in SCRIPTS {
+        assert_eq!(common::run(source, true), expected, "{source}");
+    }
+}
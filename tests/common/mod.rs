@@ -0,0 +1,41 @@
+// shared by the integration tests, not every test file uses every helper
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+
+use rustlox::values::Value;
+use rustlox::vm::VM;
+
+// print goes straight to stdout, so scripts under test hand their results to `record` instead
+thread_local! {
+    static RECORDED: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+fn record_native(vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    let text = vm.heap.format_value(&args[0]);
+    RECORDED.with(|recorded| recorded.borrow_mut().push(text));
+    return Ok(Value::from_nil());
+}
+
+pub fn vm(ast_pipeline: bool) -> VM {
+    let mut vm = VM::init();
+    if ast_pipeline {
+        vm.set_ast_pipeline();
+    }
+    vm.define_native("record", 1, record_native);
+    return vm;
+}
+
+// everything recorded on this thread so far, each test gets a thread of its own
+pub fn recorded() -> Vec<String> {
+    return RECORDED.with(|recorded| recorded.take());
+}
+
+// runs a script through one pipeline and hands back what it recorded
+pub fn run(source: &str, ast_pipeline: bool) -> Vec<String> {
+    let mut vm = vm(ast_pipeline);
+    if let Err(error) = vm.interpret(source.to_string()) {
+        panic!("script failed: {:?}", error);
+    }
+    return recorded();
+}
@@ -0,0 +1,61 @@
+#![allow(clippy::needless_return)]
+
+use rustlox::error::LoxError;
+
+mod common;
+
+#[test]
+fn escapes_decode() {
+    let source = r#"
+record("a\tb");
+record("line\nbreak");
+record("q\"q");
+record("back\\slash");
+record("nul\0end");
+record("\u{48}\u{49}");
+record("\u{1F600}");
+"#;
+    let expected = [
+        "a\tb",
+        "line\nbreak",
+        "q\"q",
+        "back\\slash",
+        "nul\0end",
+        "HI",
+        "\u{1F600}",
+    ];
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(common::run(source, ast_pipeline), expected);
+    }
+}
+
+// a bad escape is underlined on its own, an unterminated string from its opening quote
+#[test]
+fn escape_errors() {
+    let cases = [
+        ("print \"bad \\q\";", "Invalid escape sequence.", 12, 2),
+        ("print \"\\u{110000}\";", "Invalid unicode escape.", 8, 10),
+        ("print \"\\u{D800}\";", "Invalid unicode escape.", 8, 8),
+        ("print \"\\u{}\";", "Invalid unicode escape.", 8, 4),
+        ("print \"\\u48\";", "Invalid unicode escape.", 8, 2),
+        ("print \"\\u{48\";", "Unterminated unicode escape.", 8, 5),
+        ("print \"end\\", "Unterminated string.", 7, 5),
+    ];
+
+    for (source, message, column, length) in cases {
+        for ast_pipeline in [false, true] {
+            match common::vm(ast_pipeline).interpret(source.to_string()) {
+                Err(LoxError::CompileError(diagnostics)) => {
+                    assert_eq!(diagnostics[0].message, message, "{source}");
+                    assert_eq!(
+                        (diagnostics[0].span.column, diagnostics[0].span.length),
+                        (column, length),
+                        "{source}"
+                    );
+                }
+                result => panic!("{source} gave {:?}", result),
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+#![allow(clippy::needless_return)]
+
+use rustlox::chunk::Chunk;
+use rustlox::error::LoxError;
+use rustlox::values::INT_MAX;
+
+mod common;
+
+// each expression on literals next to the same expression on variables, which never folds
+const PAIRS: [(&str, &str); 9] = [
+    ("-(1 + 2) * 3", "-(one + two) * three"),
+    ("7//2 - 7 % 3", "seven//two - seven % three"),
+    ("1 / 2 + 0.25", "one / two + quarter"),
+    ("\"con\" + \"cat\"", "con + \"cat\""),
+    ("1 == 1.0", "one == 1.0"),
+    ("2 >= 3 or 2 <= 3", "two >= three or two <= three"),
+    ("!nil == !false", "!none == !no"),
+    ("\"a\" != \"b\"", "a != \"b\""),
+    ("-(-1.5)", "-(-(quarter * 6))"),
+];
+
+fn variables() -> String {
+    return format!(
+        r#"
+var one = 1;
+var two = 2;
+var three = 3;
+var seven = 7;
+var quarter = 0.25;
+var max = {INT_MAX};
+var con = "con";
+var none = nil;
+var no = false;
+var a = "a";
+"#
+    );
+}
+
+#[test]
+fn folded_matches_computed() {
+    let mut source = variables();
+    for (folded, computed) in PAIRS {
+        source += &format!("record({folded});\nrecord({computed});\n");
+    }
+
+    for ast_pipeline in [false, true] {
+        let recorded = common::run(&source, ast_pipeline);
+        assert_eq!(recorded.len(), PAIRS.len() * 2);
+        for (pair, (folded, _)) in recorded.chunks(2).zip(PAIRS) {
+            assert_eq!(pair[0], pair[1], "{folded}");
+        }
+    }
+}
+
+// only the script's name constant for `record` and the one folded value are left
+#[test]
+fn literals_collapse() {
+    for source in [
+        "record(-(1 + 2) * 3 + 0.5);",
+        "record(\"a\" + \"b\" + \"c\");",
+    ] {
+        for ast_pipeline in [false, true] {
+            let mut vm = common::vm(ast_pipeline);
+            let bytes = vm.compile_bytecode(source.to_string()).unwrap();
+            let chunk = Chunk::deserialize(&bytes, &mut vm).unwrap();
+            assert_eq!(chunk.constants.values.len(), 2, "{source}");
+        }
+    }
+}
+
+// anything that would fail is left in the code, so the error still comes from the vm
+#[test]
+fn errors_wait_for_runtime() {
+    let overflow = format!("{INT_MAX} + 1");
+    let cases = [
+        ("-\"a\"", "-a"),
+        ("1//0", "one//0"),
+        ("1 % 0", "one % 0"),
+        ("\"a\" + 1", "a + one"),
+        ("1 < \"a\"", "one < a"),
+        (&overflow, "max + one"),
+    ];
+
+    let message = |source: String, ast_pipeline: bool| {
+        let mut vm = common::vm(ast_pipeline);
+        assert!(vm.compile_bytecode(source.clone()).is_ok(), "{source}");
+        match vm.interpret(source.clone()) {
+            Err(LoxError::RuntimeError(error)) => return error.message,
+            result => panic!("{source} gave {:?}", result),
+        }
+    };
+
+    for (folded, computed) in cases {
+        for ast_pipeline in [false, true] {
+            assert_eq!(
+                message(format!("print {folded};"), ast_pipeline),
+                message(format!("{}print {computed};", variables()), ast_pipeline),
+                "{folded}"
+            );
+        }
+    }
+}
@@ -0,0 +1,89 @@
+#![allow(clippy::needless_return)]
+
+mod common;
+
+// strings, lists, maps, instances with a cycle between them and a closure over them, all garbage once the iteration ends
+fn churn(iterations: usize) -> String {
+    return format!(
+        r#"
+fun churn() {{
+  for (var i = 0; i < {iterations}; i = i + 1) {{
+    var text = "item ${{i}}";
+    var items = [i, text, {{"key": text}}];
+    class Node {{ init(next) {{ this.next = next; }} }}
+    var a = Node(nil);
+    var b = Node(a);
+    a.next = b;
+    fun keep() {{ return items; }}
+  }}
+}}
+churn();
+"#
+    );
+}
+
+// what's left after a collection can't depend on how much garbage the script made
+#[test]
+fn garbage_is_reclaimed() {
+    let retained = |iterations: usize| {
+        let mut vm = common::vm(false);
+        assert!(vm.interpret(churn(iterations)).is_ok());
+        vm.collect_garbage();
+        return vm.heap.bytes_allocated();
+    };
+
+    assert_eq!(retained(10), retained(2000));
+}
+
+// collecting before every allocation finds anything the vm or compiler forgot to root
+#[test]
+fn stress() {
+    let source = r#"
+fun counter() {
+  var count = 0;
+  fun next() {
+    count = count + 1;
+    return count;
+  }
+  return next;
+}
+var next = counter();
+next();
+
+class Point {
+  init(x, y) {
+    this.x = x;
+    this.y = y;
+  }
+  sum() { return this.x + this.y; }
+}
+class Named < Point {
+  init(name) {
+    super.init(1, 2);
+    this.name = name;
+  }
+  describe() { return "${this.name} at ${super.sum()}"; }
+}
+
+var names = [];
+for (var i = 0; i < 50; i = i + 1) names.push("n" + "${i}");
+var lookup = {};
+for (var i = 0; i < 50; i = i + 1) lookup[names[i]] = Named(names[i]);
+
+record(next());
+record(lookup["n49"].describe());
+record(names.len() + lookup.len());
+"#;
+
+    let expected = ["2", "n49 at 3", "100"];
+    for ast_pipeline in [false, true] {
+        let mut vm = common::vm(ast_pipeline);
+        vm.set_stress_gc();
+        assert!(vm.interpret(source.to_string()).is_ok());
+        assert_eq!(common::recorded(), expected);
+
+        let mut vm = common::vm(ast_pipeline);
+        vm.set_stress_gc();
+        assert!(vm.interpret(churn(20)).is_ok());
+    }
+}
@@ -0,0 +1,26 @@
+#![allow(clippy::needless_return)]
+
+use std::mem::size_of;
+
+use rustlox::values::Value;
+
+mod common;
+
+// objects that grow after they're allocated have to count towards the next collection
+#[test]
+fn growth_is_accounted() {
+    let scripts = [
+        "var items = [];\nfor (var i = 0; i < 10000; i = i + 1) items.push(i);\n",
+        "var entries = {};\nfor (var i = 0; i < 10000; i = i + 1) entries[i] = i;\n",
+    ];
+
+    for source in scripts {
+        let mut vm = common::vm(false);
+        let before = vm.heap.bytes_allocated();
+        assert!(vm.interpret(source.to_string()).is_ok(), "{source}");
+        assert!(
+            vm.heap.bytes_allocated() - before >= 10000 * size_of::<Value>(),
+            "{source}"
+        );
+    }
+}
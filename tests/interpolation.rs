@@ -0,0 +1,70 @@
+#![allow(clippy::needless_return)]
+
+use rustlox::error::LoxError;
+
+mod common;
+
+#[test]
+fn values_are_stringified() {
+    let source = r#"
+var name = "Ann";
+var n = 2;
+record("Hello ${name}, you have ${n + 1} items");
+record("${nil} ${true} ${1.5} ${[1, "a"]} ${{"k": 1}}");
+class P {}
+fun f() {}
+record("${P} ${P()} ${f} ${clock}");
+record("${1} ${2}${3}");
+record("\${not}");
+record("a" + "${1}" == "a1");
+"#;
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(
+            common::run(source, ast_pipeline),
+            [
+                "Hello Ann, you have 3 items",
+                "Nil true 1.5 [1, a] {k: 1}",
+                "P P instance <fn f> <native fn>",
+                "1 23",
+                "${not}",
+                "true",
+            ]
+        );
+    }
+}
+
+// braces and strings inside an interpolation belong to the expression, not the string around it
+#[test]
+fn nesting() {
+    let source = r#"
+record("outer ${"inner ${1 + 1} done"} end");
+record("${ {"a": {"b": 2}}["a"]["b"] }");
+"#;
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(
+            common::run(source, ast_pipeline),
+            ["outer inner 2 done end", "2"]
+        );
+    }
+}
+
+#[test]
+fn interpolation_errors() {
+    let cases = [
+        ("print \"${}\";", "Expect expression"),
+        ("print \"${1 +\";", "Unterminated string."),
+    ];
+
+    for (source, message) in cases {
+        for ast_pipeline in [false, true] {
+            match common::vm(ast_pipeline).interpret(source.to_string()) {
+                Err(LoxError::CompileError(diagnostics)) => {
+                    assert_eq!(diagnostics[0].message, message, "{source}")
+                }
+                result => panic!("{source} gave {:?}", result),
+            }
+        }
+    }
+}
@@ -0,0 +1,17 @@
+#![allow(clippy::needless_return)]
+
+mod common;
+
+// config scripts write out long literals, they aren't capped at a byte's worth of items
+#[test]
+fn long_list_literals() {
+    let items: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+    let source = format!(
+        "var items = [{}];\nrecord(items.len());\nrecord(items[999]);\n",
+        items.join(", ")
+    );
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(common::run(&source, ast_pipeline), ["1000", "999"]);
+    }
+}
@@ -0,0 +1,46 @@
+#![allow(clippy::needless_return)]
+
+mod common;
+
+// a `{` starting a statement opens a map when a `:` follows its first key, otherwise it's a block
+#[test]
+fn map_literals_starting_statements() {
+    let source = r#"
+{"f": record}["f"]("map");
+{"a": {"b": 1}}["a"]["b"] = record("nested");
+{ var a = "block"; record(a); }
+{ {"k": "inner"}["k"]; record("after inner"); }
+{ record({"k": "arg"}["k"]); }
+{}
+if (true) { record("if block"); }
+if (true) {"g": record}["g"]("if map");
+{ record("${ {"x": 1}["x"] }"); }
+"#;
+    let expected = [
+        "map",
+        "nested",
+        "block",
+        "after inner",
+        "arg",
+        "if block",
+        "if map",
+        "1",
+    ];
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(common::run(source, ast_pipeline), expected);
+    }
+}
+
+#[test]
+fn long_map_literals() {
+    let entries: Vec<String> = (0..1000).map(|i| format!("\"k{i}\": {i}")).collect();
+    let source = format!(
+        "var entries = {{{}}};\nrecord(entries.len());\nrecord(entries[\"k999\"]);\n",
+        entries.join(", ")
+    );
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(common::run(&source, ast_pipeline), ["1000", "999"]);
+    }
+}
@@ -0,0 +1,19 @@
+#![allow(clippy::needless_return)]
+
+mod common;
+
+// a literal too wide for an int still compiles, as a float, whichever build is running
+#[test]
+fn wide_int_literals_are_floats() {
+    let source = "record(99999999999999999999);\nrecord(1000000000000000 == 1000000000000000.0);\nrecord(1000000000000000 + 1);\n";
+    for ast_pipeline in [false, true] {
+        let recorded = common::run(source, ast_pipeline);
+        assert_eq!(recorded[..2], ["100000000000000000000.0", "true"]);
+        // an int in the plain build, a float once nan-boxing narrows the range
+        assert!(
+            recorded[2].starts_with("1000000000000001"),
+            "{:?}",
+            recorded
+        );
+    }
+}
@@ -0,0 +1,83 @@
+#![allow(clippy::needless_return)]
+
+use rustlox::ast::dump;
+use rustlox::parser::parse;
+use rustlox::resolver::resolve;
+
+mod common;
+
+// the ast pipeline checks most of these in the resolver, after parsing has already finished
+#[test]
+fn misplaced_super() {
+    let sources = [
+        "super;",
+        "super.f();",
+        "class A { f() { super; } }",
+        "class A { f() { super.f(); } }",
+        "class B {} class A < B { f() { super; } }",
+        "class B {} class A < B { f() { class C { g() { super; } } } }",
+    ];
+    for source in sources {
+        let single_pass = common::vm(false).interpret(source.to_string());
+        let ast = common::vm(true).interpret(source.to_string());
+        assert!(single_pass.is_err(), "{source}");
+        assert_eq!(single_pass, ast, "{source}");
+    }
+}
+
+// every diagnostic, its span and the runtime traces have to match, not just the first message
+#[test]
+fn same_errors() {
+    let sources = [
+        "var a = 1;\n{\n  var a = a;\n}",
+        "{\n  var a;\n  var a;\n}",
+        "return 1;",
+        "print ;\nvar x = ;\nprint x;",
+        "var a;\nvar b;\na + b = 1;",
+        "class A < A {}",
+        "fun f() {\n  this.x = 1;\n}",
+        "print \"unterminated;",
+        "var s = \"bad \\q escape\";",
+        "print \"${1 +}\";",
+        "fun f(a, a) {}",
+        "fun f() {\n  return nil.x;\n}\nfun g() {\n  return f();\n}\ng();",
+        "var list = [1, 2];\nlist[2];",
+        "var map = {\"a\": 1};\nmap[[]] = 2;",
+        "undefined = 1;",
+    ];
+    for source in sources {
+        let single_pass = common::vm(false).interpret(source.to_string());
+        let ast = common::vm(true).interpret(source.to_string());
+        assert!(single_pass.is_err(), "{source}");
+        assert_eq!(single_pass, ast, "{source}");
+    }
+}
+
+// the dump shows where the resolver decided each variable lives
+#[test]
+fn dump_shows_resolution() {
+    let source =
+        "fun f(a) {\n  var b = a;\n  fun g() { return b; }\n  return g;\n}\nprint f(1)();\n";
+    let mut program = parse(source.to_string()).unwrap();
+    resolve(&mut program).unwrap();
+
+    let expected = "\
+Fun f (global)
+  Function f(a (local 1))
+    Var b (local 2)
+      Variable a (local 1)
+    Fun g (local 3)
+      Function g()
+        Captures local 2
+        Return
+          Variable b (upvalue 0)
+    Return
+      Variable g (local 3)
+Print
+  Call
+    Call
+      Variable f (global)
+      Literal 1
+";
+    assert_eq!(dump(&program), expected);
+}
@@ -0,0 +1,152 @@
+#![allow(clippy::needless_return)]
+
+use rustlox::chunk::Chunk;
+use rustlox::serialize::FORMAT_VERSION;
+
+mod common;
+
+const SCRIPT: &str = r#"
+fun counter() {
+  var total = 0;
+  fun add(x) {
+    total = total + x;
+    return total;
+  }
+  return add;
+}
+var add = counter();
+add(2);
+record(add(3));
+
+class Point {
+  init(x, y) {
+    this.x = x;
+    this.y = y;
+  }
+  sum() { return this.x + this.y; }
+}
+class Point3 < Point {
+  sum() { return super.sum() + 1; }
+}
+record(Point3(1, 2.5).sum());
+
+record("con" + "cat");
+record([1, 2, 3][1]);
+record({"half": 7//2}["half"]);
+record("${-7 % 3} and ${1.5}");
+"#;
+
+fn compile(source: &str) -> Vec<u8> {
+    let mut vm = common::vm(false);
+    match vm.compile_bytecode(source.to_string()) {
+        Ok(bytes) => return bytes,
+        Err(error) => panic!("script failed to compile: {:?}", error),
+    }
+}
+
+#[test]
+fn round_trip() {
+    let expected = common::run(SCRIPT, false);
+    assert_eq!(expected, ["5", "4.5", "concat", "2", "3", "2 and 1.5"]);
+
+    // loaded into a vm that never saw the source
+    let bytes = compile(SCRIPT);
+    let mut vm = common::vm(false);
+    let chunk = Chunk::deserialize(&bytes, &mut vm).unwrap();
+    assert!(vm.interpret_chunk(chunk).is_ok());
+    assert_eq!(common::recorded(), expected);
+}
+
+// control flow is where the pipelines are most likely to drift apart, in jumps or in the spans they record
+const CONTROL_FLOW: [&str; 7] = [
+    "if (1 < 2) print \"lt\"; else print \"ge\";",
+    "var i = 0;\nwhile (i < 3) {\n  i = i + 1;\n}",
+    "for (var i = 0; i < 3; i = i + 1) print i;",
+    "fun g() {\n  for (var n = 0;; n = n + 1) {\n    if (n > 2) return n;\n  }\n}",
+    "var a = nil;\nprint a and a.b;",
+    "var a = 1;\nprint a or 2;\nprint !a == false or a > 0 and a < 2;",
+    "fun f(x) {\n  if (x) return 1;\n  while (x and x > 0) x = x - 1;\n  return x or 0;\n}",
+];
+
+// a folded value sits on the last token of its operands, even when that's a closing paren
+const FOLDED: [&str; 3] = [
+    "print -(5);",
+    "print (1) + (2);",
+    "print !(1 < (2 * 3)) == false;",
+];
+
+#[test]
+fn both_pipelines_write_the_same_file() {
+    for source in [SCRIPT]
+        .iter()
+        .chain(CONTROL_FLOW.iter())
+        .chain(FOLDED.iter())
+    {
+        let mut vm = common::vm(true);
+        assert_eq!(
+            vm.compile_bytecode(source.to_string()).unwrap(),
+            compile(source),
+            "{source}"
+        );
+    }
+}
+
+#[test]
+fn truncated_file() {
+    let bytes = compile(SCRIPT);
+
+    // every cut short of the whole file has to be turned away, not read past the end
+    for length in 0..bytes.len() {
+        let mut vm = common::vm(false);
+        assert!(
+            Chunk::deserialize(&bytes[..length], &mut vm).is_err(),
+            "{length}"
+        );
+    }
+
+    let mut vm = common::vm(false);
+    assert_eq!(
+        Chunk::deserialize(&bytes[..bytes.len() - 1], &mut vm).err(),
+        Some("Unexpected end of bytecode file.".to_string())
+    );
+}
+
+#[test]
+fn version_mismatch() {
+    let mut bytes = compile(SCRIPT);
+    // the version follows the four magic bytes
+    bytes[4..6].copy_from_slice(&(FORMAT_VERSION - 1).to_le_bytes());
+
+    let mut vm = common::vm(false);
+    assert_eq!(
+        Chunk::deserialize(&bytes, &mut vm).err(),
+        Some(format!(
+            "Bytecode format version {} is not supported, expected {}.",
+            FORMAT_VERSION - 1,
+            FORMAT_VERSION
+        ))
+    );
+}
+
+#[test]
+fn nan_payloads() {
+    let bytes = compile("record(1.5);");
+    let constant = 1.5f64.to_le_bytes();
+    let position = bytes
+        .windows(constant.len())
+        .position(|window| window == constant)
+        .unwrap();
+
+    // nans whose payloads look like an object handle and like nil
+    for bits in [0xFFFC000000001234u64, 0x7FFC000000000001] {
+        let mut bytes = bytes.clone();
+        bytes[position..position + constant.len()].copy_from_slice(&bits.to_le_bytes());
+
+        let mut vm = common::vm(false);
+        let chunk = Chunk::deserialize(&bytes, &mut vm).unwrap();
+        // the loaded constants get traced, so a nan mistaken for an object would be followed here
+        vm.collect_garbage();
+        assert!(vm.interpret_chunk(chunk).is_ok());
+        assert_eq!(common::recorded(), ["NaN"]);
+    }
+}
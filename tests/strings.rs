@@ -0,0 +1,62 @@
+#![allow(clippy::needless_return)]
+
+use rustlox::values::Value;
+use rustlox::vm::VM;
+
+mod common;
+
+// the string's heap handle, so scripts can check two strings are the same object and not just equal
+fn handle_native(_vm: &mut VM, args: &[Value]) -> Result<Value, String> {
+    return Ok(Value::from_number(args[0].as_object().0 as f64));
+}
+
+// an embedder's native making a string the script already has
+fn ab_native(vm: &mut VM, _args: &[Value]) -> Result<Value, String> {
+    return Ok(Value::from_object(vm.alloc_string("ab".to_string())));
+}
+
+#[test]
+fn alloc_string_interns() {
+    let mut vm = common::vm(false);
+    let first = vm.alloc_string("interned".to_string());
+    assert_eq!(vm.alloc_string("interned".to_string()), first);
+    assert_ne!(vm.alloc_string("other".to_string()), first);
+}
+
+// constants, concatenation, interpolation and natives all hand back the one copy of each string
+#[test]
+fn every_string_is_interned() {
+    let source = r#"
+var a = "a";
+var joined = a + "b";
+record(handle(joined) == handle("ab"));
+record(handle("${a}b") == handle("ab"));
+record(handle(ab()) == handle("ab"));
+record(handle(a + "c") == handle("ab"));
+"#;
+
+    for ast_pipeline in [false, true] {
+        let mut vm = common::vm(ast_pipeline);
+        vm.define_native("handle", 1, handle_native);
+        vm.define_native("ab", 0, ab_native);
+        assert!(vm.interpret(source.to_string()).is_ok());
+        assert_eq!(common::recorded(), ["true", "true", "true", "false"]);
+    }
+}
+
+// maps compare string keys by handle, so a key built at runtime has to find the entry made from a constant
+#[test]
+fn built_keys_find_constant_entries() {
+    let source = r#"
+var entries = {"ab": 1};
+var b = "b";
+record(entries["a" + b]);
+entries["a${b}"] = 2;
+record(entries["ab"]);
+record(entries.len());
+"#;
+
+    for ast_pipeline in [false, true] {
+        assert_eq!(common::run(source, ast_pipeline), ["1", "2", "1"]);
+    }
+}